@@ -0,0 +1,122 @@
+//! ANSI escape sequences for the serial console, written to any
+//! `core::fmt::Write`.
+//!
+//! Only the sequences every terminal emulator understands are covered:
+//! erasing, cursor movement and the eight basic colors. Rows and columns
+//! count from 1, as in the sequences themselves.
+
+use core::fmt;
+use core::fmt::Write;
+
+/// The eight basic colors, and the terminal's own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Black = 0,
+    Red = 1,
+    Green = 2,
+    Yellow = 3,
+    Blue = 4,
+    Magenta = 5,
+    Cyan = 6,
+    White = 7,
+    Default = 9,
+}
+
+/// Clears the screen and moves the cursor to the top left.
+pub fn clear_screen(w: &mut impl Write) -> fmt::Result {
+    w.write_str("\x1B[2J\x1B[H")
+}
+
+/// Clears from the cursor to the end of the line.
+pub fn clear_line(w: &mut impl Write) -> fmt::Result {
+    w.write_str("\x1B[K")
+}
+
+pub fn move_cursor(w: &mut impl Write, row: u16, col: u16) -> fmt::Result {
+    write!(w, "\x1B[{};{}H", row, col)
+}
+
+/// Moves the cursor `count` columns left, stopping at the first one.
+pub fn cursor_back(w: &mut impl Write, count: u16) -> fmt::Result {
+    write!(w, "\x1B[{}D", count)
+}
+
+/// Sets the foreground and background color of the text that follows.
+pub fn set_color(w: &mut impl Write, fg: Color, bg: Color) -> fmt::Result {
+    write!(w, "\x1B[{};{}m", 30 + fg as u8, 40 + bg as u8)
+}
+
+/// Resets colors and other attributes to the terminal's defaults.
+pub fn reset(w: &mut impl Write) -> fmt::Result {
+    w.write_str("\x1B[0m")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fixed-size buffer that rejects writes it has no room for.
+    struct Buffer {
+        bytes: [u8; 16],
+        len: usize,
+    }
+
+    impl Buffer {
+        fn new() -> Buffer {
+            Buffer {
+                bytes: [0; 16],
+                len: 0,
+            }
+        }
+
+        fn as_bytes(&self) -> &[u8] {
+            &self.bytes[..self.len]
+        }
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn written(f: impl FnOnce(&mut Buffer) -> fmt::Result) -> Vec<u8> {
+        let mut buffer = Buffer::new();
+        f(&mut buffer).unwrap();
+        buffer.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn sequences_match_the_ecma_48_bytes() {
+        assert_eq!(written(clear_screen), b"\x1B[2J\x1B[H");
+        assert_eq!(written(clear_line), b"\x1B[K");
+        assert_eq!(written(|w| move_cursor(w, 12, 80)), b"\x1B[12;80H");
+        assert_eq!(written(|w| cursor_back(w, 3)), b"\x1B[3D");
+        assert_eq!(written(reset), b"\x1B[0m");
+    }
+
+    #[test]
+    fn colors_use_the_30_and_40_ranges() {
+        assert_eq!(
+            written(|w| set_color(w, Color::Red, Color::Default)),
+            b"\x1B[31;49m"
+        );
+        assert_eq!(
+            written(|w| set_color(w, Color::White, Color::Black)),
+            b"\x1B[37;40m"
+        );
+    }
+
+    #[test]
+    fn full_writer_error_is_returned() {
+        let mut buffer = Buffer::new();
+        buffer.write_str("0123456789ABCDE").unwrap();
+        assert_eq!(clear_line(&mut buffer), Err(fmt::Error));
+    }
+}
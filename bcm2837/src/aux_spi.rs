@@ -0,0 +1,212 @@
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::shared;
+use crate::soc::Soc;
+use crate::uart::Mux;
+
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CNTL0 [
+        ShiftLength OFFSET(0) NUMBITS(6) [],
+        ShiftOutMSBFirst OFFSET(6) NUMBITS(1) [],
+        InvertClock OFFSET(7) NUMBITS(1) [],
+        OutRising OFFSET(8) NUMBITS(1) [],
+        ClearFIFOs OFFSET(9) NUMBITS(1) [],
+        InRising OFFSET(10) NUMBITS(1) [],
+        Enable OFFSET(11) NUMBITS(1) [],
+        DoutHoldTime OFFSET(12) NUMBITS(2) [],
+        VariableWidth OFFSET(14) NUMBITS(1) [],
+        VariableCS OFFSET(15) NUMBITS(1) [],
+        PostInputMode OFFSET(16) NUMBITS(1) [],
+        ChipSelects OFFSET(17) NUMBITS(3) [],
+        Speed OFFSET(20) NUMBITS(12) [],
+    ],
+    CNTL1 [
+        KeepInput OFFSET(0) NUMBITS(1) [],
+        ShiftInMSBFirst OFFSET(1) NUMBITS(1) [],
+        DoneIRQ OFFSET(6) NUMBITS(1) [],
+        TxEmptyIRQ OFFSET(7) NUMBITS(1) [],
+        CSHighTime OFFSET(8) NUMBITS(3) [],
+    ],
+    STAT [
+        BitCount OFFSET(0) NUMBITS(6) [],
+        Busy OFFSET(6) NUMBITS(1) [],
+        RxEmpty OFFSET(7) NUMBITS(1) [],
+        RxFull OFFSET(8) NUMBITS(1) [],
+        TxEmpty OFFSET(9) NUMBITS(1) [],
+        TxFull OFFSET(10) NUMBITS(1) [],
+        RxLevel OFFSET(16) NUMBITS(8) [],
+        TxLevel OFFSET(24) NUMBITS(8) [],
+    ],
+    IO [
+        Data OFFSET(0) NUMBITS(24) [],
+        Width OFFSET(24) NUMBITS(5) [],
+    ],
+];
+
+// SPI1 shares the AUX block with the Mini UART. The register offsets follow
+// the errata to the BCM2835 datasheet, which moved IO and PEEK.
+#[repr(C)]
+struct Registers {
+    _irq: ReadWrite<u32, Mux::Register>,
+
+    /// 0x04: AUX enables, shared with the Mini UART and SPI2
+    enables: ReadWrite<u32, Mux::Register>,
+    _reserved0: [u8; 0x80 - 0x08],
+
+    /// 0x80: control 0 (CNTL0)
+    cntl0: ReadWrite<u32, CNTL0::Register>,
+
+    /// 0x84: control 1 (CNTL1)
+    cntl1: ReadWrite<u32, CNTL1::Register>,
+
+    /// 0x88: status (STAT)
+    stat: ReadOnly<u32, STAT::Register>,
+
+    /// 0x8C: peek at the receive FIFO without popping it (PEEK)
+    peek: ReadOnly<u32, IO::Register>,
+    _reserved1: [u8; 0xA0 - 0x90],
+
+    /// 0xA0-0xAC: FIFO data, ending the transfer after this word (IO)
+    io: [ReadWrite<u32, IO::Register>; 4],
+
+    /// 0xB0-0xBC: FIFO data, keeping CS asserted after this word (TXHOLD)
+    txhold: [ReadWrite<u32, IO::Register>; 4],
+}
+
+/// SPI1 signals, all alt4: CE2, CE1, CE0, MISO, MOSI and SCLK.
+const PINS: [usize; 6] = [16, 17, 18, 19, 20, 21];
+
+/// Widest word the AUX SPI can shift in one FIFO entry.
+pub const MAX_WORD_BITS: u8 = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChipSelect {
+    Cs0 = 0,
+    Cs1 = 1,
+    Cs2 = 2,
+}
+
+/// Polled master driver for the AUX SPI1 peripheral.
+pub struct AuxSpi {
+    registers: StaticRef<Registers>,
+    msb_first: bool,
+    soc: Soc,
+}
+
+impl AuxSpi {
+    pub unsafe fn spi1() -> AuxSpi {
+        AuxSpi::spi1_for_soc(&Soc::detect())
+    }
+
+    /// Like `spi1`, for the peripheral window of `soc`.
+    pub unsafe fn spi1_for_soc(soc: &Soc) -> AuxSpi {
+        AuxSpi {
+            registers: StaticRef::new((soc.peripheral_base + 0x215000) as *const Registers),
+            msb_first: true,
+            soc: *soc,
+        }
+    }
+
+    /// Routes SPI1 to GPIO16-21, enables it in the AUX block and leaves it
+    /// in mode 0, MSB first, at `speed`.
+    pub fn init(&mut self, speed: u16) {
+        // Safety: GPIO16-21 belong to SPI1.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in PINS {
+            gpio.set_function(pin, Function::Alt4);
+        }
+
+        shared::modify(&self.registers.enables, Mux::SPI1::SET);
+        self.registers.cntl1.set(0);
+        self.registers.cntl0.write(CNTL0::ClearFIFOs::SET);
+        self.registers.cntl0.write(
+            CNTL0::Enable::SET
+                + CNTL0::VariableWidth::SET
+                + CNTL0::OutRising::CLEAR
+                + CNTL0::InRising::SET
+                + CNTL0::ChipSelects.val(0b111)
+                + CNTL0::Speed.val(speed as u32 & 0xFFF),
+        );
+        self.set_msb_first(true);
+    }
+
+    /// Sets the SPI clock to `core clock / (2 * (speed + 1))`. `speed` is 12
+    /// bits wide.
+    pub fn set_speed(&mut self, speed: u16) {
+        self.registers
+            .cntl0
+            .modify(CNTL0::Speed.val(speed as u32 & 0xFFF));
+    }
+
+    /// Selects whether words are shifted out and in MS bit first.
+    pub fn set_msb_first(&mut self, msb_first: bool) {
+        self.msb_first = msb_first;
+        self.registers
+            .cntl0
+            .modify(CNTL0::ShiftOutMSBFirst.val(msb_first as u32));
+        self.registers
+            .cntl1
+            .modify(CNTL1::ShiftInMSBFirst.val(msb_first as u32));
+    }
+
+    pub fn select(&mut self, cs: ChipSelect) {
+        // The field holds the level of each CS line while active.
+        let pattern = 0b111 & !(1 << cs as u32);
+        self.registers.cntl0.modify(CNTL0::ChipSelects.val(pattern));
+    }
+
+    /// Shifts out the low `bits` bits of `word` and returns the `bits` bits
+    /// shifted in. `bits` must be between 1 and `MAX_WORD_BITS`.
+    pub fn transfer_word(&mut self, word: u32, bits: u8) -> Result<u32, ErrorCode> {
+        self.shift(word, bits, false)
+    }
+
+    /// Clocks out `buffer` a byte at a time and replaces its contents with
+    /// the bytes clocked in. CS stays asserted for the whole buffer.
+    pub fn transfer(&mut self, buffer: &mut [u8]) {
+        let last = buffer.len().saturating_sub(1);
+        for (index, byte) in buffer.iter_mut().enumerate() {
+            if let Ok(received) = self.shift(*byte as u32, 8, index != last) {
+                *byte = received as u8;
+            }
+        }
+    }
+
+    fn shift(&mut self, word: u32, bits: u8, hold_cs: bool) -> Result<u32, ErrorCode> {
+        if bits == 0 || bits > MAX_WORD_BITS {
+            return Err(ErrorCode::INVAL);
+        }
+        let mask = (1 << bits) - 1;
+        // MS-bit-first data is shifted out from bit 23, so it has to sit at
+        // the top of the data field.
+        let data = if self.msb_first {
+            (word & mask) << (MAX_WORD_BITS - bits)
+        } else {
+            word & mask
+        };
+        let fifo = if hold_cs {
+            &self.registers.txhold[0]
+        } else {
+            &self.registers.io[0]
+        };
+
+        while self.registers.stat.is_set(STAT::TxFull) {}
+        fifo.write(IO::Data.val(data) + IO::Width.val(bits as u32));
+        while self.registers.stat.is_set(STAT::RxEmpty) {}
+        Ok(self.registers.io[0].read(IO::Data) & mask)
+    }
+
+    /// Next word in the receive FIFO, without removing it.
+    pub fn peek(&self) -> u32 {
+        self.registers.peek.read(IO::Data)
+    }
+}
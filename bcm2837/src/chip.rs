@@ -0,0 +1,190 @@
+use core::arch::asm;
+use core::fmt::Write;
+
+use cortex_a::exceptions;
+use cortex_a::mpu::Mpu;
+use cortex_a::syscall::SysCall;
+
+use crate::dma;
+use crate::dma::Dma;
+use crate::gpio;
+use crate::gpio::GpioPort;
+use crate::interrupt;
+use crate::interrupt::Interrupt;
+use crate::interrupt::NUM_IRQS;
+use crate::rng::Rng;
+use crate::system_timer;
+use crate::system_timer::SystemTimer;
+use crate::uart;
+use crate::uart::MiniUart;
+use crate::uart::UART;
+
+use kernel::deferred_call::DeferredCall;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::platform::chip::Chip;
+use kernel::platform::chip::InterruptService;
+
+/// Peripherals with interrupt-driven Tock drivers.
+pub struct Bcm2837DefaultPeripherals<'a> {
+    pub system_timer: SystemTimer<'a>,
+    pub mini_uart: MiniUart<'a>,
+    pub dma: Dma<'a>,
+    pub rng: Rng<'a>,
+    pub gpio: GpioPort<'a>,
+}
+
+/// The interrupt service routine of one driver.
+type Handler = fn(&Bcm2837DefaultPeripherals<'_>);
+
+/// The driver serving each interrupt line, indexed by line number. `init`
+/// enables every line with a handler, and `service_interrupt` dispatches
+/// through it, so a new driver only needs an entry in `handlers`.
+///
+/// | Line  | Source                                 | Driver         |
+/// |-------|----------------------------------------|----------------|
+/// | 1, 3  | System Timer compares 1 and 3          | `system_timer` |
+/// | 16-26 | DMA channels 0-10                      | `dma`          |
+/// | 27    | DMA channels 11-14                     | `dma`          |
+/// | 29    | AUX: Mini UART, SPI1 and SPI2          | `mini_uart`    |
+/// | 52    | GPIO, any bank                         | `gpio`         |
+/// | 53    | I2C, all BSC controllers (`I2C_IRQ`)   | polled, none   |
+/// | 54    | SPI0 (`SPI_IRQ`)                       | polled, none   |
+///
+/// System Timer compares 0 and 2 belong to the VideoCore.
+static HANDLERS: [Option<Handler>; NUM_IRQS as usize] = handlers();
+
+const fn handlers() -> [Option<Handler>; NUM_IRQS as usize] {
+    let mut table: [Option<Handler>; NUM_IRQS as usize] = [None; NUM_IRQS as usize];
+    table[system_timer::C1_IRQ as usize] = Some(|p| p.system_timer.handle_interrupt());
+    table[system_timer::C3_IRQ as usize] = Some(|p| p.system_timer.handle_interrupt());
+    table[uart::AUX_IRQ as usize] = Some(|p| p.mini_uart.handle_interrupt());
+    table[gpio::GPIO_IRQ as usize] = Some(|p| p.gpio.handle_interrupt());
+    let mut channel = 0;
+    while channel < dma::NUM_CHANNELS {
+        table[dma::irq(channel) as usize] = Some(|p| p.dma.handle_interrupt());
+        channel += 1;
+    }
+    table
+}
+
+impl<'a> Bcm2837DefaultPeripherals<'a> {
+    /// # Safety
+    ///
+    /// Must be called at most once, as the peripherals own their registers.
+    pub unsafe fn new() -> Bcm2837DefaultPeripherals<'a> {
+        Bcm2837DefaultPeripherals {
+            system_timer: SystemTimer::new(),
+            mini_uart: MiniUart::new(UART::uart1().expect("Mini UART already taken")),
+            dma: Dma::new(),
+            rng: Rng::new(),
+            gpio: GpioPort::new(),
+        }
+    }
+
+    /// Registers deferred calls, sets up the Mini UART and unmasks the
+    /// interrupt lines of the drivers.
+    pub fn init(&'static self) {
+        self.rng.register();
+        self.mini_uart.init();
+
+        // Safety: only enables lines, which `handle_irq` masks again as they
+        // are taken.
+        let interrupt = unsafe { Interrupt::new() };
+        for (irq, handler) in HANDLERS.iter().enumerate() {
+            if handler.is_some() {
+                interrupt.enable(irq as u32);
+            }
+        }
+    }
+}
+
+impl InterruptService for Bcm2837DefaultPeripherals<'_> {
+    unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
+        match HANDLERS.get(interrupt as usize) {
+            Some(Some(handler)) => {
+                handler(self);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct Bcm2837<'a, I: InterruptService + 'a> {
+    mpu: Mpu,
+    userspace_kernel_boundary: SysCall,
+    interrupt: Interrupt,
+    interrupt_service: &'a I,
+}
+
+impl<'a, I: InterruptService + 'a> Bcm2837<'a, I> {
+    pub unsafe fn new(interrupt_service: &'a I) -> Bcm2837<'a, I> {
+        Bcm2837 {
+            mpu: Mpu::new(),
+            userspace_kernel_boundary: SysCall::new(),
+            interrupt: Interrupt::new(),
+            interrupt_service,
+        }
+    }
+}
+
+impl<'a, I: InterruptService + 'a> Chip for Bcm2837<'a, I> {
+    type MPU = Mpu;
+    type UserspaceKernelBoundary = SysCall;
+
+    /// Runs the driver of every line `handle_irq` took, then unmasks it, and
+    /// runs pending deferred calls once no interrupt is left.
+    fn service_pending_interrupts(&self) {
+        interrupt::service_pending(
+            || self.interrupt.next_taken(),
+            |irq| {
+                if !unsafe { self.interrupt_service.service_interrupt(irq) } {
+                    panic!("unhandled interrupt {}", irq);
+                }
+                unsafe { self.atomic(|| self.interrupt.complete(irq)) };
+            },
+        );
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.interrupt.next_taken().is_some() || DeferredCall::has_tasks()
+    }
+
+    fn mpu(&self) -> &Self::MPU {
+        &self.mpu
+    }
+
+    fn userspace_kernel_boundary(&self) -> &SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    /// Waits for an interrupt. This also wakes up with IRQs masked, as they
+    /// are while the kernel decides to sleep.
+    fn sleep(&self) {
+        unsafe {
+            asm!("wfi", options(nomem, nostack));
+        }
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        exceptions::disable_interrupts();
+        let result = f();
+        exceptions::enable_interrupts();
+        result
+    }
+
+    unsafe fn print_state(&self, writer: &mut dyn Write) {
+        let _ = writeln!(writer, "\r\n---| BCM2837 |---\r");
+        match self.interrupt.next_taken() {
+            Some(irq) => {
+                let _ = writeln!(writer, "Unserviced interrupt: {}\r", irq);
+            }
+            None => {
+                let _ = writeln!(writer, "No unserviced interrupts\r");
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Picking the UART on the header's GPIO14/15.
+//!
+//! Which of the two UARTs is wired to the header depends on `config.txt`:
+//! by default on the Pi 3 the Mini UART has the header and the PL011 drives
+//! Bluetooth, while `dtoverlay=disable-bt` or `dtoverlay=miniuart-bt` hands
+//! the header to the PL011. `select_console` muxes GPIO14/15 to the UART
+//! asked for, whatever the firmware did, so output ends up on the header
+//! either way. The firmware still owns the pins it muxed for Bluetooth, so
+//! pick the overlay that matches, or the other UART talks to the Bluetooth
+//! chip as well.
+//!
+//! The Mini UART's baud rate is derived from the VPU core clock, which
+//! `core_freq` sets and which the firmware may scale at runtime. With the
+//! Mini UART on the header, `enable_uart=1` pins the core clock to 250 MHz.
+//! `select_console` makes no mailbox calls, so it is safe to use from the
+//! panic handler, and assumes the SoC's nominal core clock; call
+//! `UART::reclock` to take the clock from the firmware, again whenever it
+//! may have changed. The PL011 has its own 48 MHz reference
+//! clock and does not care about `core_freq`.
+
+use core::fmt::Write;
+
+use crate::pl011::Pl011;
+use crate::uart::UART;
+
+/// The UARTs that can be routed to GPIO14/15.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Console {
+    /// The Mini UART (UART1), alt5.
+    Mini,
+    /// The PL011 (UART0), alt0.
+    Pl011,
+}
+
+/// Whichever UART `select_console` set up.
+pub enum ConsoleWriter {
+    Mini(UART),
+    Pl011(Pl011),
+}
+
+/// Routes GPIO14/15 to `console` and sets it up for 8N1 at its default
+/// baud rate.
+///
+/// # Safety
+///
+/// Takes over GPIO14/15 and the UART, regardless of other instances.
+pub unsafe fn select_console(console: Console) -> ConsoleWriter {
+    match console {
+        Console::Mini => {
+            let mut uart = UART::uart1_unchecked();
+            uart.init();
+            ConsoleWriter::Mini(uart)
+        }
+        Console::Pl011 => {
+            let mut uart = Pl011::new();
+            uart.init();
+            ConsoleWriter::Pl011(uart)
+        }
+    }
+}
+
+impl ConsoleWriter {
+    pub fn console(&self) -> Console {
+        match self {
+            ConsoleWriter::Mini(_) => Console::Mini,
+            ConsoleWriter::Pl011(_) => Console::Pl011,
+        }
+    }
+
+    pub fn write_bytes(&mut self, s: &[u8]) {
+        match self {
+            ConsoleWriter::Mini(uart) => uart.write_bytes(s),
+            ConsoleWriter::Pl011(uart) => uart.write_bytes(s),
+        }
+    }
+
+    /// Waits until everything written has left the wire.
+    pub fn flush(&mut self) {
+        match self {
+            ConsoleWriter::Mini(uart) => uart.flush(),
+            ConsoleWriter::Pl011(uart) => uart.flush(),
+        }
+    }
+}
+
+impl Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
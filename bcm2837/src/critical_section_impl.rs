@@ -0,0 +1,24 @@
+//! `critical-section` implementation that masks IRQs and FIQs.
+//!
+//! `acquire` saves DAIF and sets its I and F bits; `release` writes the
+//! saved value back, so nested sections only unmask when the outermost one
+//! ends. Only the boot core runs the kernel, so masking its exceptions is
+//! enough to make a section exclusive.
+
+use core::arch::asm;
+
+struct DaifCriticalSection;
+
+critical_section::set_impl!(DaifCriticalSection);
+
+unsafe impl critical_section::Impl for DaifCriticalSection {
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        let daif: u64;
+        asm!("mrs {}, daif", "msr daifset, #3", out(reg) daif, options(nostack));
+        daif
+    }
+
+    unsafe fn release(daif: critical_section::RawRestoreState) {
+        asm!("msr daif, {}", in(reg) daif, options(nostack));
+    }
+}
@@ -0,0 +1,66 @@
+//! Hex dumps for debugging drivers, in one format across the crate.
+//!
+//! `dump_registers` prints four words per line after their address:
+//!
+//! ```text
+//! 3f215040: 00000000 000000c1 00000000 00000003
+//! ```
+//!
+//! `hexdump` prints sixteen bytes per line after their offset, followed by
+//! the printable ones:
+//!
+//! ```text
+//! 00000000: 48 65 6c 6c 6f 0d 0a                             |Hello..|
+//! ```
+//!
+//! Lines end in `\r\n`, for terminals on the UART. Write errors are
+//! ignored, as there is nowhere else to report them.
+
+use core::fmt::Write;
+
+/// Words per `dump_registers` line.
+const WORDS_PER_LINE: usize = 4;
+/// Bytes per `hexdump` line.
+const BYTES_PER_LINE: usize = 16;
+
+/// Reads `count` words starting at `base` and prints them to `uart`.
+///
+/// # Safety
+///
+/// `base` must be word aligned, and every word up to `base + 4 * count`
+/// must be mapped and safe to read. Some registers, like FIFOs and
+/// read-to-clear status, change state when read.
+pub unsafe fn dump_registers(uart: &mut impl Write, base: usize, count: usize) {
+    for line in (0..count).step_by(WORDS_PER_LINE) {
+        let address = base + 4 * line;
+        let _ = write!(uart, "{:08x}:", address);
+        for word in line..count.min(line + WORDS_PER_LINE) {
+            let value = core::ptr::read_volatile((base + 4 * word) as *const u32);
+            let _ = write!(uart, " {:08x}", value);
+        }
+        let _ = uart.write_str("\r\n");
+    }
+}
+
+/// Prints `bytes` to `uart`, with offsets from the start of the buffer.
+pub fn hexdump(uart: &mut impl Write, bytes: &[u8]) {
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(uart, "{:08x}:", line * BYTES_PER_LINE);
+        for byte in chunk {
+            let _ = write!(uart, " {:02x}", byte);
+        }
+        for _ in chunk.len()..BYTES_PER_LINE {
+            let _ = uart.write_str("   ");
+        }
+        let _ = uart.write_str("  |");
+        for &byte in chunk {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            let _ = uart.write_char(c);
+        }
+        let _ = uart.write_str("|\r\n");
+    }
+}
@@ -0,0 +1,57 @@
+//! defmt global logger on the Mini UART.
+//!
+//! Frames are rzCOBS encoded and written straight to the Mini UART registers
+//! with IRQs masked, so a frame is never interleaved with other output from
+//! this core. The UART must have been set up (`UART::init`) before the first
+//! log, and the final binary has to be linked with `-C link-arg=-Tdefmt.x`.
+
+use core::ptr::addr_of_mut;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use cortex_a::exceptions;
+
+use crate::uart::UART;
+
+#[defmt::global_logger]
+struct Logger;
+
+/// Set between `acquire` and `release`.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+/// Whether IRQs were already masked when the logger was acquired.
+static mut IRQS_MASKED: bool = false;
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+fn write_uart(bytes: &[u8]) {
+    // Safety: only called with the logger acquired, so nothing else on this
+    // core writes the FIFO midway through a frame.
+    unsafe { UART::uart1_unchecked() }.write_bytes(bytes);
+}
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let masked = unsafe { exceptions::save_and_disable_interrupts() };
+        if TAKEN.swap(true, Ordering::Acquire) {
+            panic!("defmt logger acquired twice");
+        }
+        unsafe {
+            IRQS_MASKED = masked;
+            (*addr_of_mut!(ENCODER)).start_frame(write_uart);
+        }
+    }
+
+    unsafe fn release() {
+        (*addr_of_mut!(ENCODER)).end_frame(write_uart);
+        TAKEN.store(false, Ordering::Release);
+        exceptions::restore_interrupts(IRQS_MASKED);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*addr_of_mut!(ENCODER)).write(bytes, write_uart);
+    }
+
+    /// Returns once the last bit has left the wire, not just the FIFO.
+    unsafe fn flush() {
+        UART::uart1_unchecked().flush();
+    }
+}
@@ -0,0 +1,35 @@
+//! Busy-wait delays.
+//!
+//! `delay_us` and `delay_ms` use the System Timer, which the firmware starts
+//! before the kernel runs, so they are accurate regardless of the CPU clock.
+//! `delay_us_generic_timer` uses the core-local ARM generic timer instead and
+//! touches no MMIO, for code that runs before the peripherals are usable.
+
+use crate::system_timer::SystemTimer;
+
+pub fn delay_us(us: u64) {
+    // Safety: only the free-running counter is read.
+    let timer = unsafe { SystemTimer::new() };
+    let start = timer.now_us();
+    while timer.now_us().wrapping_sub(start) < us {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn delay_ms(ms: u64) {
+    delay_us(ms.saturating_mul(1000))
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn delay_us_generic_timer(us: u64) {
+    use cortex_a::generic_timer;
+
+    use crate::time;
+    use crate::time::GenericTimerFrequency;
+
+    let ticks = time::us_to_ticks::<GenericTimerFrequency>(us);
+    let start = generic_timer::counter();
+    while generic_timer::counter().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,305 @@
+use crate::soc::Soc;
+
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CS [
+        Active OFFSET(0) NUMBITS(1) [],
+        End OFFSET(1) NUMBITS(1) [],
+        Int OFFSET(2) NUMBITS(1) [],
+        Dreq OFFSET(3) NUMBITS(1) [],
+        Paused OFFSET(4) NUMBITS(1) [],
+        Error OFFSET(8) NUMBITS(1) [],
+        Priority OFFSET(16) NUMBITS(4) [],
+        PanicPriority OFFSET(20) NUMBITS(4) [],
+        WaitForOutstandingWrites OFFSET(28) NUMBITS(1) [],
+        Abort OFFSET(30) NUMBITS(1) [],
+        Reset OFFSET(31) NUMBITS(1) [],
+    ],
+    pub TI [
+        InterruptEnable OFFSET(0) NUMBITS(1) [],
+        WaitForResponse OFFSET(3) NUMBITS(1) [],
+        DestInc OFFSET(4) NUMBITS(1) [],
+        DestWidth128 OFFSET(5) NUMBITS(1) [],
+        DestDreq OFFSET(6) NUMBITS(1) [],
+        DestIgnore OFFSET(7) NUMBITS(1) [],
+        SrcInc OFFSET(8) NUMBITS(1) [],
+        SrcWidth128 OFFSET(9) NUMBITS(1) [],
+        SrcDreq OFFSET(10) NUMBITS(1) [],
+        SrcIgnore OFFSET(11) NUMBITS(1) [],
+        BurstLength OFFSET(12) NUMBITS(4) [],
+        PeripheralMap OFFSET(16) NUMBITS(5) [],
+        Waits OFFSET(21) NUMBITS(5) [],
+        NoWideBursts OFFSET(26) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+struct ChannelRegisters {
+    /// 0x00: control and status (CS)
+    cs: ReadWrite<u32, CS::Register>,
+
+    /// 0x04: control block address (CONBLK_AD)
+    conblk_ad: ReadWrite<u32>,
+
+    /// 0x08: transfer information, loaded from the control block (TI)
+    _ti: ReadOnly<u32, TI::Register>,
+
+    /// 0x0C: source address (SOURCE_AD)
+    _source_ad: ReadOnly<u32>,
+
+    /// 0x10: destination address (DEST_AD)
+    _dest_ad: ReadOnly<u32>,
+
+    /// 0x14: transfer length (TXFR_LEN)
+    _txfr_len: ReadOnly<u32>,
+
+    /// 0x18: 2D stride (STRIDE)
+    _stride: ReadOnly<u32>,
+
+    /// 0x1C: next control block address (NEXTCONBK)
+    _nextconbk: ReadOnly<u32>,
+
+    /// 0x20: debug (DEBUG)
+    debug: ReadWrite<u32>,
+    _reserved: [u8; 0x100 - 0x24],
+}
+
+#[repr(C)]
+struct Registers {
+    /// 0x000-0xE00: channels 0-14, 0x100 apart
+    channels: [ChannelRegisters; NUM_CHANNELS],
+    _reserved0: [u8; 0xFE0 - 0xF00],
+
+    /// 0xFE0: interrupt status of each channel (INT_STATUS)
+    int_status: ReadWrite<u32>,
+    _reserved1: [u8; 0xFF0 - 0xFE4],
+
+    /// 0xFF0: global enable of each channel (ENABLE)
+    enable: ReadWrite<u32>,
+}
+
+/// Channels in the main DMA block. Channel 15 lives elsewhere and is not
+/// supported.
+pub const NUM_CHANNELS: usize = 15;
+
+/// Interrupt controller line of channel 0. Channels 0-10 have one line each;
+/// 11-14 share line 27.
+pub const DMA0_IRQ: u32 = 16;
+
+/// The interrupt controller line of `channel`.
+pub const fn irq(channel: usize) -> u32 {
+    if channel <= 10 {
+        DMA0_IRQ + channel as u32
+    } else {
+        DMA0_IRQ + 11
+    }
+}
+
+/// Bus address of the start of the peripheral window.
+const BUS_PERIPHERAL_BASE: usize = 0x7E00_0000;
+
+/// Translates an ARM physical address into the VideoCore bus address the DMA
+/// engine expects.
+///
+/// Peripherals in the window `Soc::detect` finds appear at 0x7E000000 on
+/// the bus. SDRAM is mapped through the uncached 0xC0000000 alias so that
+/// the engine does not go through the VideoCore L2 cache.
+pub fn bus_address(address: usize) -> u32 {
+    let soc = Soc::detect();
+    let base = soc.peripheral_base;
+    if address >= base && address < base + soc.peripheral_size {
+        (address - base + BUS_PERIPHERAL_BASE) as u32
+    } else {
+        address as u32 | 0xC000_0000
+    }
+}
+
+/// A DMA control block, as read by the engine.
+///
+/// Control blocks must be 32-byte aligned, and all addresses in them are bus
+/// addresses, not ARM physical addresses. Use `bus_address` to convert.
+#[repr(C, align(32))]
+pub struct ControlBlock {
+    pub ti: u32,
+    pub source: u32,
+    pub dest: u32,
+    pub length: u32,
+    pub stride: u32,
+    /// Bus address of the next control block, or 0 to stop.
+    pub next: u32,
+    _reserved: [u32; 2],
+}
+
+impl ControlBlock {
+    /// A copy of `length` bytes between two bus addresses that raises the
+    /// channel interrupt when done.
+    pub const fn new(source: u32, dest: u32, length: u32, ti: u32) -> ControlBlock {
+        ControlBlock {
+            ti,
+            source,
+            dest,
+            length,
+            stride: 0,
+            next: 0,
+            _reserved: [0; 2],
+        }
+    }
+
+    /// A copy from `source` to `dest` in memory.
+    pub fn memory_to_memory(source: &[u8], dest: &mut [u8]) -> ControlBlock {
+        let ti = TI::SrcInc::SET + TI::DestInc::SET + TI::InterruptEnable::SET;
+        ControlBlock::new(
+            bus_address(source.as_ptr() as usize),
+            bus_address(dest.as_mut_ptr() as usize),
+            source.len().min(dest.len()) as u32,
+            ti.value,
+        )
+    }
+
+    /// A copy from `source` in memory to the FIFO register at physical
+    /// address `fifo`, paced by the peripheral's DREQ line `dreq`.
+    pub fn memory_to_peripheral(source: &[u8], fifo: usize, dreq: u32) -> ControlBlock {
+        let ti = TI::SrcInc::SET
+            + TI::DestDreq::SET
+            + TI::PeripheralMap.val(dreq)
+            + TI::WaitForResponse::SET
+            + TI::InterruptEnable::SET;
+        ControlBlock::new(
+            bus_address(source.as_ptr() as usize),
+            bus_address(fifo),
+            source.len() as u32,
+            ti.value,
+        )
+    }
+
+    /// A copy from the FIFO register at physical address `fifo` to `dest`
+    /// in memory, paced by the peripheral's DREQ line `dreq`.
+    pub fn peripheral_to_memory(fifo: usize, dreq: u32, dest: &mut [u8]) -> ControlBlock {
+        let ti = TI::SrcDreq::SET
+            + TI::PeripheralMap.val(dreq)
+            + TI::DestInc::SET
+            + TI::WaitForResponse::SET
+            + TI::InterruptEnable::SET;
+        ControlBlock::new(
+            bus_address(fifo),
+            bus_address(dest.as_mut_ptr() as usize),
+            dest.len() as u32,
+            ti.value,
+        )
+    }
+}
+
+pub trait Client {
+    /// The transfer on `channel` finished, or stopped on an error.
+    fn transfer_done(&self, channel: usize, result: Result<(), ErrorCode>);
+}
+
+/// The DMA controller at offset 0x7000 of the peripheral window.
+pub struct Dma<'a> {
+    registers: StaticRef<Registers>,
+    client: OptionalCell<&'a dyn Client>,
+}
+
+impl<'a> Dma<'a> {
+    pub unsafe fn new() -> Dma<'a> {
+        Dma::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Dma<'a> {
+        Dma {
+            registers: StaticRef::new((soc.peripheral_base + 0x7000) as *const Registers),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    /// Starts `channel` on the list of control blocks starting at `block`.
+    ///
+    /// The engine does not snoop the ARM caches. `block` is cleaned to
+    /// memory here; the blocks it links to and the source buffers must be
+    /// cleaned by the caller with `cortex_a::cache`, and destination buffers
+    /// invalidated before the CPU reads them.
+    ///
+    /// # Safety
+    ///
+    /// `block`, the blocks it links to and the buffers they describe must
+    /// stay valid, and must not be touched by the CPU, until the transfer
+    /// finishes.
+    pub unsafe fn start_transfer(
+        &self,
+        channel: usize,
+        block: &ControlBlock,
+    ) -> Result<(), ErrorCode> {
+        if channel >= NUM_CHANNELS {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.is_busy(channel) {
+            return Err(ErrorCode::BUSY);
+        }
+        #[cfg(target_arch = "aarch64")]
+        cortex_a::cache::clean_range(
+            block as *const ControlBlock as usize,
+            core::mem::size_of::<ControlBlock>(),
+        );
+        let registers = &self.registers.channels[channel];
+        self.registers
+            .enable
+            .set(self.registers.enable.get() | 1 << channel);
+        registers.cs.write(CS::Reset::SET);
+        // Clear any error flags left from a previous transfer.
+        registers.debug.set(0b111);
+        registers
+            .conblk_ad
+            .set(bus_address(block as *const ControlBlock as usize));
+        registers.cs.write(CS::End::SET + CS::Int::SET);
+        registers
+            .cs
+            .write(CS::Active::SET + CS::WaitForOutstandingWrites::SET);
+        Ok(())
+    }
+
+    pub fn is_busy(&self, channel: usize) -> bool {
+        channel < NUM_CHANNELS && self.registers.channels[channel].cs.is_set(CS::Active)
+    }
+
+    /// Stops `channel` without waiting for its transfer to finish.
+    pub fn abort(&self, channel: usize) {
+        if channel < NUM_CHANNELS {
+            self.registers.channels[channel].cs.write(CS::Reset::SET);
+        }
+    }
+
+    /// Acknowledges every channel with a pending interrupt by clearing its
+    /// INT bit and passes the result to the client.
+    pub fn handle_interrupt(&self) {
+        let pending = self.registers.int_status.get();
+        for channel in 0..NUM_CHANNELS {
+            if pending & 1 << channel == 0 {
+                continue;
+            }
+            let registers = &self.registers.channels[channel];
+            let result = if registers.cs.is_set(CS::Error) {
+                Err(ErrorCode::FAIL)
+            } else {
+                Ok(())
+            };
+            // INT and END are write-1-to-clear.
+            registers.cs.modify(CS::Int::SET + CS::End::SET);
+            self.client
+                .map(|client| client.transfer_done(channel, result));
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! Polled SD card driver for the Arasan EMMC controller.
+//!
+//! Only SDHC/SDXC cards (SD physical layer 2.0 or later, block addressed)
+//! are supported, one 512-byte block at a time. SDSC and SD 1.x cards,
+//! which use byte addressing, and MMC cards, which need a different init
+//! sequence, are rejected with `EmmcError::UnsupportedCard`.
+
+use crate::delay;
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::soc::Soc;
+use crate::system_timer::SystemTimer;
+
+use kernel::utilities::StaticRef;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    BLKSIZECNT [
+        BlockSize OFFSET(0) NUMBITS(10) [],
+        BlockCount OFFSET(16) NUMBITS(16) [],
+    ],
+    CMDTM [
+        BlockCountEnable OFFSET(1) NUMBITS(1) [],
+        DataDirection OFFSET(4) NUMBITS(1) [
+            HostToCard = 0,
+            CardToHost = 1,
+        ],
+        MultiBlock OFFSET(5) NUMBITS(1) [],
+        ResponseType OFFSET(16) NUMBITS(2) [
+            None = 0,
+            Bits136 = 1,
+            Bits48 = 2,
+            Bits48Busy = 3,
+        ],
+        CrcCheck OFFSET(19) NUMBITS(1) [],
+        IndexCheck OFFSET(20) NUMBITS(1) [],
+        IsData OFFSET(21) NUMBITS(1) [],
+        Index OFFSET(24) NUMBITS(6) [],
+    ],
+    STATUS [
+        CommandInhibit OFFSET(0) NUMBITS(1) [],
+        DataInhibit OFFSET(1) NUMBITS(1) [],
+    ],
+    CONTROL1 [
+        ClockInternalEnable OFFSET(0) NUMBITS(1) [],
+        ClockStable OFFSET(1) NUMBITS(1) [],
+        ClockEnable OFFSET(2) NUMBITS(1) [],
+        ClockFreqHigh OFFSET(6) NUMBITS(2) [],
+        ClockFreqLow OFFSET(8) NUMBITS(8) [],
+        DataTimeout OFFSET(16) NUMBITS(4) [],
+        ResetHost OFFSET(24) NUMBITS(1) [],
+        ResetCommand OFFSET(25) NUMBITS(1) [],
+        ResetData OFFSET(26) NUMBITS(1) [],
+    ],
+    INTERRUPT [
+        CommandDone OFFSET(0) NUMBITS(1) [],
+        DataDone OFFSET(1) NUMBITS(1) [],
+        WriteReady OFFSET(4) NUMBITS(1) [],
+        ReadReady OFFSET(5) NUMBITS(1) [],
+        Error OFFSET(15) NUMBITS(1) [],
+        CommandTimeout OFFSET(16) NUMBITS(1) [],
+        DataTimeout OFFSET(20) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: argument for ACMD23 (ARG2)
+    _arg2: ReadWrite<u32>,
+
+    /// 0x04: block size and count (BLKSIZECNT)
+    blksizecnt: ReadWrite<u32, BLKSIZECNT::Register>,
+
+    /// 0x08: command argument (ARG1)
+    arg1: ReadWrite<u32>,
+
+    /// 0x0C: command and transfer mode (CMDTM)
+    cmdtm: ReadWrite<u32, CMDTM::Register>,
+
+    /// 0x10-0x1C: response (RESP0-RESP3)
+    resp: [ReadOnly<u32>; 4],
+
+    /// 0x20: data FIFO (DATA)
+    data: ReadWrite<u32>,
+
+    /// 0x24: status (STATUS)
+    status: ReadOnly<u32, STATUS::Register>,
+
+    /// 0x28: host configuration (CONTROL0)
+    control0: ReadWrite<u32>,
+
+    /// 0x2C: clock and reset control (CONTROL1)
+    control1: ReadWrite<u32, CONTROL1::Register>,
+
+    /// 0x30: interrupt flags, write 1 to clear (INTERRUPT)
+    interrupt: ReadWrite<u32, INTERRUPT::Register>,
+
+    /// 0x34: interrupt flag enable (IRPT_MASK)
+    irpt_mask: ReadWrite<u32, INTERRUPT::Register>,
+
+    /// 0x38: interrupt line enable (IRPT_EN)
+    irpt_en: ReadWrite<u32, INTERRUPT::Register>,
+
+    /// 0x3C: host configuration (CONTROL2)
+    control2: ReadWrite<u32>,
+}
+
+/// Size of a block of any supported card.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Block storage addressed by logical block number.
+pub trait BlockDevice {
+    type Error;
+
+    fn read_block(&mut self, lba: u32, buffer: &mut [u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+
+    fn write_block(&mut self, lba: u32, buffer: &[u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmmcError {
+    /// The card did not respond in time, or no card is inserted.
+    Timeout,
+    /// The controller flagged a CRC, index or end bit error.
+    Command,
+    /// The controller did not come out of reset or its clock did not settle.
+    Controller,
+    /// The card is not an SDHC/SDXC card.
+    UnsupportedCard,
+    /// `init` has not completed successfully.
+    NotInitialized,
+}
+
+/// SD card signals, alt3: CLK, CMD and DAT0-3.
+///
+/// The firmware routes the card slot to the SDHOST controller; these pins
+/// move it to the EMMC controller.
+const PINS: [usize; 6] = [48, 49, 50, 51, 52, 53];
+
+/// Clock used during card identification.
+const IDENTIFICATION_CLOCK_HZ: u32 = 400_000;
+/// Clock used once the card is selected, the default-speed maximum.
+const TRANSFER_CLOCK_HZ: u32 = 25_000_000;
+
+const COMMAND_TIMEOUT_US: u64 = 100_000;
+const DATA_TIMEOUT_US: u64 = 500_000;
+/// How long the card may take to finish powering up after ACMD41.
+const POWER_UP_TIMEOUT_US: u64 = 1_000_000;
+
+/// SEND_IF_COND argument: 2.7-3.6 V and the check pattern 0xAA.
+const IF_COND_CHECK: u32 = 0x1AA;
+/// ACMD41 argument: host supports high capacity, 3.2-3.4 V window.
+const OCR_REQUEST: u32 = 0x4030_0000;
+const OCR_POWERED_UP: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+
+#[derive(Clone, Copy)]
+enum Response {
+    None,
+    R1,
+    R1b,
+    R2,
+    R3,
+    R6,
+    R7,
+}
+
+#[derive(Clone, Copy)]
+enum Command {
+    GoIdle,
+    AllSendCid,
+    SendRelativeAddress,
+    Select,
+    SendIfCond,
+    ReadSingleBlock,
+    WriteBlock,
+    AppCommand,
+    SendOpCond,
+}
+
+impl Command {
+    fn index(self) -> u32 {
+        match self {
+            Command::GoIdle => 0,
+            Command::AllSendCid => 2,
+            Command::SendRelativeAddress => 3,
+            Command::Select => 7,
+            Command::SendIfCond => 8,
+            Command::ReadSingleBlock => 17,
+            Command::WriteBlock => 24,
+            Command::AppCommand => 55,
+            // ACMD41, sent after APP_CMD.
+            Command::SendOpCond => 41,
+        }
+    }
+
+    fn response(self) -> Response {
+        match self {
+            Command::GoIdle => Response::None,
+            Command::AllSendCid => Response::R2,
+            Command::SendRelativeAddress => Response::R6,
+            Command::Select => Response::R1b,
+            Command::SendIfCond => Response::R7,
+            Command::ReadSingleBlock | Command::WriteBlock | Command::AppCommand => Response::R1,
+            Command::SendOpCond => Response::R3,
+        }
+    }
+}
+
+/// The EMMC controller at offset 0x300000 of the peripheral window, driving
+/// the SD card slot.
+pub struct Emmc {
+    registers: StaticRef<Registers>,
+    base_clock: u32,
+    /// Relative card address, assigned during `init`.
+    rca: Option<u32>,
+    soc: Soc,
+}
+
+impl Emmc {
+    pub unsafe fn new() -> Emmc {
+        Emmc::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Emmc {
+        Emmc {
+            registers: StaticRef::new((soc.peripheral_base + 0x300000) as *const Registers),
+            base_clock: 0,
+            rca: None,
+            soc: *soc,
+        }
+    }
+
+    /// Resets the controller and identifies and selects the card.
+    ///
+    /// `base_clock` is the EMMC clock in Hz, as reported by
+    /// `Mailbox::get_clock_rate(Clock::Emmc)`.
+    pub fn init(&mut self, base_clock: u32) -> Result<(), EmmcError> {
+        self.rca = None;
+        self.base_clock = base_clock;
+
+        // Safety: GPIO48-53 belong to the SD card slot.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in PINS {
+            gpio.set_function(pin, Function::Alt3);
+        }
+
+        self.reset()?;
+        self.set_clock(IDENTIFICATION_CLOCK_HZ)?;
+
+        self.command(Command::GoIdle, 0)?;
+        // Cards older than version 2.00 do not answer SEND_IF_COND, and
+        // none of them are high capacity.
+        match self.command(Command::SendIfCond, IF_COND_CHECK) {
+            Ok(echo) if echo & 0xFFF == IF_COND_CHECK => {}
+            Ok(_) | Err(EmmcError::Timeout) => return Err(EmmcError::UnsupportedCard),
+            Err(error) => return Err(error),
+        }
+
+        let deadline = now_us() + POWER_UP_TIMEOUT_US;
+        let ocr = loop {
+            self.command(Command::AppCommand, 0)?;
+            let ocr = self.command(Command::SendOpCond, OCR_REQUEST)?;
+            if ocr & OCR_POWERED_UP != 0 {
+                break ocr;
+            }
+            if now_us() > deadline {
+                return Err(EmmcError::Timeout);
+            }
+            delay::delay_ms(10);
+        };
+        if ocr & OCR_HIGH_CAPACITY == 0 {
+            return Err(EmmcError::UnsupportedCard);
+        }
+
+        self.command(Command::AllSendCid, 0)?;
+        let rca = self.command(Command::SendRelativeAddress, 0)? & 0xFFFF_0000;
+        self.command(Command::Select, rca)?;
+        self.set_clock(TRANSFER_CLOCK_HZ)?;
+        self.rca = Some(rca);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), EmmcError> {
+        self.registers.control0.set(0);
+        self.registers.control2.set(0);
+        self.registers.control1.write(CONTROL1::ResetHost::SET);
+        self.wait(COMMAND_TIMEOUT_US, || {
+            !self.registers.control1.is_set(CONTROL1::ResetHost)
+        })
+        .map_err(|_| EmmcError::Controller)?;
+
+        // Report every event in INTERRUPT, but do not raise the IRQ line.
+        self.registers.irpt_en.set(0);
+        self.registers.irpt_mask.set(0xFFFF_FFFF);
+        self.registers.interrupt.set(0xFFFF_FFFF);
+        Ok(())
+    }
+
+    /// Switches the card clock to at most `hz`.
+    fn set_clock(&mut self, hz: u32) -> Result<(), EmmcError> {
+        self.wait(COMMAND_TIMEOUT_US, || {
+            !self.registers.status.is_set(STATUS::CommandInhibit)
+                && !self.registers.status.is_set(STATUS::DataInhibit)
+        })?;
+        self.registers.control1.modify(CONTROL1::ClockEnable::CLEAR);
+
+        // The card clock is base / (2 * divisor), with a 10-bit divisor.
+        let divisor = self.base_clock.div_ceil(2 * hz).clamp(1, 0x3FF);
+        self.registers.control1.modify(
+            CONTROL1::ClockFreqLow.val(divisor & 0xFF)
+                + CONTROL1::ClockFreqHigh.val(divisor >> 8)
+                + CONTROL1::DataTimeout.val(0xE)
+                + CONTROL1::ClockInternalEnable::SET,
+        );
+        self.wait(COMMAND_TIMEOUT_US, || {
+            self.registers.control1.is_set(CONTROL1::ClockStable)
+        })
+        .map_err(|_| EmmcError::Controller)?;
+        self.registers.control1.modify(CONTROL1::ClockEnable::SET);
+        Ok(())
+    }
+
+    /// Sends `command` and returns the first response word.
+    fn command(&mut self, command: Command, argument: u32) -> Result<u32, EmmcError> {
+        self.send(command, argument, None)
+    }
+
+    fn send(
+        &mut self,
+        command: Command,
+        argument: u32,
+        direction: Option<bool>,
+    ) -> Result<u32, EmmcError> {
+        self.wait(COMMAND_TIMEOUT_US, || {
+            !self.registers.status.is_set(STATUS::CommandInhibit)
+        })?;
+
+        let response = match command.response() {
+            Response::None => CMDTM::ResponseType::None,
+            Response::R2 => CMDTM::ResponseType::Bits136 + CMDTM::CrcCheck::SET,
+            // R3 carries no CRC or command index.
+            Response::R3 => CMDTM::ResponseType::Bits48,
+            Response::R1b => {
+                CMDTM::ResponseType::Bits48Busy + CMDTM::CrcCheck::SET + CMDTM::IndexCheck::SET
+            }
+            Response::R1 | Response::R6 | Response::R7 => {
+                CMDTM::ResponseType::Bits48 + CMDTM::CrcCheck::SET + CMDTM::IndexCheck::SET
+            }
+        };
+        let data = match direction {
+            None => CMDTM::IsData::CLEAR,
+            Some(true) => CMDTM::IsData::SET + CMDTM::DataDirection::CardToHost,
+            Some(false) => CMDTM::IsData::SET + CMDTM::DataDirection::HostToCard,
+        };
+
+        self.registers.interrupt.set(0xFFFF_FFFF);
+        self.registers.arg1.set(argument);
+        self.registers
+            .cmdtm
+            .write(CMDTM::Index.val(command.index()) + response + data);
+        self.wait_interrupt(INTERRUPT::CommandDone::SET.value, COMMAND_TIMEOUT_US)?;
+        Ok(self.registers.resp[0].get())
+    }
+
+    /// Waits for any of the interrupt flags in `mask`, clearing them.
+    fn wait_interrupt(&mut self, mask: u32, timeout_us: u64) -> Result<(), EmmcError> {
+        let deadline = now_us() + timeout_us;
+        loop {
+            let flags = self.registers.interrupt.extract();
+            if flags.is_set(INTERRUPT::CommandTimeout) || flags.is_set(INTERRUPT::DataTimeout) {
+                self.recover();
+                return Err(EmmcError::Timeout);
+            }
+            if flags.is_set(INTERRUPT::Error) {
+                self.recover();
+                return Err(EmmcError::Command);
+            }
+            if flags.get() & mask != 0 {
+                self.registers.interrupt.set(flags.get() & mask);
+                return Ok(());
+            }
+            if now_us() > deadline {
+                self.recover();
+                return Err(EmmcError::Timeout);
+            }
+        }
+    }
+
+    /// Clears the error flags and resets the command and data lines.
+    fn recover(&mut self) {
+        self.registers.interrupt.set(0xFFFF_FFFF);
+        self.registers
+            .control1
+            .modify(CONTROL1::ResetCommand::SET + CONTROL1::ResetData::SET);
+        let _ = self.wait(COMMAND_TIMEOUT_US, || {
+            !self.registers.control1.is_set(CONTROL1::ResetCommand)
+                && !self.registers.control1.is_set(CONTROL1::ResetData)
+        });
+    }
+
+    fn wait(&self, timeout_us: u64, done: impl Fn() -> bool) -> Result<(), EmmcError> {
+        let deadline = now_us() + timeout_us;
+        while !done() {
+            if now_us() > deadline {
+                return Err(EmmcError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn start_block(&mut self, command: Command, lba: u32, read: bool) -> Result<(), EmmcError> {
+        if self.rca.is_none() {
+            return Err(EmmcError::NotInitialized);
+        }
+        self.wait(DATA_TIMEOUT_US, || {
+            !self.registers.status.is_set(STATUS::DataInhibit)
+        })?;
+        self.registers
+            .blksizecnt
+            .write(BLKSIZECNT::BlockSize.val(BLOCK_SIZE as u32) + BLKSIZECNT::BlockCount.val(1));
+        // High capacity cards are addressed in blocks, so `lba` is passed
+        // as-is.
+        self.send(command, lba, Some(read))?;
+        Ok(())
+    }
+}
+
+impl BlockDevice for Emmc {
+    type Error = EmmcError;
+
+    fn read_block(&mut self, lba: u32, buffer: &mut [u8; BLOCK_SIZE]) -> Result<(), EmmcError> {
+        self.start_block(Command::ReadSingleBlock, lba, true)?;
+        self.wait_interrupt(INTERRUPT::ReadReady::SET.value, DATA_TIMEOUT_US)?;
+        for chunk in buffer.chunks_exact_mut(4) {
+            chunk.copy_from_slice(&self.registers.data.get().to_le_bytes());
+        }
+        self.wait_interrupt(INTERRUPT::DataDone::SET.value, DATA_TIMEOUT_US)
+    }
+
+    fn write_block(&mut self, lba: u32, buffer: &[u8; BLOCK_SIZE]) -> Result<(), EmmcError> {
+        self.start_block(Command::WriteBlock, lba, false)?;
+        self.wait_interrupt(INTERRUPT::WriteReady::SET.value, DATA_TIMEOUT_US)?;
+        for chunk in buffer.chunks_exact(4) {
+            self.registers
+                .data
+                .set(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        }
+        self.wait_interrupt(INTERRUPT::DataDone::SET.value, DATA_TIMEOUT_US)
+    }
+}
+
+fn now_us() -> u64 {
+    // Safety: only the free-running counter is read.
+    unsafe { SystemTimer::new() }.now_us()
+}
@@ -0,0 +1,476 @@
+//! Read-only FAT32 on a `BlockDevice`, enough to find files on the SD
+//! card's boot partition.
+//!
+//! `FileSystem::mount` takes the first FAT32 partition (type 0x0B or 0x0C)
+//! of the MBR, or the whole device if it starts with a FAT32 boot sector
+//! instead. Only 8.3 names are supported: long file name entries are
+//! skipped, and a path component that does not fit 8.3 is not found. Names
+//! are compared without regard to ASCII case, as FAT stores them in upper
+//! case.
+//!
+//! Files are read sequentially, following the cluster chain as the read
+//! position moves; `File::rewind` starts over.
+
+use crate::emmc::BlockDevice;
+use crate::emmc::BLOCK_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatError<E> {
+    /// The block device failed.
+    Device(E),
+    /// Neither the MBR nor block 0 describes a FAT32 volume.
+    NoPartition,
+    /// The boot sector has a layout this driver does not handle, e.g.
+    /// sectors that are not 512 bytes.
+    Unsupported,
+    /// A cluster chain or directory entry points outside the volume.
+    Corrupt,
+    NotFound,
+    /// A path component other than the last one is a file.
+    NotADirectory,
+    IsADirectory,
+}
+
+/// Directory entry attributes.
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// The attribute combination marking a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// First byte of a free entry; no entries follow it.
+const ENTRY_END: u8 = 0x00;
+/// First byte of a deleted entry.
+const ENTRY_DELETED: u8 = 0xE5;
+const ENTRY_SIZE: usize = 32;
+
+/// FAT entries at or above this end a cluster chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+/// FAT entry of a cluster marked bad.
+const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+
+fn u16_at(block: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([block[offset], block[offset + 1]])
+}
+
+fn u32_at(block: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        block[offset],
+        block[offset + 1],
+        block[offset + 2],
+        block[offset + 3],
+    ])
+}
+
+/// Whether `block` is a FAT32 boot sector: the signature, 512-byte sectors
+/// and the FAT32 layout, with no fixed root directory and no 16-bit FAT
+/// size.
+fn is_fat32_boot_sector(block: &[u8; BLOCK_SIZE]) -> bool {
+    block[510..512] == [0x55, 0xAA]
+        && matches!(block[0], 0xEB | 0xE9)
+        && u16_at(block, 17) == 0
+        && u16_at(block, 22) == 0
+}
+
+/// `component` as the space-padded upper-case 11 bytes of an 8.3 entry, or
+/// `None` if it does not fit.
+fn short_name(component: &str) -> Option<[u8; 11]> {
+    let mut name = [b' '; 11];
+    if component == "." || component == ".." {
+        name[..component.len()].copy_from_slice(component.as_bytes());
+        return Some(name);
+    }
+    let (base, extension) = match component.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (component, ""),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+    let (base_field, extension_field) = name.split_at_mut(8);
+    for (field, part) in [(base_field, base), (extension_field, extension)] {
+        for (byte, c) in field.iter_mut().zip(part.bytes()) {
+            if !c.is_ascii_graphic() || b"\"*+,./:;<=>?[\\]|".contains(&c) {
+                return None;
+            }
+            *byte = c.to_ascii_uppercase();
+        }
+    }
+    Some(name)
+}
+
+/// A file or directory found by `FileSystem::open`.
+#[derive(Clone, Copy, Debug)]
+pub struct File {
+    first_cluster: u32,
+    size: u32,
+    is_dir: bool,
+    /// Read position in bytes.
+    position: u32,
+    /// The cluster holding `position`, and its index in the chain.
+    cluster: u32,
+    cluster_index: u32,
+}
+
+impl File {
+    fn new(first_cluster: u32, size: u32, is_dir: bool) -> File {
+        File {
+            first_cluster,
+            size,
+            is_dir,
+            position: 0,
+            cluster: first_cluster,
+            cluster_index: 0,
+        }
+    }
+
+    /// Size in bytes. Directories report 0.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Moves the read position back to the start.
+    pub fn rewind(&mut self) {
+        *self = File::new(self.first_cluster, self.size, self.is_dir);
+    }
+}
+
+/// A mounted FAT32 volume.
+pub struct FileSystem<D: BlockDevice> {
+    device: D,
+    /// Block of the first FAT.
+    fat_start: u32,
+    /// Block of cluster 2, the first data cluster.
+    data_start: u32,
+    sectors_per_cluster: u32,
+    root_cluster: u32,
+    /// Highest valid cluster number.
+    max_cluster: u32,
+    block: [u8; BLOCK_SIZE],
+    /// The block held in `block`.
+    cached: Option<u32>,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// Finds the FAT32 volume on `device` and reads its boot sector.
+    pub fn mount(device: D) -> Result<FileSystem<D>, FatError<D::Error>> {
+        let mut fs = FileSystem {
+            device,
+            fat_start: 0,
+            data_start: 0,
+            sectors_per_cluster: 0,
+            root_cluster: 0,
+            max_cluster: 0,
+            block: [0; BLOCK_SIZE],
+            cached: None,
+        };
+
+        fs.load(0)?;
+        let start = if is_fat32_boot_sector(&fs.block) {
+            0
+        } else if fs.block[510..512] == [0x55, 0xAA] {
+            // Four 16-byte partition entries at 446: the type at 4 and the
+            // first block at 8.
+            (0..4)
+                .map(|index| 446 + 16 * index)
+                .find(|&entry| matches!(fs.block[entry + 4], 0x0B | 0x0C))
+                .map(|entry| u32_at(&fs.block, entry + 8))
+                .ok_or(FatError::NoPartition)?
+        } else {
+            return Err(FatError::NoPartition);
+        };
+
+        fs.load(start)?;
+        if !is_fat32_boot_sector(&fs.block) {
+            return Err(FatError::NoPartition);
+        }
+        let bytes_per_sector = u16_at(&fs.block, 11) as usize;
+        let sectors_per_cluster = fs.block[13] as u32;
+        let reserved_sectors = u16_at(&fs.block, 14) as u32;
+        let num_fats = fs.block[16] as u32;
+        let total_sectors = u32_at(&fs.block, 32);
+        let fat_size = u32_at(&fs.block, 36);
+        let root_cluster = u32_at(&fs.block, 44);
+        if bytes_per_sector != BLOCK_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || num_fats == 0
+            || fat_size == 0
+        {
+            return Err(FatError::Unsupported);
+        }
+
+        let metadata = reserved_sectors + num_fats * fat_size;
+        let clusters = total_sectors
+            .checked_sub(metadata)
+            .ok_or(FatError::Corrupt)?
+            / sectors_per_cluster;
+        fs.fat_start = start + reserved_sectors;
+        fs.data_start = start + metadata;
+        fs.sectors_per_cluster = sectors_per_cluster;
+        fs.root_cluster = root_cluster;
+        fs.max_cluster = clusters + 1;
+        if !fs.is_data_cluster(root_cluster) {
+            return Err(FatError::Corrupt);
+        }
+        Ok(fs)
+    }
+
+    /// Gives the block device back.
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    /// Reads block `lba` into `self.block`, unless it is already there.
+    fn load(&mut self, lba: u32) -> Result<(), FatError<D::Error>> {
+        if self.cached != Some(lba) {
+            self.cached = None;
+            self.device
+                .read_block(lba, &mut self.block)
+                .map_err(FatError::Device)?;
+            self.cached = Some(lba);
+        }
+        Ok(())
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (2..=self.max_cluster).contains(&cluster)
+    }
+
+    fn cluster_lba(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * BLOCK_SIZE as u32
+    }
+
+    /// The cluster after `cluster` in its chain, or `None` at the end.
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FatError<D::Error>> {
+        let offset = cluster as usize * 4;
+        self.load(self.fat_start + (offset / BLOCK_SIZE) as u32)?;
+        // The top four bits are reserved.
+        let next = u32_at(&self.block, offset % BLOCK_SIZE) & 0x0FFF_FFFF;
+        match next {
+            END_OF_CHAIN.. => Ok(None),
+            BAD_CLUSTER => Err(FatError::Corrupt),
+            next if self.is_data_cluster(next) => Ok(Some(next)),
+            _ => Err(FatError::Corrupt),
+        }
+    }
+
+    /// The root directory.
+    pub fn root(&self) -> File {
+        File::new(self.root_cluster, 0, true)
+    }
+
+    /// Looks up `path`, with components separated by `/`, from the root
+    /// directory.
+    pub fn open(&mut self, path: &str) -> Result<File, FatError<D::Error>> {
+        let mut file = self.root();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !file.is_dir {
+                return Err(FatError::NotADirectory);
+            }
+            let name = short_name(component).ok_or(FatError::NotFound)?;
+            file = self.find(&file, &name)?;
+        }
+        Ok(file)
+    }
+
+    /// Finds the entry named `name` in `dir`.
+    fn find(&mut self, dir: &File, name: &[u8; 11]) -> Result<File, FatError<D::Error>> {
+        let mut cluster = Some(dir.first_cluster);
+        // A chain longer than the volume has clusters must be a cycle.
+        let mut walked = 0;
+        while let Some(current) = cluster {
+            walked += 1;
+            if !self.is_data_cluster(current) || walked > self.max_cluster {
+                return Err(FatError::Corrupt);
+            }
+            for sector in 0..self.sectors_per_cluster {
+                self.load(self.cluster_lba(current) + sector)?;
+                for entry in self.block.chunks_exact(ENTRY_SIZE) {
+                    let attributes = entry[11];
+                    if entry[0] == ENTRY_END {
+                        return Err(FatError::NotFound);
+                    }
+                    if entry[0] == ENTRY_DELETED
+                        || attributes & ATTR_LONG_NAME == ATTR_LONG_NAME
+                        || attributes & ATTR_VOLUME_ID != 0
+                        || entry[..11] != name[..]
+                    {
+                        continue;
+                    }
+                    let first_cluster =
+                        ((u16_at(entry, 20) as u32) << 16) | u16_at(entry, 26) as u32;
+                    let is_dir = attributes & ATTR_DIRECTORY != 0;
+                    // `..` in a directory below the root points at cluster 0.
+                    if is_dir && first_cluster == 0 {
+                        return Ok(self.root());
+                    }
+                    // Only an empty file may have no clusters.
+                    let size = u32_at(entry, 28);
+                    if (is_dir || size != 0) && !self.is_data_cluster(first_cluster) {
+                        return Err(FatError::Corrupt);
+                    }
+                    return Ok(File::new(first_cluster, size, is_dir));
+                }
+            }
+            cluster = self.next_cluster(current)?;
+        }
+        Err(FatError::NotFound)
+    }
+
+    /// Reads from `file` at its read position into `buf` and advances the
+    /// position. Returns the number of bytes read, 0 at the end of the file.
+    pub fn read(&mut self, file: &mut File, buf: &mut [u8]) -> Result<usize, FatError<D::Error>> {
+        if file.is_dir {
+            return Err(FatError::IsADirectory);
+        }
+        let cluster_bytes = self.cluster_bytes();
+        let mut done = 0;
+        while done < buf.len() && file.position < file.size {
+            while file.cluster_index < file.position / cluster_bytes {
+                file.cluster = self.next_cluster(file.cluster)?.ok_or(FatError::Corrupt)?;
+                file.cluster_index += 1;
+            }
+            if !self.is_data_cluster(file.cluster) {
+                return Err(FatError::Corrupt);
+            }
+
+            let offset = file.position % cluster_bytes;
+            self.load(self.cluster_lba(file.cluster) + offset / BLOCK_SIZE as u32)?;
+            let start = offset as usize % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - start)
+                .min(buf.len() - done)
+                .min((file.size - file.position) as usize);
+            buf[done..done + len].copy_from_slice(&self.block[start..start + len]);
+            done += len;
+            file.position += len as u32;
+        }
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A block device over blocks in memory.
+    struct Image(Vec<[u8; BLOCK_SIZE]>);
+
+    impl BlockDevice for Image {
+        type Error = ();
+
+        fn read_block(&mut self, lba: u32, buffer: &mut [u8; BLOCK_SIZE]) -> Result<(), ()> {
+            *buffer = *self.0.get(lba as usize).ok_or(())?;
+            Ok(())
+        }
+
+        fn write_block(&mut self, _lba: u32, _buffer: &[u8; BLOCK_SIZE]) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    const FAT_BLOCK: usize = 1;
+    /// Block of cluster 2, with one block per cluster.
+    const DATA_BLOCK: usize = 2;
+
+    fn entry(
+        block: &mut [u8; BLOCK_SIZE],
+        index: usize,
+        name: &[u8; 11],
+        attributes: u8,
+        cluster: u32,
+        size: u32,
+    ) {
+        let entry = &mut block[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attributes;
+        entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// A 16-block volume without an MBR: the root directory in cluster 2,
+    /// `SUB` in 3, `HELLO.TXT` in 4, `BAD` pointing at cluster 1 and `LOOP`
+    /// chained 5, 6, 5, ...
+    fn image() -> Image {
+        let mut blocks = vec![[0; BLOCK_SIZE]; 16];
+
+        let boot = &mut blocks[0];
+        boot[0] = 0xEB;
+        boot[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+        boot[13] = 1;
+        boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+        boot[16] = 1;
+        boot[32..36].copy_from_slice(&16u32.to_le_bytes());
+        boot[36..40].copy_from_slice(&1u32.to_le_bytes());
+        boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+        boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        let fat = &mut blocks[FAT_BLOCK];
+        for (cluster, next) in [
+            (2, END_OF_CHAIN),
+            (3, END_OF_CHAIN),
+            (4, END_OF_CHAIN),
+            (5, 6),
+            (6, 5),
+        ] {
+            fat[cluster * 4..cluster * 4 + 4].copy_from_slice(&next.to_le_bytes());
+        }
+
+        let root = &mut blocks[DATA_BLOCK];
+        entry(root, 0, b"SUB        ", ATTR_DIRECTORY, 3, 0);
+        entry(root, 1, b"HELLO   TXT", 0, 4, 5);
+        entry(root, 2, b"BAD        ", ATTR_DIRECTORY, 1, 0);
+        entry(root, 3, b"LOOP       ", ATTR_DIRECTORY, 5, 0);
+        entry(root, 4, b"EMPTY      ", 0, 0, 0);
+
+        let sub = &mut blocks[DATA_BLOCK + 1];
+        entry(sub, 0, b".          ", ATTR_DIRECTORY, 3, 0);
+        entry(sub, 1, b"..         ", ATTR_DIRECTORY, 0, 0);
+
+        blocks[DATA_BLOCK + 2][..5].copy_from_slice(b"hello");
+
+        // The looping directory is full of deleted entries, so a lookup
+        // never reaches an end marker.
+        for cluster in [5, 6] {
+            for index in 0..BLOCK_SIZE / ENTRY_SIZE {
+                blocks[DATA_BLOCK + cluster - 2][index * ENTRY_SIZE] = ENTRY_DELETED;
+            }
+        }
+        Image(blocks)
+    }
+
+    #[test]
+    fn opens_and_reads_through_subdirectories() {
+        let mut fs = FileSystem::mount(image()).unwrap();
+        let mut file = fs.open("/sub/../hello.txt").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(fs.read(&mut file, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(fs.open("empty").map(|file| file.size()), Ok(0));
+        assert_eq!(fs.open("sub/missing").err(), Some(FatError::NotFound));
+    }
+
+    #[test]
+    fn entry_below_cluster_2_is_corrupt() {
+        let mut fs = FileSystem::mount(image()).unwrap();
+        assert_eq!(fs.open("bad").err(), Some(FatError::Corrupt));
+        assert_eq!(fs.open("bad/file").err(), Some(FatError::Corrupt));
+    }
+
+    #[test]
+    fn cyclic_directory_chain_is_corrupt() {
+        let mut fs = FileSystem::mount(image()).unwrap();
+        assert_eq!(fs.open("loop/file").err(), Some(FatError::Corrupt));
+    }
+}
@@ -0,0 +1,28 @@
+use core::arch::asm;
+use core::fmt::Write;
+
+use cortex_a::exceptions::exception_name;
+
+use crate::uart::UART;
+
+/// Entry point for every exception other than EL1 IRQs, called from the
+/// `cortex-a` vector table. Reports the exception on the Mini UART and halts.
+#[no_mangle]
+pub unsafe extern "C" fn handle_exception(kind: u64, esr: u64, far: u64, elr: u64) -> ! {
+    let mut uart = UART::uart1_unchecked();
+    let _ = write!(
+        uart,
+        "\nunhandled {} exception\nESR_EL1: {:#018x}\nFAR_EL1: {:#018x}\nELR_EL1: {:#018x}\n",
+        exception_name(kind),
+        esr,
+        far,
+        elr
+    );
+    if cortex_a::stack::is_guard_fault(far) {
+        let _ = write!(uart, "kernel stack overflow\n");
+    }
+    uart.flush();
+    loop {
+        asm!("wfe");
+    }
+}
@@ -0,0 +1,197 @@
+mod font;
+
+use core::fmt;
+
+use crate::mailbox::Mailbox;
+use crate::mailbox::MailboxError;
+use crate::mailbox::PropertyMessage;
+
+const ALLOCATE_BUFFER: u32 = 0x0004_0001;
+const GET_PITCH: u32 = 0x0004_0008;
+const SET_PHYSICAL_SIZE: u32 = 0x0004_8003;
+const SET_VIRTUAL_SIZE: u32 = 0x0004_8004;
+const SET_DEPTH: u32 = 0x0004_8005;
+const SET_PIXEL_ORDER: u32 = 0x0004_8006;
+
+/// Bits per pixel requested from the firmware.
+const DEPTH: u32 = 32;
+/// Pixel order value for RGB.
+const PIXEL_ORDER_RGB: u32 = 1;
+/// Alignment requested for the framebuffer allocation.
+const BUFFER_ALIGNMENT: u32 = 16;
+
+/// The VideoCore reports buffer addresses as bus addresses. The top two bits
+/// select a cache alias and must be dropped to get the ARM physical address.
+const BUS_ADDRESS_MASK: u32 = 0x3FFF_FFFF;
+
+/// A 32 bpp linear framebuffer allocated from the firmware over the mailbox.
+///
+/// Colors are `0x00RRGGBB`.
+pub struct Framebuffer {
+    base: *mut u32,
+    width: u32,
+    height: u32,
+    /// Bytes per row, which may be larger than `width * 4`.
+    pitch: u32,
+}
+
+impl Framebuffer {
+    pub fn new(mailbox: &Mailbox, width: u32, height: u32) -> Result<Framebuffer, MailboxError> {
+        let mut message = PropertyMessage::<36>::new();
+        message.add_tag(SET_PHYSICAL_SIZE, &[width, height], 2)?;
+        message.add_tag(SET_VIRTUAL_SIZE, &[width, height], 2)?;
+        let depth = message.add_tag(SET_DEPTH, &[DEPTH], 1)?;
+        message.add_tag(SET_PIXEL_ORDER, &[PIXEL_ORDER_RGB], 1)?;
+        let buffer = message.add_tag(ALLOCATE_BUFFER, &[BUFFER_ALIGNMENT], 2)?;
+        let pitch = message.add_tag(GET_PITCH, &[], 1)?;
+        mailbox.call_property(&mut message)?;
+
+        let base = message.response(buffer, 0)? & BUS_ADDRESS_MASK;
+        if base == 0 || message.response(depth, 0)? != DEPTH {
+            return Err(MailboxError::RequestFailed);
+        }
+        Ok(Framebuffer {
+            base: base as usize as *mut u32,
+            width,
+            height,
+            pitch: message.response(pitch, 0)?,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Sets the pixel at (`x`, `y`). Out-of-range coordinates are ignored.
+    ///
+    /// The buffer is mapped cacheable, so the write may sit in the data cache
+    /// where the VideoCore can't see it. Call `flush` after a batch of pixels.
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let offset = (y * (self.pitch / 4) + x) as usize;
+        // Safety: the offset is within the buffer allocated by the firmware.
+        unsafe { core::ptr::write_volatile(self.base.add(offset), color) };
+    }
+
+    pub fn fill(&mut self, color: u32) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.set_pixel(x, y, color);
+            }
+        }
+        self.flush();
+    }
+
+    /// Cleans the whole buffer out of the data cache so the display shows
+    /// what has been written.
+    pub fn flush(&self) {
+        self.flush_rows(0, self.height);
+    }
+
+    /// Cleans `count` rows starting at row `y` out of the data cache.
+    fn flush_rows(&self, y: u32, count: u32) {
+        if y >= self.height {
+            return;
+        }
+        let count = count.min(self.height - y);
+        let start = self.base as usize + (y * self.pitch) as usize;
+        let len = (count * self.pitch) as usize;
+        #[cfg(target_arch = "aarch64")]
+        cortex_a::cache::clean_range(start, len);
+        #[cfg(not(target_arch = "aarch64"))]
+        let _ = (start, len);
+    }
+
+    /// Draws `c` with its top-left corner at (`x`, `y`) and cleans its rows
+    /// from the data cache. Characters outside printable ASCII are drawn as
+    /// `?`.
+    pub fn draw_char(&mut self, x: u32, y: u32, c: u8, fg: u32, bg: u32) {
+        let glyph = font::GLYPHS
+            .get(c.wrapping_sub(font::FIRST) as usize)
+            .unwrap_or(&font::GLYPHS[(b'?' - font::FIRST) as usize]);
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..8 {
+                let color = if bits & (1 << column) != 0 { fg } else { bg };
+                self.set_pixel(x + column, y + row as u32, color);
+            }
+        }
+        self.flush_rows(y, glyph.len() as u32);
+    }
+}
+
+/// Width and height of a character cell of `TextConsole`.
+pub const CHAR_SIZE: u32 = 8;
+
+/// A text console on a `Framebuffer` using an 8x8 font. It wraps at the
+/// right edge and starts over at the top when the screen is full.
+pub struct TextConsole<'a> {
+    framebuffer: &'a mut Framebuffer,
+    column: u32,
+    row: u32,
+    fg: u32,
+    bg: u32,
+}
+
+impl<'a> TextConsole<'a> {
+    pub fn new(framebuffer: &'a mut Framebuffer, fg: u32, bg: u32) -> TextConsole<'a> {
+        framebuffer.fill(bg);
+        TextConsole {
+            framebuffer,
+            column: 0,
+            row: 0,
+            fg,
+            bg,
+        }
+    }
+
+    fn columns(&self) -> u32 {
+        self.framebuffer.width() / CHAR_SIZE
+    }
+
+    fn rows(&self) -> u32 {
+        self.framebuffer.height() / CHAR_SIZE
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+        self.row += 1;
+        if self.row >= self.rows() {
+            self.row = 0;
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.newline(),
+            b'\r' => self.column = 0,
+            _ => {
+                if self.column >= self.columns() {
+                    self.newline();
+                }
+                self.framebuffer.draw_char(
+                    self.column * CHAR_SIZE,
+                    self.row * CHAR_SIZE,
+                    byte,
+                    self.fg,
+                    self.bg,
+                );
+                self.column += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Write for TextConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
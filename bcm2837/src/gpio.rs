@@ -0,0 +1,479 @@
+use core::cell::Cell;
+use core::ops::Index;
+
+use crate::shared;
+use crate::soc::Soc;
+
+use kernel::hil;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::StaticRef;
+use tock_registers::fields::Field;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+use tock_registers::registers::WriteOnly;
+
+register_bitfields![u32,
+    GPFSEL [
+        FSEL0 OFFSET(0) NUMBITS(3) [],
+        FSEL1 OFFSET(3) NUMBITS(3) [],
+        FSEL2 OFFSET(6) NUMBITS(3) [],
+        FSEL3 OFFSET(9) NUMBITS(3) [],
+        FSEL4 OFFSET(12) NUMBITS(3) [],
+        FSEL5 OFFSET(15) NUMBITS(3) [],
+        FSEL6 OFFSET(18) NUMBITS(3) [],
+        FSEL7 OFFSET(21) NUMBITS(3) [],
+        FSEL8 OFFSET(24) NUMBITS(3) [],
+        FSEL9 OFFSET(27) NUMBITS(3) [],
+    ],
+    GPBANK [
+        Pin OFFSET(0) NUMBITS(32) [],
+    ],
+    GPPUD [
+        PUD OFFSET(0) NUMBITS(2) [
+            Off = 0,
+            PullDown = 1,
+            PullUp = 2,
+        ],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00-0x14: function select (GPFSEL0-5), ten pins per register
+    gpfsel: [ReadWrite<u32, GPFSEL::Register>; 6],
+    _reserved0: u32,
+
+    /// 0x1C: output set (GPSET0-1)
+    gpset: [WriteOnly<u32, GPBANK::Register>; 2],
+    _reserved1: u32,
+
+    /// 0x28: output clear (GPCLR0-1)
+    gpclr: [WriteOnly<u32, GPBANK::Register>; 2],
+    _reserved2: u32,
+
+    /// 0x34: pin level (GPLEV0-1)
+    gplev: [ReadOnly<u32, GPBANK::Register>; 2],
+    _reserved3: u32,
+
+    /// 0x40: event detect status (GPEDS0-1), write 1 to clear
+    gpeds: [ReadWrite<u32, GPBANK::Register>; 2],
+    _reserved4: u32,
+
+    /// 0x4C: rising edge detect enable (GPREN0-1)
+    gpren: [ReadWrite<u32, GPBANK::Register>; 2],
+    _reserved5: u32,
+
+    /// 0x58: falling edge detect enable (GPFEN0-1)
+    gpfen: [ReadWrite<u32, GPBANK::Register>; 2],
+    _reserved6: u32,
+
+    /// 0x64: high level detect enable (GPHEN0-1)
+    gphen: [ReadWrite<u32, GPBANK::Register>; 2],
+    _reserved7: u32,
+
+    /// 0x70: low level detect enable (GPLEN0-1)
+    gplen: [ReadWrite<u32, GPBANK::Register>; 2],
+    _reserved8: [u8; 0x94 - 0x78],
+
+    /// 0x94: pull-up/down enable (GPPUD)
+    gppud: ReadWrite<u32, GPPUD::Register>,
+
+    /// 0x98: pull-up/down clock (GPPUDCLK0-1)
+    gppudclk: [ReadWrite<u32, GPBANK::Register>; 2],
+}
+
+/// Number of GPIO pins on the BCM2837.
+pub const NUM_PINS: usize = 54;
+
+/// Pin function, encoded as the GPFSEL field value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Function {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
+/// Event that sets a pin's event detect status bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+    High,
+    Low,
+}
+
+/// Interrupt controller line shared by all GPIO events. The controller also
+/// has per-bank lines (49 and 50), but this one covers every pin, so the
+/// handler must use `pending_events` to find out which pin fired.
+pub const GPIO_IRQ: u32 = 52;
+
+/// Cycles to wait after writing GPPUD before clocking it into the pins.
+pub const PULL_SETUP_CYCLES: usize = 150;
+
+/// Cycles to hold GPPUDCLK before removing the control signal.
+pub const PULL_HOLD_CYCLES: usize = 150;
+
+fn spin(cycles: usize) {
+    for _ in 0..cycles {
+        core::hint::spin_loop();
+    }
+}
+
+/// GPFSEL register index and bit offset of the function field for `pin`.
+pub const fn fsel_location(pin: usize) -> (usize, usize) {
+    (pin / 10, (pin % 10) * 3)
+}
+
+/// GPSET/GPCLR/GPLEV-style bank index and bit for `pin`.
+const fn bank_location(pin: usize) -> (usize, u32) {
+    (pin / 32, 1 << (pin % 32))
+}
+
+pub struct Gpio(StaticRef<Registers>);
+
+impl Gpio {
+    pub unsafe fn new() -> Gpio {
+        Gpio::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Gpio {
+        Gpio(StaticRef::new(
+            (soc.peripheral_base + 0x200000) as *const Registers,
+        ))
+    }
+
+    pub fn function(&self, pin: usize) -> Function {
+        assert!(pin < NUM_PINS);
+        let (index, shift) = fsel_location(pin);
+        let field = Field::<u32, GPFSEL::Register>::new(0b111, shift);
+        match self.0.gpfsel[index].read(field) {
+            0b000 => Function::Input,
+            0b001 => Function::Output,
+            0b100 => Function::Alt0,
+            0b101 => Function::Alt1,
+            0b110 => Function::Alt2,
+            0b111 => Function::Alt3,
+            0b011 => Function::Alt4,
+            _ => Function::Alt5,
+        }
+    }
+
+    pub fn set_function(&self, pin: usize, function: Function) {
+        assert!(pin < NUM_PINS);
+        let (index, shift) = fsel_location(pin);
+        let field = Field::<u32, GPFSEL::Register>::new(0b111, shift);
+        shared::modify(&self.0.gpfsel[index], field.val(function as u32));
+    }
+
+    /// Configures the pull-up/down resistor on `pin`.
+    ///
+    /// The control signal must be set up for `PULL_SETUP_CYCLES` before it
+    /// is clocked into the pin, and the clock held for `PULL_HOLD_CYCLES`
+    /// before both are removed again.
+    pub fn set_pull(&self, pin: usize, pull: Pull) {
+        assert!(pin < NUM_PINS);
+        let (bank, bit) = bank_location(pin);
+        let pud = match pull {
+            Pull::None => GPPUD::PUD::Off,
+            Pull::Up => GPPUD::PUD::PullUp,
+            Pull::Down => GPPUD::PUD::PullDown,
+        };
+        self.0.gppud.write(pud);
+        spin(PULL_SETUP_CYCLES);
+        self.0.gppudclk[bank].set(bit);
+        spin(PULL_HOLD_CYCLES);
+        self.0.gppud.write(GPPUD::PUD::Off);
+        self.0.gppudclk[bank].set(0);
+    }
+
+    /// Enables event detection on `pin`. Events are reported through
+    /// `pending_events` and raise `GPIO_IRQ` once it is enabled on the
+    /// interrupt controller.
+    pub fn enable_interrupt(&self, pin: usize, edge: Edge) {
+        assert!(pin < NUM_PINS);
+        self.disable_interrupt(pin);
+        let (bank, bit) = bank_location(pin);
+        let enable = match edge {
+            Edge::Rising => [&self.0.gpren[bank], &self.0.gpren[bank]],
+            Edge::Falling => [&self.0.gpfen[bank], &self.0.gpfen[bank]],
+            Edge::Both => [&self.0.gpren[bank], &self.0.gpfen[bank]],
+            Edge::High => [&self.0.gphen[bank], &self.0.gphen[bank]],
+            Edge::Low => [&self.0.gplen[bank], &self.0.gplen[bank]],
+        };
+        shared::without_interrupts(|| {
+            for register in enable {
+                register.set(register.get() | bit);
+            }
+        });
+    }
+
+    /// Disables all event detection on `pin` and clears any pending event.
+    pub fn disable_interrupt(&self, pin: usize) {
+        assert!(pin < NUM_PINS);
+        let (bank, bit) = bank_location(pin);
+        shared::without_interrupts(|| {
+            for enable in [
+                &self.0.gpren[bank],
+                &self.0.gpfen[bank],
+                &self.0.gphen[bank],
+                &self.0.gplen[bank],
+            ] {
+                enable.set(enable.get() & !bit);
+            }
+        });
+        self.clear_event(pin);
+    }
+
+    pub fn clear_event(&self, pin: usize) {
+        assert!(pin < NUM_PINS);
+        let (bank, bit) = bank_location(pin);
+        // GPEDS is write-1-to-clear; writing zeros leaves other pins alone.
+        self.0.gpeds[bank].set(bit);
+    }
+
+    /// Pins with a detected event, bit `n` for GPIO `n`.
+    pub fn pending_events(&self) -> u64 {
+        (self.0.gpeds[1].get() as u64) << 32 | self.0.gpeds[0].get() as u64
+    }
+
+    pub fn set_high(&self, pin: usize) {
+        assert!(pin < NUM_PINS);
+        let (bank, bit) = bank_location(pin);
+        self.0.gpset[bank].set(bit);
+    }
+
+    pub fn set_low(&self, pin: usize) {
+        assert!(pin < NUM_PINS);
+        let (bank, bit) = bank_location(pin);
+        self.0.gpclr[bank].set(bit);
+    }
+
+    pub fn read(&self, pin: usize) -> bool {
+        assert!(pin < NUM_PINS);
+        let (bank, bit) = bank_location(pin);
+        self.0.gplev[bank].get() & bit != 0
+    }
+}
+
+/// A single GPIO as a Tock `hil::gpio` pin.
+pub struct GpioPin<'a> {
+    gpio: Gpio,
+    pin: usize,
+    /// The pulls cannot be read back, so the last one set is kept here.
+    pull: Cell<hil::gpio::FloatingState>,
+    client: OptionalCell<&'a dyn hil::gpio::Client>,
+}
+
+impl<'a> GpioPin<'a> {
+    /// # Safety
+    ///
+    /// No other driver may use `pin`.
+    pub unsafe fn new(pin: usize) -> GpioPin<'a> {
+        assert!(pin < NUM_PINS);
+        GpioPin {
+            gpio: Gpio::new(),
+            pin,
+            pull: Cell::new(hil::gpio::FloatingState::PullNone),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Clears the pin's event and calls its client.
+    fn handle_interrupt(&self) {
+        self.gpio.clear_event(self.pin);
+        self.client.map(|client| client.fired());
+    }
+}
+
+impl hil::gpio::Configure for GpioPin<'_> {
+    fn configuration(&self) -> hil::gpio::Configuration {
+        match self.gpio.function(self.pin) {
+            Function::Input => hil::gpio::Configuration::Input,
+            // GPLEV reads back the level of output pins as well.
+            Function::Output => hil::gpio::Configuration::InputOutput,
+            _ => hil::gpio::Configuration::Function,
+        }
+    }
+
+    fn make_output(&self) -> hil::gpio::Configuration {
+        self.gpio.set_function(self.pin, Function::Output);
+        self.configuration()
+    }
+
+    fn disable_output(&self) -> hil::gpio::Configuration {
+        self.gpio.set_function(self.pin, Function::Input);
+        self.configuration()
+    }
+
+    fn make_input(&self) -> hil::gpio::Configuration {
+        // Inputs are always enabled, on output pins as well.
+        if self.gpio.function(self.pin) != Function::Output {
+            self.gpio.set_function(self.pin, Function::Input);
+        }
+        self.configuration()
+    }
+
+    /// The input buffer cannot be turned off, so this changes nothing.
+    fn disable_input(&self) -> hil::gpio::Configuration {
+        self.configuration()
+    }
+
+    /// Returns the pin to its reset state: an input without pulls or event
+    /// detection.
+    fn deactivate_to_low_power(&self) {
+        self.gpio.disable_interrupt(self.pin);
+        self.gpio.set_function(self.pin, Function::Input);
+        self.set_floating_state(hil::gpio::FloatingState::PullNone);
+    }
+
+    fn set_floating_state(&self, state: hil::gpio::FloatingState) {
+        let pull = match state {
+            hil::gpio::FloatingState::PullUp => Pull::Up,
+            hil::gpio::FloatingState::PullDown => Pull::Down,
+            hil::gpio::FloatingState::PullNone => Pull::None,
+        };
+        self.gpio.set_pull(self.pin, pull);
+        self.pull.set(state);
+    }
+
+    /// The state last set with `set_floating_state`. Before that, the pin
+    /// has its reset pull, which depends on the pin and is reported as
+    /// `PullNone`.
+    fn floating_state(&self) -> hil::gpio::FloatingState {
+        self.pull.get()
+    }
+}
+
+impl hil::gpio::Output for GpioPin<'_> {
+    fn set(&self) {
+        self.gpio.set_high(self.pin);
+    }
+
+    fn clear(&self) {
+        self.gpio.set_low(self.pin);
+    }
+
+    fn toggle(&self) -> bool {
+        let level = !self.gpio.read(self.pin);
+        if level {
+            self.set();
+        } else {
+            self.clear();
+        }
+        level
+    }
+}
+
+impl hil::gpio::Input for GpioPin<'_> {
+    fn read(&self) -> bool {
+        self.gpio.read(self.pin)
+    }
+}
+
+impl<'a> hil::gpio::Interrupt<'a> for GpioPin<'a> {
+    fn set_client(&self, client: &'a dyn hil::gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: hil::gpio::InterruptEdge) {
+        let edge = match mode {
+            hil::gpio::InterruptEdge::RisingEdge => Edge::Rising,
+            hil::gpio::InterruptEdge::FallingEdge => Edge::Falling,
+            hil::gpio::InterruptEdge::EitherEdge => Edge::Both,
+        };
+        self.gpio.enable_interrupt(self.pin, edge);
+    }
+
+    fn disable_interrupts(&self) {
+        self.gpio.disable_interrupt(self.pin);
+    }
+
+    fn is_pending(&self) -> bool {
+        self.gpio.pending_events() & (1 << self.pin) != 0
+    }
+}
+
+/// Every GPIO as a `GpioPin`, indexed by pin number, with the handler for
+/// `GPIO_IRQ`.
+pub struct GpioPort<'a> {
+    pins: [GpioPin<'a>; NUM_PINS],
+    gpio: Gpio,
+}
+
+impl<'a> GpioPort<'a> {
+    /// # Safety
+    ///
+    /// Pins used by other drivers must not be used through the port.
+    pub unsafe fn new() -> GpioPort<'a> {
+        GpioPort {
+            pins: core::array::from_fn(|pin| GpioPin::new(pin)),
+            gpio: Gpio::new(),
+        }
+    }
+
+    /// Dispatches every pending event to its pin's client.
+    pub fn handle_interrupt(&self) {
+        let mut pending = self.gpio.pending_events();
+        while pending != 0 {
+            let pin = pending.trailing_zeros() as usize;
+            pending &= pending - 1;
+            self.pins[pin].handle_interrupt();
+        }
+    }
+}
+
+impl<'a> Index<usize> for GpioPort<'a> {
+    type Output = GpioPin<'a>;
+
+    fn index(&self, pin: usize) -> &GpioPin<'a> {
+        &self.pins[pin]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset of the GPIO block in the peripheral window.
+    const GPIO: usize = 0x200000;
+
+    #[test]
+    fn pin_47_is_in_gpfsel4_at_bit_21() {
+        assert_eq!(fsel_location(47), (4, 21));
+        assert_eq!(fsel_location(0), (0, 0));
+        assert_eq!(fsel_location(53), (5, 9));
+    }
+
+    #[test]
+    fn set_function_only_changes_its_field() {
+        let soc = Soc::in_memory();
+        let gpio = unsafe { Gpio::for_soc(&soc) };
+        let gpfsel4 = GPIO + 4 * 4;
+        soc.write(gpfsel4, 0xFFFF_FFFF);
+
+        gpio.set_function(47, Function::Output);
+        assert_eq!(soc.read(gpfsel4), !(0b111 << 21) | 0b001 << 21);
+        assert_eq!(gpio.function(47), Function::Output);
+
+        gpio.set_function(47, Function::Input);
+        assert_eq!(soc.read(gpfsel4), !(0b111 << 21));
+    }
+}
@@ -0,0 +1,140 @@
+//! Bump allocator for `alloc`, covering the RAM above the kernel image.
+//!
+//! The heap starts at `__heap_start`, which the linker script puts above
+//! everything it places, and ends at the top of the ARM memory the firmware
+//! reports through the mailbox, so the RAM size is not hard-coded. Until
+//! `init_heap` has run every allocation fails.
+//!
+//! Memory is only given back when the most recent allocation is freed, which
+//! suits allocations made once during setup.
+
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::ptr::addr_of;
+use core::ptr::null_mut;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::mailbox::Mailbox;
+use crate::mailbox::MailboxError;
+
+extern "C" {
+    static __heap_start: u8;
+}
+
+pub struct BumpHeap {
+    /// First free byte.
+    next: AtomicUsize,
+    /// End of the heap, 0 until `init_heap`.
+    end: AtomicUsize,
+}
+
+#[cfg_attr(not(test), global_allocator)]
+static HEAP: BumpHeap = BumpHeap {
+    next: AtomicUsize::new(0),
+    end: AtomicUsize::new(0),
+};
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the heap and returns the number of free bytes in it. Calls
+/// after the first that succeeded change nothing.
+///
+/// The mailbox must be usable, i.e. the MMU on, as it asks the firmware for
+/// the ARM memory size.
+pub fn init_heap() -> Result<usize, MailboxError> {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        // Safety: only a property query is made.
+        let (base, size) = unsafe { Mailbox::new() }.get_arm_memory()?;
+        let start = unsafe { addr_of!(__heap_start) } as usize;
+        let end = (base as usize + size as usize).max(start);
+        if !INITIALIZED.swap(true, Ordering::AcqRel) {
+            HEAP.next.store(start, Ordering::Relaxed);
+            HEAP.end.store(end, Ordering::Release);
+        }
+    }
+    Ok(HEAP.end.load(Ordering::Acquire) - HEAP.next.load(Ordering::Relaxed))
+}
+
+unsafe impl GlobalAlloc for BumpHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let end = self.end.load(Ordering::Acquire);
+        let mut next = self.next.load(Ordering::Relaxed);
+        loop {
+            let start = match next.checked_add(layout.align() - 1) {
+                Some(address) => address & !(layout.align() - 1),
+                None => return null_mut(),
+            };
+            let new_next = match start.checked_add(layout.size()) {
+                Some(address) if address <= end => address,
+                _ => return null_mut(),
+            };
+            match self.next.compare_exchange_weak(
+                next,
+                new_next,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return start as *mut u8,
+                Err(current) => next = current,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Only the last allocation can be taken back; anything else leaks.
+        let _ = self.next.compare_exchange(
+            ptr as usize + layout.size(),
+            ptr as usize,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A heap over `memory` instead of the RAM above the kernel.
+    fn heap_over(memory: &mut [u8]) -> BumpHeap {
+        let start = memory.as_mut_ptr() as usize;
+        BumpHeap {
+            next: AtomicUsize::new(start),
+            end: AtomicUsize::new(start + memory.len()),
+        }
+    }
+
+    #[test]
+    fn alloc_aligns_and_fails_when_full() {
+        let mut memory = [0u64; 8];
+        let start = memory.as_ptr() as usize;
+        let heap = heap_over(unsafe { memory.align_to_mut::<u8>().1 });
+
+        let byte = unsafe { heap.alloc(Layout::from_size_align(1, 1).unwrap()) };
+        let word = unsafe { heap.alloc(Layout::from_size_align(16, 8).unwrap()) };
+        assert_eq!(byte as usize, start);
+        assert_eq!(word as usize, start + 8);
+
+        let rest = Layout::from_size_align(40, 1).unwrap();
+        assert_eq!(unsafe { heap.alloc(rest) } as usize, start + 24);
+        assert!(unsafe { heap.alloc(Layout::new::<u8>()) }.is_null());
+    }
+
+    #[test]
+    fn free_only_takes_back_the_last_allocation() {
+        let mut memory = [0u8; 32];
+        let heap = heap_over(&mut memory);
+        let layout = Layout::from_size_align(8, 1).unwrap();
+
+        let first = unsafe { heap.alloc(layout) };
+        let second = unsafe { heap.alloc(layout) };
+        unsafe { heap.dealloc(first, layout) };
+        assert_eq!(unsafe { heap.alloc(layout) } as usize, second as usize + 8);
+
+        let last = unsafe { heap.alloc(layout) };
+        unsafe { heap.dealloc(last, layout) };
+        assert_eq!(unsafe { heap.alloc(layout) }, last);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::soc::Soc;
+
+use kernel::utilities::StaticRef;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    C [
+        Read OFFSET(0) NUMBITS(1) [],
+        Clear OFFSET(4) NUMBITS(2) [
+            Fifo = 1,
+        ],
+        Start OFFSET(7) NUMBITS(1) [],
+        InterruptOnDone OFFSET(8) NUMBITS(1) [],
+        InterruptOnTx OFFSET(9) NUMBITS(1) [],
+        InterruptOnRx OFFSET(10) NUMBITS(1) [],
+        Enable OFFSET(15) NUMBITS(1) [],
+    ],
+    S [
+        TransferActive OFFSET(0) NUMBITS(1) [],
+        Done OFFSET(1) NUMBITS(1) [],
+        TxWanted OFFSET(2) NUMBITS(1) [],
+        RxNeedsReading OFFSET(3) NUMBITS(1) [],
+        TxData OFFSET(4) NUMBITS(1) [],
+        RxData OFFSET(5) NUMBITS(1) [],
+        TxEmpty OFFSET(6) NUMBITS(1) [],
+        RxFull OFFSET(7) NUMBITS(1) [],
+        Err OFFSET(8) NUMBITS(1) [],
+        ClockTimeout OFFSET(9) NUMBITS(1) [],
+    ],
+    DLEN [
+        Length OFFSET(0) NUMBITS(16) [],
+    ],
+    A [
+        Address OFFSET(0) NUMBITS(7) [],
+    ],
+    DIV [
+        Divider OFFSET(0) NUMBITS(16) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: control (C)
+    c: ReadWrite<u32, C::Register>,
+
+    /// 0x04: status (S)
+    s: ReadWrite<u32, S::Register>,
+
+    /// 0x08: data length (DLEN)
+    dlen: ReadWrite<u32, DLEN::Register>,
+
+    /// 0x0C: slave address (A)
+    a: ReadWrite<u32, A::Register>,
+
+    /// 0x10: data FIFO (FIFO)
+    fifo: ReadWrite<u32>,
+
+    /// 0x14: clock divider (DIV)
+    div: ReadWrite<u32, DIV::Register>,
+
+    /// 0x18: data delay (DEL)
+    _del: ReadWrite<u32>,
+
+    /// 0x1C: clock stretch timeout (CLKT)
+    _clkt: ReadWrite<u32>,
+}
+
+/// BSC1 signals on the header, both alt0: SDA and SCL.
+const PINS: [usize; 2] = [2, 3];
+
+/// Depth of the BSC FIFO in bytes.
+pub const FIFO_DEPTH: usize = 16;
+
+/// Interrupt controller line shared by the BSC controllers. The driver is
+/// polled and leaves it disabled.
+pub const I2C_IRQ: u32 = 53;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cError {
+    /// The slave did not acknowledge its address or a data byte (ERR).
+    Nack,
+    /// The slave held SCL low for longer than the clock stretch timeout
+    /// (CLKT).
+    ClockStretchTimeout,
+    /// The transfer is longer than DLEN can express, or than the FIFO can
+    /// hold for the write half of `write_read`.
+    Size,
+}
+
+/// Polled I2C master on BSC1 (offset 0x804000 of the peripheral window).
+pub struct I2c {
+    registers: StaticRef<Registers>,
+    soc: Soc,
+}
+
+impl I2c {
+    pub unsafe fn new() -> I2c {
+        I2c::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> I2c {
+        I2c {
+            registers: StaticRef::new((soc.peripheral_base + 0x804000) as *const Registers),
+            soc: *soc,
+        }
+    }
+
+    /// Routes BSC1 to GPIO2/3 and enables the controller.
+    pub fn init(&mut self) {
+        // Safety: GPIO2/3 belong to BSC1.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in PINS {
+            gpio.set_function(pin, Function::Alt0);
+        }
+        self.registers.c.write(C::Enable::SET + C::Clear::Fifo);
+    }
+
+    /// Sets SCL to the core clock divided by `divider`, which is rounded
+    /// down to an even value; 0 means 32768.
+    pub fn set_clock_divider(&mut self, divider: u16) {
+        self.registers
+            .div
+            .write(DIV::Divider.val(divider as u32 & !1));
+    }
+
+    /// Sends `data` to the slave at `addr`.
+    pub fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.start(addr, data.len(), false)?;
+        let mut index = 0;
+        while !self.registers.s.is_set(S::Done) {
+            while index < data.len() && self.registers.s.is_set(S::TxData) {
+                self.registers.fifo.set(data[index] as u32);
+                index += 1;
+            }
+            self.check_errors()?;
+        }
+        self.finish()
+    }
+
+    /// Fills `buffer` with bytes read from the slave at `addr`.
+    pub fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.start(addr, buffer.len(), true)?;
+        self.receive(buffer)
+    }
+
+    /// Sends `data` to the slave at `addr` and reads `buffer` back after a
+    /// repeated start, as used to read a register of the slave.
+    pub fn write_read(&mut self, addr: u8, data: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+        if data.len() > FIFO_DEPTH {
+            return Err(I2cError::Size);
+        }
+        self.start(addr, data.len(), false)?;
+        for byte in data {
+            self.registers.fifo.set(*byte as u32);
+        }
+
+        // Once the write is under way, queuing a read makes the controller
+        // issue a repeated start instead of a stop when DLEN runs out.
+        while !self.registers.s.is_set(S::TransferActive) && !self.registers.s.is_set(S::Done) {
+            self.check_errors()?;
+        }
+        self.registers
+            .dlen
+            .write(DLEN::Length.val(buffer.len() as u32));
+        self.registers.c.modify(C::Read::SET + C::Start::SET);
+        self.receive(buffer)
+    }
+
+    fn start(&mut self, addr: u8, len: usize, read: bool) -> Result<(), I2cError> {
+        if len > DLEN::Length.mask as usize {
+            return Err(I2cError::Size);
+        }
+        self.registers.c.modify(C::Clear::Fifo);
+        self.registers
+            .s
+            .write(S::Done::SET + S::Err::SET + S::ClockTimeout::SET);
+        self.registers.a.write(A::Address.val(addr as u32));
+        self.registers.dlen.write(DLEN::Length.val(len as u32));
+        self.registers
+            .c
+            .modify(C::Read.val(read as u32) + C::Start::SET);
+        Ok(())
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<(), I2cError> {
+        let mut index = 0;
+        loop {
+            while index < buffer.len() && self.registers.s.is_set(S::RxData) {
+                buffer[index] = self.registers.fifo.get() as u8;
+                index += 1;
+            }
+            if self.registers.s.is_set(S::Done) && !self.registers.s.is_set(S::RxData) {
+                break;
+            }
+            self.check_errors()?;
+        }
+        self.finish()
+    }
+
+    /// Checks the error bits, clearing them and the FIFO on an error.
+    fn check_errors(&mut self) -> Result<(), I2cError> {
+        let status = self.registers.s.extract();
+        let error = if status.is_set(S::Err) {
+            I2cError::Nack
+        } else if status.is_set(S::ClockTimeout) {
+            I2cError::ClockStretchTimeout
+        } else {
+            return Ok(());
+        };
+        self.registers
+            .s
+            .write(S::Done::SET + S::Err::SET + S::ClockTimeout::SET);
+        self.registers.c.modify(C::Clear::Fifo);
+        Err(error)
+    }
+
+    fn finish(&mut self) -> Result<(), I2cError> {
+        self.check_errors()?;
+        self.registers.s.write(S::Done::SET);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C_OFFSET: usize = 0x804000;
+    const S_OFFSET: usize = 0x804004;
+    const DLEN_OFFSET: usize = 0x804008;
+    const A_OFFSET: usize = 0x80400C;
+
+    #[test]
+    fn start_writes_address_and_length() {
+        let soc = Soc::in_memory();
+        let mut i2c = unsafe { I2c::for_soc(&soc) };
+        soc.write(C_OFFSET, 1 << 15);
+
+        assert_eq!(i2c.start(0x48, 3, true), Ok(()));
+        assert_eq!(soc.read(A_OFFSET), 0x48);
+        assert_eq!(soc.read(DLEN_OFFSET), 3);
+        // Enabled, FIFO cleared, a read started.
+        assert_eq!(soc.read(C_OFFSET), 1 << 15 | 1 << 4 | 1 << 7 | 1);
+        // Done, ERR and CLKT are write-1-to-clear.
+        assert_eq!(soc.read(S_OFFSET), 1 << 1 | 1 << 8 | 1 << 9);
+
+        assert_eq!(i2c.start(0x50, 0xFFFF, false), Ok(()));
+        assert_eq!(soc.read(A_OFFSET), 0x50);
+        assert_eq!(soc.read(DLEN_OFFSET), 0xFFFF);
+        assert_eq!(soc.read(C_OFFSET) & 1, 0);
+    }
+
+    #[test]
+    fn start_rejects_lengths_dlen_cannot_hold() {
+        let soc = Soc::in_memory();
+        let mut i2c = unsafe { I2c::for_soc(&soc) };
+        assert_eq!(i2c.start(0x48, 0x1_0000, false), Err(I2cError::Size));
+        assert_eq!(soc.read(A_OFFSET), 0);
+        assert_eq!(soc.read(DLEN_OFFSET), 0);
+    }
+
+    #[test]
+    fn init_muxes_pins_in_its_own_window() {
+        const GPFSEL0: usize = 0x200000;
+        let soc = Soc::in_memory();
+        let mut i2c = unsafe { I2c::for_soc(&soc) };
+        i2c.init();
+
+        let alt0 = Function::Alt0 as u32;
+        assert_eq!(soc.read(GPFSEL0), alt0 << 6 | alt0 << 9);
+        assert_eq!(soc.read(C_OFFSET), 1 << 15 | 1 << 4);
+    }
+}
@@ -0,0 +1,250 @@
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+use crate::soc::Soc;
+
+use kernel::deferred_call::DeferredCall;
+use kernel::utilities::StaticRef;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+use tock_registers::registers::WriteOnly;
+
+register_bitfields![u32,
+    BASIC [
+        /// ARM peripheral interrupts 64-71 (ARM timer, mailbox, doorbells,
+        /// GPU halted, illegal access).
+        Arm OFFSET(0) NUMBITS(8) [],
+        /// A non-shortcut interrupt is pending in IRQ pending 1.
+        Pending1 OFFSET(8) NUMBITS(1) [],
+        /// A non-shortcut interrupt is pending in IRQ pending 2.
+        Pending2 OFFSET(9) NUMBITS(1) [],
+        /// Copies of selected GPU interrupts, see `SHORTCUTS`.
+        Shortcuts OFFSET(10) NUMBITS(11) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x200: IRQ basic pending
+    basic_pending: ReadOnly<u32, BASIC::Register>,
+
+    /// 0x204: IRQ pending 1 (interrupts 0-31)
+    pending1: ReadOnly<u32>,
+
+    /// 0x208: IRQ pending 2 (interrupts 32-63)
+    pending2: ReadOnly<u32>,
+
+    /// 0x20C: FIQ control
+    _fiq_control: ReadWrite<u32>,
+
+    /// 0x210: enable IRQs 1
+    enable1: WriteOnly<u32>,
+
+    /// 0x214: enable IRQs 2
+    enable2: WriteOnly<u32>,
+
+    /// 0x218: enable basic IRQs
+    enable_basic: WriteOnly<u32>,
+
+    /// 0x21C: disable IRQs 1
+    disable1: WriteOnly<u32>,
+
+    /// 0x220: disable IRQs 2
+    disable2: WriteOnly<u32>,
+
+    /// 0x224: disable basic IRQs
+    disable_basic: WriteOnly<u32>,
+}
+
+/// Interrupt numbers of the basic pending shortcut bits 10-20. These
+/// interrupts do not set `Pending1`/`Pending2`.
+const SHORTCUTS: [u32; 11] = [7, 9, 10, 18, 19, 53, 54, 55, 56, 57, 62];
+
+/// First of the ARM peripheral interrupts, which follow the 64 GPU ones.
+pub const ARM_IRQ_BASE: u32 = 64;
+
+/// Number of interrupt lines handled by the controller.
+pub const NUM_IRQS: u32 = 72;
+
+/// Lines taken by `handle_irq` that have not been serviced yet, one bit per
+/// interrupt number.
+///
+/// Only plain loads and stores are used: exclusives are not usable before
+/// the MMU is on, and `handle_irq` runs with IRQs masked, so the kernel side
+/// just has to mask IRQs while it clears bits.
+static TAKEN: [AtomicU32; 3] = [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)];
+
+/// IRQ entry point, called from the `cortex-a` vector table.
+///
+/// Each pending line is masked and recorded so that the kernel loop can run
+/// its driver outside of the exception context and then `complete` it.
+#[no_mangle]
+pub unsafe extern "C" fn handle_irq() {
+    let controller = Interrupt::new();
+    while let Some(irq) = controller.next_pending() {
+        controller.disable(irq);
+        let word = &TAKEN[(irq / 32) as usize];
+        word.store(
+            word.load(Ordering::Relaxed) | 1 << (irq % 32),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// The legacy BCM2835-style interrupt controller.
+///
+/// Interrupts are numbered 0-31 for IRQ bank 1, 32-63 for IRQ bank 2 and
+/// `ARM_IRQ_BASE`-71 for the basic ARM interrupts.
+pub struct Interrupt(StaticRef<Registers>);
+
+impl Interrupt {
+    pub unsafe fn new() -> Interrupt {
+        Interrupt::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Interrupt {
+        Interrupt(StaticRef::new(
+            (soc.peripheral_base + 0xB200) as *const Registers,
+        ))
+    }
+
+    pub fn enable(&self, irq: u32) {
+        match irq {
+            0..=31 => self.0.enable1.set(1 << irq),
+            32..=63 => self.0.enable2.set(1 << (irq - 32)),
+            64..=71 => self.0.enable_basic.set(1 << (irq - ARM_IRQ_BASE)),
+            _ => {}
+        }
+    }
+
+    pub fn disable(&self, irq: u32) {
+        match irq {
+            0..=31 => self.0.disable1.set(1 << irq),
+            32..=63 => self.0.disable2.set(1 << (irq - 32)),
+            64..=71 => self.0.disable_basic.set(1 << (irq - ARM_IRQ_BASE)),
+            _ => {}
+        }
+    }
+
+    /// Lowest-numbered pending interrupt, if any.
+    pub fn next_pending(&self) -> Option<u32> {
+        let basic = self.0.basic_pending.extract();
+
+        let arm = basic.read(BASIC::Arm);
+        if arm != 0 {
+            return Some(ARM_IRQ_BASE + arm.trailing_zeros());
+        }
+
+        // Shortcut interrupts are only reported here, so they have to be
+        // decoded before relying on the bank summary bits.
+        let shortcuts = basic.read(BASIC::Shortcuts);
+        if shortcuts != 0 {
+            return Some(SHORTCUTS[shortcuts.trailing_zeros() as usize]);
+        }
+
+        if basic.is_set(BASIC::Pending1) {
+            let pending = self.0.pending1.get();
+            if pending != 0 {
+                return Some(pending.trailing_zeros());
+            }
+        }
+        if basic.is_set(BASIC::Pending2) {
+            let pending = self.0.pending2.get();
+            if pending != 0 {
+                return Some(32 + pending.trailing_zeros());
+            }
+        }
+        None
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.0.basic_pending.get() != 0
+    }
+
+    /// Lowest-numbered line taken by `handle_irq` and not yet completed.
+    pub fn next_taken(&self) -> Option<u32> {
+        TAKEN.iter().enumerate().find_map(|(index, word)| {
+            let bits = word.load(Ordering::Relaxed);
+            if bits != 0 {
+                Some(index as u32 * 32 + bits.trailing_zeros())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Marks a line returned by `next_taken` as serviced and unmasks it.
+    ///
+    /// Must be called with IRQs masked.
+    pub unsafe fn complete(&self, irq: u32) {
+        let word = &TAKEN[(irq / 32) as usize];
+        word.store(
+            word.load(Ordering::Relaxed) & !(1 << (irq % 32)),
+            Ordering::Relaxed,
+        );
+        self.enable(irq);
+    }
+}
+
+/// The kernel side of interrupt handling: runs `service` on every line
+/// `next_taken` returns, and pending deferred calls once no line is left.
+pub fn service_pending(mut next_taken: impl FnMut() -> Option<u32>, mut service: impl FnMut(u32)) {
+    loop {
+        if let Some(irq) = next_taken() {
+            service(irq);
+        } else if DeferredCall::has_tasks() {
+            DeferredCall::service_next_pending();
+        } else {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::deferred_call::DeferredCallClient;
+
+    struct Client {
+        deferred_call: DeferredCall,
+        /// The lines serviced, with `None` for the deferred call.
+        log: RefCell<Vec<Option<u32>>>,
+    }
+
+    impl DeferredCallClient for Client {
+        fn handle_deferred_call(&self) {
+            self.log.borrow_mut().push(None);
+        }
+
+        fn register(&'static self) {
+            self.deferred_call.register(self);
+        }
+    }
+
+    #[test]
+    fn deferred_call_runs_after_the_taken_lines() {
+        let client: &'static Client = Box::leak(Box::new(Client {
+            deferred_call: DeferredCall::new(),
+            log: RefCell::new(Vec::new()),
+        }));
+        client.register();
+        let taken = RefCell::new(vec![9, 57]);
+
+        service_pending(
+            || taken.borrow_mut().pop(),
+            |irq| {
+                // Drivers finish their work from a deferred call.
+                client.deferred_call.set();
+                client.log.borrow_mut().push(Some(irq));
+            },
+        );
+
+        assert_eq!(*client.log.borrow(), [Some(57), Some(9), None]);
+        assert!(!client.deferred_call.is_pending());
+    }
+}
@@ -0,0 +1,97 @@
+//! Interrupt latency of the System Timer, with the `profiling` feature.
+//!
+//! `SystemTimer::set_compare_raw` notes when its compare is due, in generic
+//! timer ticks, and `SystemTimer::handle_interrupt` notes when it got to
+//! the match. The difference covers the whole path from the line going high
+//! through the vector table, `handle_irq`, the chip's dispatch and any
+//! deferred servicing in between. The deadline is derived from CLO, so it
+//! is only accurate to the System Timer's 1 us.
+//!
+//! The last `NUM_SAMPLES` pairs are kept; the shell's `latency` command
+//! prints them.
+
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use cortex_a::generic_timer;
+
+use crate::time;
+use crate::time::GenericTimerFrequency;
+
+/// Samples kept.
+pub const NUM_SAMPLES: usize = 32;
+
+/// One compare, in generic timer ticks.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// When the compare was due.
+    pub deadline: u64,
+    /// When `handle_interrupt` saw the match.
+    pub actual: u64,
+}
+
+impl Sample {
+    /// Nanoseconds from the deadline to the handler, 0 if the deadline
+    /// computed from CLO came out late.
+    pub fn latency_ns(&self) -> u64 {
+        time::ticks_to_us::<GenericTimerFrequency>(self.actual.saturating_sub(self.deadline) * 1000)
+    }
+}
+
+/// Pending deadline of each channel, 0 if none.
+static DEADLINES: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+static SAMPLES: [[AtomicU64; 2]; NUM_SAMPLES] =
+    [const { [AtomicU64::new(0), AtomicU64::new(0)] }; NUM_SAMPLES];
+/// Number of samples recorded so far; the next goes to `NEXT % NUM_SAMPLES`.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Notes that the compare of `channel` fires `us_from_now` microseconds
+/// from now, a `wrapping_sub` of CLO from the compare value. A compare set
+/// in the past matches only after CLO wraps, but alarms set one to fire
+/// right away, so it counts as due now.
+pub(crate) fn set_deadline(channel: usize, us_from_now: u32) {
+    let us_from_now = if us_from_now > i32::MAX as u32 {
+        0
+    } else {
+        us_from_now
+    };
+    let deadline =
+        generic_timer::ticks() + time::us_to_ticks::<GenericTimerFrequency>(us_from_now as u64);
+    DEADLINES[channel].store(deadline, Ordering::Relaxed);
+}
+
+/// Records a sample for the match on `channel`, if a deadline was noted.
+pub(crate) fn matched(channel: usize) {
+    let actual = generic_timer::ticks();
+    let deadline = DEADLINES[channel].swap(0, Ordering::Relaxed);
+    if deadline == 0 {
+        return;
+    }
+    let slot = &SAMPLES[NEXT.fetch_add(1, Ordering::Relaxed) % NUM_SAMPLES];
+    slot[0].store(deadline, Ordering::Relaxed);
+    slot[1].store(actual, Ordering::Relaxed);
+}
+
+/// Calls `f` with the kept samples, oldest first.
+pub fn for_each_sample(mut f: impl FnMut(Sample)) {
+    let next = NEXT.load(Ordering::Relaxed);
+    for index in next.saturating_sub(NUM_SAMPLES)..next {
+        let slot = &SAMPLES[index % NUM_SAMPLES];
+        f(Sample {
+            deadline: slot[0].load(Ordering::Relaxed),
+            actual: slot[1].load(Ordering::Relaxed),
+        });
+    }
+}
+
+/// Drops the kept samples.
+pub fn clear() {
+    NEXT.store(0, Ordering::Relaxed);
+}
@@ -0,0 +1,145 @@
+//! The onboard activity (ACT) LED as a Tock `hil::led::Led`.
+//!
+//! Where the LED sits depends on the board, which `ActivityLed::detect`
+//! finds from the revision code:
+//!
+//! | Board                        | ACT LED                    |
+//! |------------------------------|----------------------------|
+//! | Pi 2 Model B                 | GPIO47, active high        |
+//! | Pi 3 Model B                 | expander pin 130, mailbox  |
+//! | Pi 3 Model B+, 3 Model A+    | GPIO29, active high        |
+//! | Pi Zero 2 W                  | GPIO29, active low         |
+//! | Pi 4 Model B                 | GPIO42, active high        |
+//!
+//! The Pi 3 Model B's LED is on the firmware's GPIO expander and is driven
+//! with a mailbox call. The Pi 4's is only driven when `Soc::detect` finds
+//! the BCM2711, so that GPIO42 is set in the right peripheral window. On
+//! boards not in the table, the LED calls do nothing and `read` returns
+//! `false`.
+
+use core::cell::Cell;
+
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::mailbox;
+use crate::mailbox::Mailbox;
+use crate::soc::Model;
+use crate::soc::Soc;
+
+use kernel::hil;
+
+/// Where the ACT LED is wired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedPin {
+    Gpio {
+        pin: usize,
+        active_low: bool,
+    },
+    /// A pin on the firmware's GPIO expander, numbered from
+    /// `mailbox::EXPANDER_PIN_BASE`.
+    Expander(u32),
+}
+
+/// Where the ACT LED is on the board with revision code `revision`, running
+/// on the SoC `model`.
+pub const fn act_led_pin(revision: u32, model: Model) -> Option<LedPin> {
+    if revision & (1 << 23) == 0 {
+        return None;
+    }
+    let pin = match (revision >> 4) & 0xFF {
+        0x04 => (47, false),
+        0x08 => return Some(LedPin::Expander(mailbox::EXPANDER_ACT_LED)),
+        0x0D | 0x0E => (29, false),
+        0x11 if matches!(model, Model::Bcm2711) => (42, false),
+        0x12 => (29, true),
+        _ => return None,
+    };
+    Some(LedPin::Gpio {
+        pin: pin.0,
+        active_low: pin.1,
+    })
+}
+
+pub struct ActivityLed {
+    gpio: Gpio,
+    pin: Option<LedPin>,
+    /// Whether the LED is lit.
+    lit: Cell<bool>,
+}
+
+impl ActivityLed {
+    /// The ACT LED of the board with revision code `revision`.
+    ///
+    /// # Safety
+    ///
+    /// The LED's GPIO must not be used by other drivers.
+    pub unsafe fn new(revision: u32) -> ActivityLed {
+        let soc = Soc::detect();
+        ActivityLed {
+            gpio: Gpio::for_soc(&soc),
+            pin: act_led_pin(revision, soc.model),
+            lit: Cell::new(false),
+        }
+    }
+
+    /// The ACT LED of this board, asking the mailbox for the revision. If
+    /// that fails, the LED is treated as not drivable.
+    ///
+    /// # Safety
+    ///
+    /// As for `new`.
+    pub unsafe fn detect() -> ActivityLed {
+        let revision = Mailbox::new().get_board_revision().unwrap_or(0);
+        ActivityLed::new(revision)
+    }
+
+    /// Whether the LED can be driven on this board.
+    pub fn is_supported(&self) -> bool {
+        self.pin.is_some()
+    }
+
+    fn set(&self, lit: bool) {
+        match self.pin {
+            Some(LedPin::Gpio { pin, active_low }) => {
+                if lit != active_low {
+                    self.gpio.set_high(pin);
+                } else {
+                    self.gpio.set_low(pin);
+                }
+            }
+            Some(LedPin::Expander(pin)) => {
+                // Safety: only the expander pin of the LED is touched.
+                if unsafe { Mailbox::new() }.set_gpio_state(pin, lit).is_err() {
+                    return;
+                }
+            }
+            None => {}
+        }
+        self.lit.set(self.pin.is_some() && lit);
+    }
+}
+
+impl hil::led::Led for ActivityLed {
+    fn init(&self) {
+        self.set(false);
+        if let Some(LedPin::Gpio { pin, .. }) = self.pin {
+            self.gpio.set_function(pin, Function::Output);
+        }
+    }
+
+    fn on(&self) {
+        self.set(true);
+    }
+
+    fn off(&self) {
+        self.set(false);
+    }
+
+    fn toggle(&self) {
+        self.set(!self.lit.get());
+    }
+
+    fn read(&self) -> bool {
+        self.lit.get()
+    }
+}
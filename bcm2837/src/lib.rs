@@ -1,5 +1,67 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+#[cfg(target_arch = "aarch64")]
 pub static _START: unsafe extern "C" fn() -> ! = cortex_a::_start;
 
+/// Peripheral window of the BCM2837 and QEMU's `raspi3b` machine.
+pub const BCM2837_PERIPHERAL_BASE: usize = 0x3F00_0000;
+pub const BCM2837_PERIPHERAL_SIZE: usize = 0x0100_0000;
+
+/// Peripheral window of the BCM2711 (Pi 4) in its low-peripheral mode.
+pub const BCM2711_PERIPHERAL_BASE: usize = 0xFE00_0000;
+pub const BCM2711_PERIPHERAL_SIZE: usize = 0x0180_0000;
+
+/// Start of the peripheral window in the ARM physical address space, for
+/// the SoC the `bcm2711` feature selects. The drivers use the window
+/// `soc::Soc::detect` finds, which falls back to this one.
+#[cfg(not(feature = "bcm2711"))]
+pub const PERIPHERAL_BASE: usize = BCM2837_PERIPHERAL_BASE;
+#[cfg(feature = "bcm2711")]
+pub const PERIPHERAL_BASE: usize = BCM2711_PERIPHERAL_BASE;
+
+/// Size of the peripheral window.
+#[cfg(not(feature = "bcm2711"))]
+pub const PERIPHERAL_SIZE: usize = BCM2837_PERIPHERAL_SIZE;
+#[cfg(feature = "bcm2711")]
+pub const PERIPHERAL_SIZE: usize = BCM2711_PERIPHERAL_SIZE;
+
+pub mod ansi;
+pub mod aux_spi;
+#[cfg(target_arch = "aarch64")]
+pub mod chip;
+pub mod console;
+#[cfg(all(feature = "critical-section-impl", target_arch = "aarch64"))]
+mod critical_section_impl;
+pub mod debug;
+#[cfg(all(feature = "defmt", target_arch = "aarch64"))]
+pub mod defmt_uart;
+pub mod delay;
+pub mod dma;
+pub mod emmc;
+pub mod fat;
+#[cfg(target_arch = "aarch64")]
+pub mod fault;
+pub mod framebuffer;
+pub mod gpio;
+#[cfg(all(feature = "alloc", any(target_arch = "aarch64", test)))]
+pub mod heap;
+pub mod i2c;
+pub mod interrupt;
+#[cfg(all(feature = "profiling", target_arch = "aarch64"))]
+pub mod latency;
+pub mod led;
+pub mod mailbox;
+pub mod pcm;
+pub mod pl011;
+pub mod power;
+pub mod pwm;
+pub mod rng;
+mod shared;
+#[cfg(target_arch = "aarch64")]
+pub mod shell;
+pub mod soc;
+pub mod spi;
+pub mod system_timer;
+pub mod time;
 pub mod uart;
+pub mod xmodem;
@@ -0,0 +1,600 @@
+use core::sync::atomic::fence;
+use core::sync::atomic::Ordering;
+
+use crate::soc::Soc;
+
+use kernel::utilities::StaticRef;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::WriteOnly;
+
+register_bitfields![u32,
+    STATUS [
+        Empty OFFSET(30) NUMBITS(1) [],
+        Full OFFSET(31) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: mailbox 0 (VideoCore to ARM) read
+    read: ReadOnly<u32>,
+    _reserved0: [u8; 0x18 - 0x04],
+
+    /// 0x18: mailbox 0 status
+    read_status: ReadOnly<u32, STATUS::Register>,
+    _reserved1: [u8; 0x20 - 0x1C],
+
+    /// 0x20: mailbox 1 (ARM to VideoCore) write
+    write: WriteOnly<u32>,
+    _reserved2: [u8; 0x38 - 0x24],
+
+    /// 0x38: mailbox 1 status
+    write_status: ReadOnly<u32, STATUS::Register>,
+}
+
+/// Channel of the property-tag interface.
+pub const PROPERTY_CHANNEL: u8 = 8;
+
+/// Buffer request/response code: request.
+const REQUEST: u32 = 0;
+/// Buffer request/response code: the firmware handled the request.
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+/// Buffer request/response code: the firmware failed to parse the request.
+const RESPONSE_ERROR: u32 = 0x8000_0001;
+/// Tag request/response code bit set by the firmware on a processed tag.
+const TAG_RESPONSE: u32 = 1 << 31;
+/// Tag ID terminating a property message.
+const END_TAG: u32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MailboxError {
+    /// The message is not 16-byte aligned, so its address would clobber the
+    /// channel bits.
+    Unaligned,
+    /// Channels are 4 bits wide.
+    InvalidChannel,
+    /// The property message has no room for another tag.
+    BufferFull,
+    /// The firmware did not set the response bit of the buffer or a tag.
+    NoResponse,
+    /// The firmware reported an error parsing the buffer.
+    RequestFailed,
+}
+
+/// VideoCore mailbox at offset 0xB880 of the peripheral window.
+///
+/// A message is passed to the firmware as the address of its buffer with the
+/// channel in the low 4 bits, which is why buffers must be 16-byte aligned.
+/// The address is passed as-is, so `call_property` cleans the message out of
+/// the data cache before the call and invalidates it afterwards.
+pub struct Mailbox {
+    registers: StaticRef<Registers>,
+    transport: Transport,
+}
+
+/// Hands a checked message to the firmware and waits for its answer. Tests
+/// replace `exchange` with a stand-in for the firmware.
+type Transport = unsafe fn(registers: &Registers, channel: u8, message: *mut u32);
+
+impl Mailbox {
+    pub unsafe fn new() -> Mailbox {
+        Mailbox::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Mailbox {
+        Mailbox {
+            registers: StaticRef::new((soc.peripheral_base + 0xB880) as *const Registers),
+            transport: exchange,
+        }
+    }
+
+    /// Sends the buffer at `message` on `channel` and waits for the
+    /// firmware to hand it back.
+    ///
+    /// # Safety
+    ///
+    /// `message` must point to a valid buffer for the channel, which the
+    /// firmware may write to until this returns.
+    pub unsafe fn call(&self, channel: u8, message: *mut u32) -> Result<(), MailboxError> {
+        if message as usize & 0xF != 0 {
+            return Err(MailboxError::Unaligned);
+        }
+        if channel > 0xF {
+            return Err(MailboxError::InvalidChannel);
+        }
+        (self.transport)(&self.registers, channel, message);
+        Ok(())
+    }
+
+    /// Sends a property message and checks the firmware's response code.
+    pub fn call_property<const N: usize>(
+        &self,
+        message: &mut PropertyMessage<N>,
+    ) -> Result<(), MailboxError> {
+        message.finish()?;
+        let start = message.words.0.as_ptr() as usize;
+        let len = core::mem::size_of::<CacheLines<N>>();
+        clean_dcache(start, len);
+        // Safety: the message is a complete, aligned property buffer that
+        // stays borrowed for the duration of the call.
+        unsafe { self.call(PROPERTY_CHANNEL, message.words.0.as_mut_ptr()) }?;
+        // Safety: the range is exactly the lines of the message, which the
+        // CPU has not written since they were cleaned.
+        unsafe { invalidate_dcache(start, len) };
+        match message.word(1) {
+            RESPONSE_SUCCESS => Ok(()),
+            RESPONSE_ERROR => Err(MailboxError::RequestFailed),
+            _ => Err(MailboxError::NoResponse),
+        }
+    }
+}
+
+/// Writes the address of `message` with `channel` in the low 4 bits to the
+/// ARM-to-VideoCore mailbox, and waits until the firmware writes it back.
+unsafe fn exchange(registers: &Registers, channel: u8, message: *mut u32) {
+    let value = message as usize as u32 | channel as u32;
+
+    // Make the buffer contents visible before handing it over.
+    fence(Ordering::SeqCst);
+    while registers.write_status.is_set(STATUS::Full) {}
+    registers.write.set(value);
+
+    loop {
+        while registers.read_status.is_set(STATUS::Empty) {}
+        if registers.read.get() == value {
+            break;
+        }
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// Writes the `len` bytes from `start` back from the data cache, so the
+/// VideoCore sees the CPU's writes.
+fn clean_dcache(start: usize, len: usize) {
+    #[cfg(target_arch = "aarch64")]
+    {
+        debug_assert!(cortex_a::cache::dcache_line_size() <= CACHE_LINE);
+        cortex_a::cache::clean_range(start, len);
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = (start, len);
+}
+
+/// Discards the data cache lines of the `len` bytes from `start`, so the CPU
+/// reads what the VideoCore wrote.
+///
+/// # Safety
+///
+/// The range must be cache line aligned and not written by the CPU since
+/// `clean_dcache`.
+unsafe fn invalidate_dcache(start: usize, len: usize) {
+    #[cfg(target_arch = "aarch64")]
+    cortex_a::cache::invalidate_range(start, len);
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = (start, len);
+}
+
+/// Property tag IDs.
+pub mod tag {
+    pub const GET_BOARD_REVISION: u32 = 0x0001_0002;
+    pub const GET_BOARD_SERIAL: u32 = 0x0001_0004;
+    pub const GET_ARM_MEMORY: u32 = 0x0001_0005;
+    pub const GET_VC_MEMORY: u32 = 0x0001_0006;
+    pub const GET_CLOCK_RATE: u32 = 0x0003_0002;
+    pub const GET_MAX_CLOCK_RATE: u32 = 0x0003_0004;
+    pub const GET_MIN_CLOCK_RATE: u32 = 0x0003_0007;
+    pub const GET_CLOCK_RATE_MEASURED: u32 = 0x0003_0047;
+    pub const SET_CLOCK_RATE: u32 = 0x0003_8002;
+    pub const GET_TEMPERATURE: u32 = 0x0003_0006;
+    pub const GET_MAX_TEMPERATURE: u32 = 0x0003_000A;
+    pub const GET_GPIO_STATE: u32 = 0x0003_0041;
+    pub const SET_GPIO_STATE: u32 = 0x0003_8041;
+}
+
+/// ID of the SoC temperature sensor, the only one the firmware has.
+const TEMPERATURE_SENSOR: u32 = 0;
+
+/// Splits a temperature in millidegrees Celsius into whole degrees and
+/// tenths, rounding down.
+pub const fn celsius_and_tenths(millidegrees: u32) -> (u32, u32) {
+    (millidegrees / 1000, millidegrees % 1000 / 100)
+}
+
+/// Model name for a board revision code, or `None` if it is not known.
+///
+/// New-style codes have bit 23 set and the board type in bits 4-11. Codes
+/// without it belong to the original Pi 1 boards.
+pub const fn board_model(revision: u32) -> Option<&'static str> {
+    if revision & (1 << 23) == 0 {
+        return Some("Raspberry Pi 1 (old-style revision)");
+    }
+    match (revision >> 4) & 0xFF {
+        0x04 => Some("Raspberry Pi 2 Model B"),
+        0x08 => Some("Raspberry Pi 3 Model B"),
+        0x0A => Some("Raspberry Pi Compute Module 3"),
+        0x0D => Some("Raspberry Pi 3 Model B+"),
+        0x0E => Some("Raspberry Pi 3 Model A+"),
+        0x10 => Some("Raspberry Pi Compute Module 3+"),
+        0x11 => Some("Raspberry Pi 4 Model B"),
+        0x12 => Some("Raspberry Pi Zero 2 W"),
+        _ => None,
+    }
+}
+
+/// First pin number of the firmware's GPIO expander, as used by
+/// `get_gpio_state` and `set_gpio_state`. Expander pin `n` is `128 + n`.
+pub const EXPANDER_PIN_BASE: u32 = 128;
+
+/// The ACT LED on the Pi 3 Model B, which sits on expander pin 2. Pin 7 is
+/// the power LED.
+pub const EXPANDER_ACT_LED: u32 = EXPANDER_PIN_BASE + 2;
+
+/// Firmware clock IDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Clock {
+    Emmc = 1,
+    /// The PL011 reference clock.
+    Uart = 2,
+    Arm = 3,
+    /// The VPU clock, which also clocks the Mini UART.
+    Core = 4,
+}
+
+impl Mailbox {
+    /// Sends a message with the single tag `tag` and returns the first `W`
+    /// words of its response.
+    pub fn query<const W: usize>(
+        &self,
+        tag: u32,
+        request: &[u32],
+    ) -> Result<[u32; W], MailboxError> {
+        let mut message = PropertyMessage::<32>::new();
+        let handle = message.add_tag(tag, request, W)?;
+        self.call_property(&mut message)?;
+        let mut response = [0; W];
+        for (index, word) in response.iter_mut().enumerate() {
+            *word = message.response(handle, index)?;
+        }
+        Ok(response)
+    }
+
+    pub fn get_board_revision(&self) -> Result<u32, MailboxError> {
+        let [revision] = self.query::<1>(tag::GET_BOARD_REVISION, &[])?;
+        Ok(revision)
+    }
+
+    pub fn get_board_serial(&self) -> Result<u64, MailboxError> {
+        let [lo, hi] = self.query::<2>(tag::GET_BOARD_SERIAL, &[])?;
+        Ok((hi as u64) << 32 | lo as u64)
+    }
+
+    /// Base address and size in bytes of the memory assigned to the ARM.
+    pub fn get_arm_memory(&self) -> Result<(u32, u32), MailboxError> {
+        let [base, size] = self.query::<2>(tag::GET_ARM_MEMORY, &[])?;
+        Ok((base, size))
+    }
+
+    /// Configured rate of `clock` in Hz.
+    pub fn get_clock_rate(&self, clock: Clock) -> Result<u32, MailboxError> {
+        let [_, rate] = self.query::<2>(tag::GET_CLOCK_RATE, &[clock as u32])?;
+        Ok(rate)
+    }
+
+    /// Rate of `clock` in Hz as measured by the firmware.
+    pub fn get_measured_clock_rate(&self, clock: Clock) -> Result<u32, MailboxError> {
+        let [_, rate] = self.query::<2>(tag::GET_CLOCK_RATE_MEASURED, &[clock as u32])?;
+        Ok(rate)
+    }
+
+    pub fn get_max_clock_rate(&self, clock: Clock) -> Result<u32, MailboxError> {
+        let [_, rate] = self.query::<2>(tag::GET_MAX_CLOCK_RATE, &[clock as u32])?;
+        Ok(rate)
+    }
+
+    pub fn get_min_clock_rate(&self, clock: Clock) -> Result<u32, MailboxError> {
+        let [_, rate] = self.query::<2>(tag::GET_MIN_CLOCK_RATE, &[clock as u32])?;
+        Ok(rate)
+    }
+
+    /// Requests `hz` for `clock` and returns the rate actually set. The
+    /// firmware clamps requests to the clock's min/max rather than failing,
+    /// so the result may differ from `hz`.
+    pub fn set_clock_rate(&self, clock: Clock, hz: u32) -> Result<u32, MailboxError> {
+        // The third word asks the firmware not to apply turbo settings.
+        let [_, rate] = self.query::<2>(tag::SET_CLOCK_RATE, &[clock as u32, hz, 0])?;
+        Ok(rate)
+    }
+
+    /// Measured input clock of the Mini UART, suitable for
+    /// `UART::set_clock`.
+    pub fn mini_uart_clock(&self) -> Result<u32, MailboxError> {
+        self.get_measured_clock_rate(Clock::Core)
+    }
+
+    /// SoC temperature in millidegrees Celsius.
+    pub fn get_temperature(&self) -> Result<u32, MailboxError> {
+        let [_, temperature] = self.query::<2>(tag::GET_TEMPERATURE, &[TEMPERATURE_SENSOR])?;
+        Ok(temperature)
+    }
+
+    /// Temperature in millidegrees Celsius at which the firmware throttles
+    /// the clocks.
+    pub fn get_max_temperature(&self) -> Result<u32, MailboxError> {
+        let [_, temperature] = self.query::<2>(tag::GET_MAX_TEMPERATURE, &[TEMPERATURE_SENSOR])?;
+        Ok(temperature)
+    }
+
+    /// SoC temperature as whole degrees Celsius and tenths, see
+    /// `celsius_and_tenths`.
+    pub fn temperature_celsius(&self) -> Result<(u32, u32), MailboxError> {
+        self.get_temperature().map(celsius_and_tenths)
+    }
+
+    /// Base address and size in bytes of the memory assigned to the
+    /// VideoCore.
+    pub fn get_vc_memory(&self) -> Result<(u32, u32), MailboxError> {
+        let [base, size] = self.query::<2>(tag::GET_VC_MEMORY, &[])?;
+        Ok((base, size))
+    }
+
+    /// State of a pin on the firmware's GPIO expander, numbered from
+    /// `EXPANDER_PIN_BASE`.
+    pub fn get_gpio_state(&self, pin: u32) -> Result<bool, MailboxError> {
+        let [_, state] = self.query::<2>(tag::GET_GPIO_STATE, &[pin])?;
+        Ok(state != 0)
+    }
+
+    /// Drives a pin on the firmware's GPIO expander, numbered from
+    /// `EXPANDER_PIN_BASE`. This is the only way to reach the LEDs of the
+    /// Pi 3 Model B.
+    pub fn set_gpio_state(&self, pin: u32, on: bool) -> Result<(), MailboxError> {
+        self.query::<2>(tag::SET_GPIO_STATE, &[pin, on as u32])?;
+        Ok(())
+    }
+}
+
+/// A property-tag message of up to `N` words, built up one tag at a time.
+///
+/// The layout is the total size in bytes, the request/response code, the
+/// tags, and an end tag. Each tag is its ID, the size of its value buffer in
+/// bytes, its request/response code, and the value buffer.
+pub struct PropertyMessage<const N: usize> {
+    words: CacheLines<N>,
+    len: usize,
+}
+
+/// Size of a data cache line on the Cortex-A53 and A72.
+const CACHE_LINE: usize = 64;
+
+/// The words of a `PropertyMessage`, padded to whole cache lines so the
+/// maintenance around a call never touches anything else.
+#[repr(C, align(64))]
+struct CacheLines<const N: usize>([u32; N]);
+
+const _: () = assert!(core::mem::align_of::<CacheLines<1>>() == CACHE_LINE);
+
+impl<const N: usize> PropertyMessage<N> {
+    pub fn new() -> PropertyMessage<N> {
+        PropertyMessage {
+            words: CacheLines([0; N]),
+            len: 2,
+        }
+    }
+
+    /// Appends a tag with the given request values and room for
+    /// `response_words` words of response. Returns a handle to the tag for
+    /// reading its response.
+    pub fn add_tag(
+        &mut self,
+        tag: u32,
+        request: &[u32],
+        response_words: usize,
+    ) -> Result<Tag, MailboxError> {
+        let value_words = request.len().max(response_words);
+        // Leave room for the end tag.
+        if self.len + 3 + value_words + 1 > N {
+            return Err(MailboxError::BufferFull);
+        }
+        let start = self.len;
+        self.words.0[start] = tag;
+        self.words.0[start + 1] = (value_words * 4) as u32;
+        self.words.0[start + 2] = REQUEST;
+        self.words.0[start + 3..start + 3 + request.len()].copy_from_slice(request);
+        self.words.0[start + 3 + request.len()..start + 3 + value_words].fill(0);
+        self.len += 3 + value_words;
+        Ok(Tag(start))
+    }
+
+    /// Reads word `index` of the value buffer of `tag`, checking that the
+    /// firmware processed the tag.
+    pub fn response(&self, tag: Tag, index: usize) -> Result<u32, MailboxError> {
+        if self.word(tag.0 + 2) & TAG_RESPONSE == 0 {
+            return Err(MailboxError::NoResponse);
+        }
+        Ok(self.word(tag.0 + 3 + index))
+    }
+
+    fn finish(&mut self) -> Result<(), MailboxError> {
+        if self.len >= N {
+            return Err(MailboxError::BufferFull);
+        }
+        self.words.0[self.len] = END_TAG;
+        self.words.0[0] = ((self.len + 1) * 4) as u32;
+        self.words.0[1] = REQUEST;
+        Ok(())
+    }
+
+    fn word(&self, index: usize) -> u32 {
+        // The firmware writes the buffer behind the compiler's back.
+        unsafe { core::ptr::read_volatile(&self.words.0[index]) }
+    }
+}
+
+impl<const N: usize> Default for PropertyMessage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Position of a tag within a `PropertyMessage`.
+#[derive(Clone, Copy, Debug)]
+pub struct Tag(usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+
+    /// Values for a tag given its ID and request values, or `None` to leave
+    /// the tag unanswered.
+    type Firmware = fn(tag: u32, request: &[u32]) -> Option<Vec<u32>>;
+
+    std::thread_local! {
+        static FIRMWARE: Cell<Option<Firmware>> = const { Cell::new(None) };
+        static LAST_REQUEST: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// A mailbox whose property messages are answered by `firmware`
+    /// instead of the VideoCore.
+    fn mailbox_with(firmware: Firmware) -> Mailbox {
+        FIRMWARE.with(|cell| cell.set(Some(firmware)));
+        Mailbox {
+            transport: answer,
+            ..unsafe { Mailbox::for_soc(&Soc::in_memory()) }
+        }
+    }
+
+    /// The last message sent, as it was before the firmware answered it.
+    fn last_request() -> Vec<u32> {
+        LAST_REQUEST.with(|request| request.borrow().clone())
+    }
+
+    /// A `Transport` that answers each tag of a property message as this
+    /// thread's firmware says.
+    unsafe fn answer(_registers: &Registers, channel: u8, message: *mut u32) {
+        assert_eq!(channel, PROPERTY_CHANNEL);
+        let len = *message as usize / 4;
+        let words = core::slice::from_raw_parts_mut(message, len);
+        LAST_REQUEST.with(|request| *request.borrow_mut() = words.to_vec());
+        let Some(firmware) = FIRMWARE.with(Cell::get) else {
+            return;
+        };
+        let mut index = 2;
+        while words[index] != END_TAG {
+            let size = words[index + 1] as usize / 4;
+            let tag = words[index];
+            let value = &mut words[index + 3..index + 3 + size];
+            if let Some(response) = firmware(tag, value) {
+                value[..response.len()].copy_from_slice(&response);
+                words[index + 2] = TAG_RESPONSE | (response.len() * 4) as u32;
+            }
+            index += 3 + size;
+        }
+        words[1] = RESPONSE_SUCCESS;
+    }
+
+    fn board(tag: u32, _request: &[u32]) -> Option<Vec<u32>> {
+        match tag {
+            tag::GET_BOARD_REVISION => Some(vec![0x00A0_2082]),
+            tag::GET_BOARD_SERIAL => Some(vec![0x89AB_CDEF, 0x0123_4567]),
+            tag::GET_ARM_MEMORY => Some(vec![0, 0x3B40_0000]),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_revision_serial_and_memory() {
+        let mailbox = mailbox_with(board);
+        assert_eq!(mailbox.get_board_revision(), Ok(0x00A0_2082));
+        assert_eq!(mailbox.get_board_serial(), Ok(0x0123_4567_89AB_CDEF));
+        assert_eq!(mailbox.get_arm_memory(), Ok((0, 0x3B40_0000)));
+    }
+
+    #[test]
+    fn unanswered_tag_is_no_response() {
+        let mailbox = mailbox_with(board);
+        assert_eq!(mailbox.get_vc_memory(), Err(MailboxError::NoResponse));
+    }
+
+    #[test]
+    fn query_lays_out_a_single_tag() {
+        let mailbox = mailbox_with(board);
+        mailbox.get_board_serial().unwrap();
+        assert_eq!(
+            last_request(),
+            [
+                8 * 4,
+                REQUEST,
+                tag::GET_BOARD_SERIAL,
+                8,
+                REQUEST,
+                0,
+                0,
+                END_TAG
+            ]
+        );
+    }
+
+    #[test]
+    fn temperature_celsius_splits_millidegrees() {
+        let mailbox = mailbox_with(|tag, request| match (tag, request) {
+            (tag::GET_TEMPERATURE, [TEMPERATURE_SENSOR, _]) => {
+                Some(vec![TEMPERATURE_SENSOR, 48_312])
+            }
+            _ => None,
+        });
+        assert_eq!(mailbox.get_temperature(), Ok(48_312));
+        assert_eq!(mailbox.temperature_celsius(), Ok((48, 3)));
+        assert_eq!(celsius_and_tenths(999), (0, 9));
+    }
+
+    #[test]
+    fn set_gpio_state_sends_pin_and_state() {
+        let mailbox = mailbox_with(|tag, request| match (tag, request) {
+            (tag::SET_GPIO_STATE, [pin, _]) => Some(vec![*pin, 0]),
+            _ => None,
+        });
+        for (on, state) in [(true, 1), (false, 0)] {
+            assert_eq!(mailbox.set_gpio_state(EXPANDER_ACT_LED, on), Ok(()));
+            assert_eq!(
+                last_request(),
+                [
+                    8 * 4,
+                    REQUEST,
+                    tag::SET_GPIO_STATE,
+                    8,
+                    REQUEST,
+                    EXPANDER_ACT_LED,
+                    state,
+                    END_TAG
+                ][..]
+            );
+        }
+    }
+
+    #[test]
+    fn call_checks_alignment_and_channel() {
+        let mailbox = mailbox_with(board);
+        let mut message = PropertyMessage::<4>::new();
+        let words = message.words.0.as_mut_ptr();
+        unsafe {
+            assert_eq!(mailbox.call(0x10, words), Err(MailboxError::InvalidChannel));
+            assert_eq!(
+                mailbox.call(PROPERTY_CHANNEL, words.add(1)),
+                Err(MailboxError::Unaligned)
+            );
+        }
+    }
+
+    #[test]
+    fn message_words_fill_whole_cache_lines() {
+        assert_eq!(core::mem::size_of::<CacheLines<36>>(), 3 * CACHE_LINE);
+        assert_eq!(core::mem::size_of::<CacheLines<16>>(), CACHE_LINE);
+    }
+}
@@ -0,0 +1,297 @@
+//! PCM/I2S audio interface, polled transmit of 16-bit stereo.
+//!
+//! `init` follows the polled-mode sequence of the datasheet:
+//!
+//! 1. Stop the PCM clock, program its divisor from PLLD and restart it,
+//!    as the clock manager must not be changed while the clock runs.
+//! 2. Set EN and release the FIFO RAMs from standby (STBY).
+//! 3. Program the frame (MODE_A) and channel (TXC_A) layout.
+//! 4. Clear the FIFOs with TXCLR/RXCLR and wait two PCM clocks, which the
+//!    SYNC bit measures: it reads back as written only after two clocks.
+//! 5. Set the FIFO threshold.
+//!
+//! `start` then sets TXON, after the caller has queued the first samples.
+//! Frames are 32 bit clocks long, one 16-bit channel per half. Channels are
+//! delayed by one clock from the frame sync edge, as I2S expects, and the
+//! frame sync is low for the left channel.
+//!
+//! DMA (DREQ_A and CS_A.DMAEN) is not used yet.
+
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::soc::Soc;
+
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CS [
+        Enable OFFSET(0) NUMBITS(1) [],
+        RxOn OFFSET(1) NUMBITS(1) [],
+        TxOn OFFSET(2) NUMBITS(1) [],
+        TxClear OFFSET(3) NUMBITS(1) [],
+        RxClear OFFSET(4) NUMBITS(1) [],
+        TxThreshold OFFSET(5) NUMBITS(2) [
+            Empty = 0,
+            LessThanFull = 3,
+        ],
+        RxThreshold OFFSET(7) NUMBITS(2) [],
+        DmaEnable OFFSET(9) NUMBITS(1) [],
+        TxError OFFSET(15) NUMBITS(1) [],
+        RxError OFFSET(16) NUMBITS(1) [],
+        TxNeedsWriting OFFSET(17) NUMBITS(1) [],
+        TxCanAccept OFFSET(19) NUMBITS(1) [],
+        TxEmpty OFFSET(21) NUMBITS(1) [],
+        Sync OFFSET(24) NUMBITS(1) [],
+        RamRelease OFFSET(25) NUMBITS(1) [],
+    ],
+    MODE [
+        FrameSyncLength OFFSET(0) NUMBITS(10) [],
+        FrameLength OFFSET(10) NUMBITS(10) [],
+        FrameSyncInvert OFFSET(20) NUMBITS(1) [],
+        FrameSyncSlave OFFSET(21) NUMBITS(1) [],
+        ClockInvert OFFSET(22) NUMBITS(1) [],
+        ClockSlave OFFSET(23) NUMBITS(1) [],
+        TxPacked OFFSET(24) NUMBITS(1) [],
+        RxPacked OFFSET(25) NUMBITS(1) [],
+        ClockDisable OFFSET(28) NUMBITS(1) [],
+    ],
+    XC [
+        Ch2Width OFFSET(0) NUMBITS(4) [],
+        Ch2Position OFFSET(4) NUMBITS(10) [],
+        Ch2Enable OFFSET(14) NUMBITS(1) [],
+        Ch2WidthExtension OFFSET(15) NUMBITS(1) [],
+        Ch1Width OFFSET(16) NUMBITS(4) [],
+        Ch1Position OFFSET(20) NUMBITS(10) [],
+        Ch1Enable OFFSET(30) NUMBITS(1) [],
+        Ch1WidthExtension OFFSET(31) NUMBITS(1) [],
+    ],
+    CM_CTL [
+        Source OFFSET(0) NUMBITS(4) [
+            Plld = 6,
+        ],
+        Enable OFFSET(4) NUMBITS(1) [],
+        Kill OFFSET(5) NUMBITS(1) [],
+        Busy OFFSET(7) NUMBITS(1) [],
+        Mash OFFSET(9) NUMBITS(2) [
+            Integer = 0,
+            OneStage = 1,
+        ],
+        Password OFFSET(24) NUMBITS(8) [
+            Password = 0x5A,
+        ],
+    ],
+    CM_DIV [
+        Fraction OFFSET(0) NUMBITS(12) [],
+        Integer OFFSET(12) NUMBITS(12) [],
+        Password OFFSET(24) NUMBITS(8) [
+            Password = 0x5A,
+        ],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: control and status (CS_A)
+    cs: ReadWrite<u32, CS::Register>,
+
+    /// 0x04: FIFO data (FIFO_A)
+    fifo: ReadWrite<u32>,
+
+    /// 0x08: mode (MODE_A)
+    mode: ReadWrite<u32, MODE::Register>,
+
+    /// 0x0C: receive configuration (RXC_A)
+    rxc: ReadWrite<u32, XC::Register>,
+
+    /// 0x10: transmit configuration (TXC_A)
+    txc: ReadWrite<u32, XC::Register>,
+
+    /// 0x14: DMA request level (DREQ_A)
+    _dreq: ReadWrite<u32>,
+
+    /// 0x18: interrupt enables (INTEN_A)
+    _inten: ReadWrite<u32>,
+
+    /// 0x1C: interrupt status and clear (INTSTC_A)
+    _intstc: ReadWrite<u32>,
+
+    /// 0x20: gray mode control (GRAY)
+    _gray: ReadWrite<u32>,
+}
+
+/// The PCM clock in the clock manager, at offset 0x101098 of the peripheral
+/// window.
+#[repr(C)]
+struct ClockRegisters {
+    /// 0x00: control (CM_PCMCTL)
+    ctl: ReadWrite<u32, CM_CTL::Register>,
+
+    /// 0x04: divisor (CM_PCMDIV)
+    div: ReadWrite<u32, CM_DIV::Register>,
+}
+
+/// PLLD, the PCM clock source.
+const PLLD_HZ: u64 = 500_000_000;
+
+/// PCM_CLK, PCM_FS, PCM_DIN and PCM_DOUT, all alt0.
+const PINS: [usize; 4] = [18, 19, 20, 21];
+
+/// Bit clocks per frame.
+const FRAME_LENGTH: u32 = 32;
+/// Bits per channel.
+const CHANNEL_WIDTH: u32 = 16;
+
+/// PCM/I2S transmitter.
+pub struct Pcm {
+    registers: StaticRef<Registers>,
+    clock: StaticRef<ClockRegisters>,
+    soc: Soc,
+}
+
+impl Pcm {
+    pub unsafe fn new() -> Pcm {
+        Pcm::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Pcm {
+        Pcm {
+            registers: StaticRef::new((soc.peripheral_base + 0x203000) as *const Registers),
+            clock: StaticRef::new((soc.peripheral_base + 0x101098) as *const ClockRegisters),
+            soc: *soc,
+        }
+    }
+
+    /// Routes the PCM to GPIO18-21 and sets it up for 16-bit stereo at
+    /// `sample_rate` Hz, with the transmitter stopped.
+    pub fn init(&mut self, sample_rate: u32) -> Result<(), ErrorCode> {
+        // Safety: GPIO18-21 belong to the PCM.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in PINS {
+            gpio.set_function(pin, Function::Alt0);
+        }
+
+        self.registers.cs.set(0);
+        self.set_clock(sample_rate * FRAME_LENGTH)?;
+
+        self.registers
+            .cs
+            .write(CS::Enable::SET + CS::RamRelease::SET);
+        self.registers.mode.write(
+            MODE::FrameLength.val(FRAME_LENGTH - 1)
+                + MODE::FrameSyncLength.val(FRAME_LENGTH / 2)
+                + MODE::FrameSyncInvert::SET
+                + MODE::TxPacked::SET,
+        );
+        // Widths are stored as bits - 8; positions count from the frame
+        // sync edge.
+        self.registers.txc.write(
+            XC::Ch1Enable::SET
+                + XC::Ch1Width.val(CHANNEL_WIDTH - 8)
+                + XC::Ch1Position.val(1)
+                + XC::Ch2Enable::SET
+                + XC::Ch2Width.val(CHANNEL_WIDTH - 8)
+                + XC::Ch2Position.val(FRAME_LENGTH / 2 + 1),
+        );
+        self.registers.rxc.set(0);
+
+        self.registers
+            .cs
+            .modify(CS::TxClear::SET + CS::RxClear::SET);
+        self.wait_two_clocks();
+        self.registers
+            .cs
+            .modify(CS::TxThreshold::LessThanFull + CS::TxError::SET);
+        Ok(())
+    }
+
+    /// Runs the bit clock at `hz` from PLLD, with a one-stage MASH filter
+    /// smoothing the fractional divisor.
+    fn set_clock(&mut self, hz: u32) -> Result<(), ErrorCode> {
+        if hz == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        let divisor = (PLLD_HZ << 12) / hz as u64;
+        let integer = (divisor >> 12) as u32;
+        // MASH needs an integer part of at least 2.
+        if !(2..=0xFFF).contains(&integer) {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.clock
+            .ctl
+            .write(CM_CTL::Password::Password + CM_CTL::Kill::SET);
+        while self.clock.ctl.is_set(CM_CTL::Busy) {}
+
+        self.clock.div.write(
+            CM_DIV::Password::Password
+                + CM_DIV::Integer.val(integer)
+                + CM_DIV::Fraction.val(divisor as u32 & 0xFFF),
+        );
+        let ctl = CM_CTL::Password::Password + CM_CTL::Source::Plld + CM_CTL::Mash::OneStage;
+        self.clock.ctl.write(ctl);
+        self.clock.ctl.write(ctl + CM_CTL::Enable::SET);
+        while !self.clock.ctl.is_set(CM_CTL::Busy) {}
+        Ok(())
+    }
+
+    /// Waits for two PCM clocks using CS_A.SYNC.
+    fn wait_two_clocks(&self) {
+        let target = !self.registers.cs.is_set(CS::Sync);
+        self.registers.cs.modify(CS::Sync.val(target as u32));
+        while self.registers.cs.is_set(CS::Sync) != target {}
+    }
+
+    /// Starts transmitting. Queue a few samples first, or the transmitter
+    /// underruns straight away.
+    pub fn start(&mut self) {
+        self.registers.cs.modify(CS::TxOn::SET);
+    }
+
+    pub fn stop(&mut self) {
+        self.registers.cs.modify(CS::TxOn::CLEAR);
+    }
+
+    /// Queues one stereo frame, the left sample in bits 0-15 and the right
+    /// one in bits 16-31, waiting for room in the FIFO.
+    pub fn write_sample(&mut self, sample: u32) {
+        while !self.tx_fifo_can_accept() {}
+        self.registers.fifo.set(sample);
+    }
+
+    /// Queues one stereo frame, see `write_sample`.
+    pub fn write_stereo(&mut self, left: i16, right: i16) {
+        self.write_sample((left as u16 as u32) | ((right as u16 as u32) << 16));
+    }
+
+    /// Whether the transmit FIFO has room for a frame (CS_A.TXD).
+    pub fn tx_fifo_can_accept(&self) -> bool {
+        self.registers.cs.is_set(CS::TxCanAccept)
+    }
+
+    /// Whether the transmit FIFO is below its threshold and should be
+    /// refilled (CS_A.TXW).
+    pub fn tx_fifo_needs_writing(&self) -> bool {
+        self.registers.cs.is_set(CS::TxNeedsWriting)
+    }
+
+    pub fn tx_fifo_empty(&self) -> bool {
+        self.registers.cs.is_set(CS::TxEmpty)
+    }
+
+    /// Whether the transmit FIFO ran dry since the last call (CS_A.TXERR),
+    /// which also clears the flag.
+    pub fn take_tx_underrun(&mut self) -> bool {
+        let underrun = self.registers.cs.is_set(CS::TxError);
+        if underrun {
+            self.registers.cs.modify(CS::TxError::SET);
+        }
+        underrun
+    }
+}
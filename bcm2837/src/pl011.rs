@@ -0,0 +1,268 @@
+//! Polled driver for the PL011 UART (UART0).
+//!
+//! On the Pi 3 the PL011 is wired to the Bluetooth chip on GPIO32/33 by
+//! default, and the header's GPIO14/15 get the Mini UART. `init` muxes
+//! GPIO14/15 to the PL011 (alt0), which takes them away from the Mini UART,
+//! but the firmware must be told to leave the PL011 alone as well: add
+//! `dtoverlay=disable-bt` (or `miniuart-bt`, which moves Bluetooth to the
+//! Mini UART) to `config.txt`. On a board without Bluetooth, like QEMU's
+//! `raspi3b` machine, the PL011 is the first serial port.
+//!
+//! Its reference clock is set by the firmware, 48 MHz on current firmware
+//! (`init_uart_clock` in `config.txt`); use `set_clock` if it differs, e.g.
+//! with the rate the mailbox reports for `Clock::Uart`.
+
+use core::fmt::Write;
+
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::gpio::Pull;
+use crate::soc::Soc;
+use crate::uart::RXD_PIN;
+use crate::uart::TXD_PIN;
+
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+use tock_registers::registers::WriteOnly;
+
+register_bitfields![u32,
+    DR [
+        Data OFFSET(0) NUMBITS(8) [],
+        FramingError OFFSET(8) NUMBITS(1) [],
+        ParityError OFFSET(9) NUMBITS(1) [],
+        BreakError OFFSET(10) NUMBITS(1) [],
+        OverrunError OFFSET(11) NUMBITS(1) [],
+    ],
+    FR [
+        ClearToSend OFFSET(0) NUMBITS(1) [],
+        Busy OFFSET(3) NUMBITS(1) [],
+        RxFifoEmpty OFFSET(4) NUMBITS(1) [],
+        TxFifoFull OFFSET(5) NUMBITS(1) [],
+        RxFifoFull OFFSET(6) NUMBITS(1) [],
+        TxFifoEmpty OFFSET(7) NUMBITS(1) [],
+    ],
+    IBRD [
+        Divisor OFFSET(0) NUMBITS(16) [],
+    ],
+    FBRD [
+        Fraction OFFSET(0) NUMBITS(6) [],
+    ],
+    LCRH [
+        SendBreak OFFSET(0) NUMBITS(1) [],
+        ParityEnable OFFSET(1) NUMBITS(1) [],
+        EvenParity OFFSET(2) NUMBITS(1) [],
+        TwoStopBits OFFSET(3) NUMBITS(1) [],
+        FifoEnable OFFSET(4) NUMBITS(1) [],
+        WordLength OFFSET(5) NUMBITS(2) [
+            Bits5 = 0,
+            Bits6 = 1,
+            Bits7 = 2,
+            Bits8 = 3,
+        ],
+        StickParity OFFSET(7) NUMBITS(1) [],
+    ],
+    CR [
+        Enable OFFSET(0) NUMBITS(1) [],
+        LoopbackEnable OFFSET(7) NUMBITS(1) [],
+        TransmitEnable OFFSET(8) NUMBITS(1) [],
+        ReceiveEnable OFFSET(9) NUMBITS(1) [],
+        RequestToSend OFFSET(11) NUMBITS(1) [],
+        RtsEnable OFFSET(14) NUMBITS(1) [],
+        CtsEnable OFFSET(15) NUMBITS(1) [],
+    ],
+    INT [
+        ClearToSend OFFSET(1) NUMBITS(1) [],
+        Receive OFFSET(4) NUMBITS(1) [],
+        Transmit OFFSET(5) NUMBITS(1) [],
+        ReceiveTimeout OFFSET(6) NUMBITS(1) [],
+        FramingError OFFSET(7) NUMBITS(1) [],
+        ParityError OFFSET(8) NUMBITS(1) [],
+        BreakError OFFSET(9) NUMBITS(1) [],
+        OverrunError OFFSET(10) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: data (DR)
+    dr: ReadWrite<u32, DR::Register>,
+
+    /// 0x04: receive status and error clear (RSRECR)
+    _rsrecr: ReadWrite<u32>,
+    _reserved0: [u32; 4],
+
+    /// 0x18: flags (FR)
+    fr: ReadOnly<u32, FR::Register>,
+    _reserved1: [u32; 2],
+
+    /// 0x24: integer baud rate divisor (IBRD)
+    ibrd: ReadWrite<u32, IBRD::Register>,
+
+    /// 0x28: fractional baud rate divisor (FBRD)
+    fbrd: ReadWrite<u32, FBRD::Register>,
+
+    /// 0x2C: line control (LCRH)
+    lcrh: ReadWrite<u32, LCRH::Register>,
+
+    /// 0x30: control (CR)
+    cr: ReadWrite<u32, CR::Register>,
+
+    /// 0x34: interrupt FIFO level select (IFLS)
+    _ifls: ReadWrite<u32>,
+
+    /// 0x38: interrupt mask set/clear (IMSC)
+    imsc: ReadWrite<u32, INT::Register>,
+
+    /// 0x3C: raw interrupt status (RIS)
+    _ris: ReadOnly<u32, INT::Register>,
+
+    /// 0x40: masked interrupt status (MIS)
+    _mis: ReadOnly<u32, INT::Register>,
+
+    /// 0x44: interrupt clear (ICR)
+    icr: WriteOnly<u32, INT::Register>,
+}
+
+/// Reference clock the firmware sets up by default.
+pub const PL011_CLOCK: u32 = 48_000_000;
+
+/// Baud rate configured by `init()`.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Depth of the transmit and receive FIFOs.
+pub const FIFO_DEPTH: usize = 16;
+
+/// IBRD and FBRD values for `baud` with a reference clock of `clock`, i.e.
+/// `clock / (16 * baud)` in 16.6 fixed point, rounded to nearest.
+///
+/// Returns `None` if the integer part would be 0 or not fit in 16 bits.
+pub const fn baud_divisors(clock: u32, baud: u32) -> Option<(u32, u32)> {
+    if baud == 0 {
+        return None;
+    }
+    let divisor = (4 * clock as u64 + baud as u64 / 2) / baud as u64;
+    let integer = divisor >> 6;
+    if integer == 0 || integer > u16::MAX as u64 {
+        None
+    } else {
+        Some((integer as u32, divisor as u32 & 0x3F))
+    }
+}
+
+/// The PL011 at offset 0x201000 of the peripheral window.
+pub struct Pl011 {
+    registers: StaticRef<Registers>,
+    clock: u32,
+    soc: Soc,
+}
+
+impl Pl011 {
+    pub unsafe fn new() -> Pl011 {
+        Pl011::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Pl011 {
+        Pl011 {
+            registers: StaticRef::new((soc.peripheral_base + 0x201000) as *const Registers),
+            clock: PL011_CLOCK,
+            soc: *soc,
+        }
+    }
+
+    /// Sets the reference clock (in Hz) used for subsequent baud rate
+    /// calculations.
+    pub fn set_clock(&mut self, clock: u32) {
+        self.clock = clock;
+    }
+
+    /// Routes the PL011 to GPIO14/15 and configures it for 8N1 at
+    /// `DEFAULT_BAUD_RATE` with the FIFOs on and interrupts masked.
+    pub fn init(&mut self) {
+        // Safety: GPIO14/15 are handed over to the PL011.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in [TXD_PIN, RXD_PIN] {
+            gpio.set_function(pin, Function::Alt0);
+            gpio.set_pull(pin, Pull::None);
+        }
+
+        // The line settings must not change while the UART is enabled or
+        // busy; disabling the FIFOs also discards what is in them.
+        self.registers.cr.set(0);
+        while self.registers.fr.is_set(FR::Busy) {}
+        self.registers.lcrh.set(0);
+        self.registers.imsc.set(0);
+        self.registers.icr.set(0x7FF);
+        let _ = self.set_baud_rate(DEFAULT_BAUD_RATE);
+        self.registers
+            .lcrh
+            .write(LCRH::WordLength::Bits8 + LCRH::FifoEnable::SET);
+        self.registers
+            .cr
+            .write(CR::Enable::SET + CR::TransmitEnable::SET + CR::ReceiveEnable::SET);
+    }
+
+    /// Programs the divisors for `baud`. They take effect on the next LCRH
+    /// write, so call this before `init` finishes or follow it with one.
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<(), ErrorCode> {
+        let (integer, fraction) = baud_divisors(self.clock, baud).ok_or(ErrorCode::INVAL)?;
+        self.registers.ibrd.write(IBRD::Divisor.val(integer));
+        self.registers.fbrd.write(FBRD::Fraction.val(fraction));
+        Ok(())
+    }
+
+    /// Queues `byte` for transmission if the transmit FIFO has room.
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        if self.registers.fr.is_set(FR::TxFifoFull) {
+            false
+        } else {
+            self.registers.dr.set(byte as u32);
+            true
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        while !self.try_write_byte(byte) {}
+    }
+
+    pub fn write_bytes(&mut self, s: &[u8]) {
+        for byte in s {
+            self.write_byte(*byte);
+        }
+    }
+
+    /// Waits until every queued byte has left the wire.
+    pub fn flush(&mut self) {
+        while self.registers.fr.is_set(FR::Busy) {}
+    }
+
+    /// Returns the next received byte, or `None` if none is available.
+    /// Bytes received with a line error are returned as well.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if self.registers.fr.is_set(FR::RxFifoEmpty) {
+            None
+        } else {
+            Some(self.registers.dr.read(DR::Data) as u8)
+        }
+    }
+
+    pub fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return byte;
+            }
+        }
+    }
+}
+
+impl Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use core::cell::Cell;
+
+use crate::soc::Soc;
+
+use kernel::utilities::StaticRef;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::registers::ReadWrite;
+
+/// Every write to the power manager must carry this in bits 24-31, or the
+/// write is silently dropped.
+const PASSWORD: u32 = 0x5A00_0000;
+const PASSWORD_MASK: u32 = 0xFF00_0000;
+
+/// RSTC reset configuration field.
+const RSTC_WRCFG_MASK: u32 = 0x0000_0030;
+/// RSTC reset configuration: full reset when the watchdog expires.
+const RSTC_WRCFG_FULL_RESET: u32 = 0x0000_0020;
+/// RSTC value stopping the watchdog.
+const RSTC_RESET: u32 = 0x0000_0102;
+
+/// RSTS boot partition fields, spread over the even bits 0-10.
+const RSTS_PARTITION_MASK: u32 = 0x0000_0555;
+/// RSTS partition 63, which tells the firmware to halt instead of booting
+/// after the next reset.
+const RSTS_PARTITION_HALT: u32 = 0x0000_0555;
+
+/// Widest watchdog timeout, in ticks of 16 us.
+pub const WDOG_MAX_TICKS: u32 = 0x000F_FFFF;
+
+/// Watchdog ticks per second. The nominal "16 us" tick is really 15.26 us.
+pub const WDOG_TICKS_PER_SECOND: u32 = 65536;
+
+/// Watchdog ticks for `ms` milliseconds, clamped to `WDOG_MAX_TICKS`, just
+/// under 16 s.
+pub const fn watchdog_ticks(ms: u32) -> u32 {
+    let ticks = ms as u64 * WDOG_TICKS_PER_SECOND as u64 / 1000;
+    if ticks > WDOG_MAX_TICKS as u64 {
+        WDOG_MAX_TICKS
+    } else {
+        ticks as u32
+    }
+}
+
+/// Timeout used by `reset`, short enough to be effectively immediate.
+const RESET_TICKS: u32 = 10;
+
+/// A power manager register. Writes always carry the password, so it cannot
+/// be forgotten.
+#[repr(transparent)]
+struct PmRegister(ReadWrite<u32>);
+
+impl PmRegister {
+    fn get(&self) -> u32 {
+        self.0.get() & !PASSWORD_MASK
+    }
+
+    fn set(&self, value: u32) {
+        self.0.set(PASSWORD | (value & !PASSWORD_MASK));
+    }
+}
+
+#[repr(C)]
+struct Registers {
+    _reserved0: [u8; 0x1C],
+
+    /// 0x1C: reset control (PM_RSTC)
+    rstc: PmRegister,
+
+    /// 0x20: reset status (PM_RSTS)
+    rsts: PmRegister,
+
+    /// 0x24: watchdog timeout (PM_WDOG)
+    wdog: PmRegister,
+}
+
+/// The power manager at offset 0x100000 of the peripheral window, used for
+/// its watchdog.
+pub struct PowerManager {
+    registers: StaticRef<Registers>,
+    /// Timeout reloaded by `watchdog_kick`.
+    watchdog_ticks: Cell<u32>,
+}
+
+impl PowerManager {
+    pub unsafe fn new() -> PowerManager {
+        PowerManager::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> PowerManager {
+        PowerManager {
+            registers: StaticRef::new((soc.peripheral_base + 0x100000) as *const Registers),
+            watchdog_ticks: Cell::new(0),
+        }
+    }
+
+    /// Resets the whole SoC.
+    pub fn reset(&self) -> ! {
+        self.arm(RESET_TICKS);
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Shuts the board down the way Linux does: selects boot partition 63
+    /// and resets, after which the firmware halts instead of booting.
+    ///
+    /// Whether that really cuts the power depends on the board. Boards
+    /// without a power controller the firmware can switch off, including
+    /// the Pi 3 models, keep drawing power with the ARM cores halted; only
+    /// unplugging, or a reset through the RUN pads, brings them back.
+    pub fn power_off(&self) -> ! {
+        let rsts = self.registers.rsts.get() & !RSTS_PARTITION_MASK;
+        self.registers.rsts.set(rsts | RSTS_PARTITION_HALT);
+        self.reset()
+    }
+
+    /// Resets the SoC unless `watchdog_kick` is called at least every
+    /// `ticks` ticks of 16 us. `ticks` is clamped to `WDOG_MAX_TICKS`.
+    pub fn watchdog_start(&self, ticks: u32) {
+        let ticks = ticks.min(WDOG_MAX_TICKS);
+        self.watchdog_ticks.set(ticks);
+        self.arm(ticks);
+    }
+
+    /// Restarts the watchdog timeout.
+    pub fn watchdog_kick(&self) {
+        self.registers.wdog.set(self.watchdog_ticks.get());
+    }
+
+    pub fn watchdog_stop(&self) {
+        self.registers.rstc.set(RSTC_RESET);
+    }
+
+    fn arm(&self, ticks: u32) {
+        self.registers.wdog.set(ticks & WDOG_MAX_TICKS);
+        let rstc = self.registers.rstc.get() & !RSTC_WRCFG_MASK;
+        self.registers.rstc.set(rstc | RSTC_WRCFG_FULL_RESET);
+    }
+}
+
+/// Resets the board if boot does not reach `boot_complete` in time, for
+/// boards left unattended.
+///
+/// The timeout has the watchdog's granularity of one tick, about 16 us, and
+/// cannot exceed `WDOG_MAX_TICKS`, just under 16 s; longer timeouts are
+/// clamped.
+pub struct BootWatchdog(PowerManager);
+
+impl BootWatchdog {
+    /// Starts the watchdog with a timeout of `timeout_ms` milliseconds.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may use the watchdog until `boot_complete`.
+    pub unsafe fn arm(timeout_ms: u32) -> BootWatchdog {
+        let power = PowerManager::new();
+        power.watchdog_start(watchdog_ticks(timeout_ms));
+        BootWatchdog(power)
+    }
+
+    /// Stops the watchdog, once the board is up.
+    pub fn boot_complete(self) {
+        self.0.watchdog_stop();
+    }
+}
@@ -0,0 +1,203 @@
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::soc::Soc;
+
+use kernel::utilities::StaticRef;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CTL [
+        Enable1 OFFSET(0) NUMBITS(1) [],
+        Serializer1 OFFSET(1) NUMBITS(1) [],
+        Polarity1 OFFSET(4) NUMBITS(1) [],
+        UseFifo1 OFFSET(5) NUMBITS(1) [],
+        ClearFifo OFFSET(6) NUMBITS(1) [],
+        MarkSpace1 OFFSET(7) NUMBITS(1) [],
+        Enable2 OFFSET(8) NUMBITS(1) [],
+        Serializer2 OFFSET(9) NUMBITS(1) [],
+        Polarity2 OFFSET(12) NUMBITS(1) [],
+        UseFifo2 OFFSET(13) NUMBITS(1) [],
+        MarkSpace2 OFFSET(15) NUMBITS(1) [],
+    ],
+    CM_CTL [
+        Source OFFSET(0) NUMBITS(4) [
+            Oscillator = 1,
+        ],
+        Enable OFFSET(4) NUMBITS(1) [],
+        Kill OFFSET(5) NUMBITS(1) [],
+        Busy OFFSET(7) NUMBITS(1) [],
+        Password OFFSET(24) NUMBITS(8) [
+            Password = 0x5A,
+        ],
+    ],
+    CM_DIV [
+        Fraction OFFSET(0) NUMBITS(12) [],
+        Integer OFFSET(12) NUMBITS(12) [],
+        Password OFFSET(24) NUMBITS(8) [
+            Password = 0x5A,
+        ],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: control (CTL)
+    ctl: ReadWrite<u32, CTL::Register>,
+
+    /// 0x04: status (STA)
+    _sta: ReadWrite<u32>,
+
+    /// 0x08: DMA configuration (DMAC)
+    _dmac: ReadWrite<u32>,
+    _reserved0: u32,
+
+    /// 0x10: channel 1 range (RNG1)
+    rng1: ReadWrite<u32>,
+
+    /// 0x14: channel 1 data (DAT1)
+    dat1: ReadWrite<u32>,
+
+    /// 0x18: FIFO input (FIF1)
+    _fif1: ReadWrite<u32>,
+    _reserved1: u32,
+
+    /// 0x20: channel 2 range (RNG2)
+    rng2: ReadWrite<u32>,
+
+    /// 0x24: channel 2 data (DAT2)
+    dat2: ReadWrite<u32>,
+}
+
+/// The PWM clock in the clock manager, at offset 0x1010A0 of the peripheral
+/// window.
+#[repr(C)]
+struct ClockRegisters {
+    /// 0x00: control (CM_PWMCTL)
+    ctl: ReadWrite<u32, CM_CTL::Register>,
+
+    /// 0x04: divisor (CM_PWMDIV)
+    div: ReadWrite<u32, CM_DIV::Register>,
+}
+
+/// Channel 1 and 2 outputs, both alt5.
+const PINS: [usize; 2] = [18, 19];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Pwm1,
+    Pwm2,
+}
+
+/// How a channel spreads `data` high cycles over each `range` cycles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// The output is high for `data` cycles, then low for the rest of the
+    /// period, as servos expect.
+    MarkSpace,
+    /// The high cycles are spread as evenly as possible over the period,
+    /// which is better for filtering into an analog level.
+    Balanced,
+}
+
+/// PWM with two channels clocked from the 19.2 MHz oscillator.
+pub struct Pwm {
+    registers: StaticRef<Registers>,
+    clock: StaticRef<ClockRegisters>,
+    soc: Soc,
+}
+
+impl Pwm {
+    pub unsafe fn new() -> Pwm {
+        Pwm::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Pwm {
+        Pwm {
+            registers: StaticRef::new((soc.peripheral_base + 0x20C000) as *const Registers),
+            clock: StaticRef::new((soc.peripheral_base + 0x1010A0) as *const ClockRegisters),
+            soc: *soc,
+        }
+    }
+
+    /// Routes both channels to GPIO18/19.
+    pub fn init(&mut self) {
+        // Safety: GPIO18/19 belong to the PWM.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in PINS {
+            gpio.set_function(pin, Function::Alt5);
+        }
+    }
+
+    /// Sets the PWM clock to the oscillator divided by `divisor`, which is
+    /// 12 bits wide.
+    ///
+    /// The clock manager must not be reprogrammed while the clock runs, so
+    /// this stops the PWM and its clock, waits for BUSY to clear, writes the
+    /// divisor, and only then restarts the clock. Channels that were enabled
+    /// are re-enabled afterwards.
+    pub fn set_clock(&mut self, divisor: u16) {
+        let ctl = self.registers.ctl.get();
+        self.registers
+            .ctl
+            .modify(CTL::Enable1::CLEAR + CTL::Enable2::CLEAR);
+
+        self.clock
+            .ctl
+            .write(CM_CTL::Password::Password + CM_CTL::Kill::SET);
+        while self.clock.ctl.is_set(CM_CTL::Busy) {}
+
+        self.clock
+            .div
+            .write(CM_DIV::Password::Password + CM_DIV::Integer.val(divisor as u32 & 0xFFF));
+        self.clock
+            .ctl
+            .write(CM_CTL::Password::Password + CM_CTL::Source::Oscillator);
+        self.clock
+            .ctl
+            .write(CM_CTL::Password::Password + CM_CTL::Source::Oscillator + CM_CTL::Enable::SET);
+        while !self.clock.ctl.is_set(CM_CTL::Busy) {}
+
+        self.registers.ctl.set(ctl);
+    }
+
+    /// Sets the period of both channels to `range` PWM clock cycles.
+    pub fn set_period(&mut self, range: u32) {
+        self.registers.rng1.set(range);
+        self.registers.rng2.set(range);
+    }
+
+    /// Sets how many cycles of each period `channel` is high.
+    pub fn set_duty(&mut self, channel: Channel, data: u32) {
+        match channel {
+            Channel::Pwm1 => self.registers.dat1.set(data),
+            Channel::Pwm2 => self.registers.dat2.set(data),
+        }
+    }
+
+    pub fn set_mode(&mut self, channel: Channel, mode: Mode) {
+        let mark_space = (mode == Mode::MarkSpace) as u32;
+        self.registers.ctl.modify(match channel {
+            Channel::Pwm1 => CTL::MarkSpace1.val(mark_space),
+            Channel::Pwm2 => CTL::MarkSpace2.val(mark_space),
+        });
+    }
+
+    pub fn enable(&mut self, channel: Channel) {
+        self.registers.ctl.modify(match channel {
+            Channel::Pwm1 => CTL::Enable1::SET,
+            Channel::Pwm2 => CTL::Enable2::SET,
+        });
+    }
+
+    pub fn disable(&mut self, channel: Channel) {
+        self.registers.ctl.modify(match channel {
+            Channel::Pwm1 => CTL::Enable1::CLEAR,
+            Channel::Pwm2 => CTL::Enable2::CLEAR,
+        });
+    }
+}
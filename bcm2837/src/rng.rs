@@ -0,0 +1,245 @@
+use core::cell::Cell;
+
+use crate::soc::Soc;
+
+use kernel::deferred_call::DeferredCall;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::hil::entropy;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CTRL [
+        Enable OFFSET(0) NUMBITS(1) [],
+    ],
+    STATUS [
+        WarmUpCount OFFSET(0) NUMBITS(20) [],
+        WordsAvailable OFFSET(24) NUMBITS(8) [],
+    ],
+    INT_MASK [
+        Disable OFFSET(0) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: control (RNG_CTRL)
+    ctrl: ReadWrite<u32, CTRL::Register>,
+
+    /// 0x04: status (RNG_STATUS)
+    status: ReadWrite<u32, STATUS::Register>,
+
+    /// 0x08: data (RNG_DATA)
+    data: ReadOnly<u32>,
+    _reserved0: u32,
+
+    /// 0x10: interrupt mask (RNG_INT_MASK)
+    int_mask: ReadWrite<u32, INT_MASK::Register>,
+}
+
+/// Number of words the generator discards after being enabled.
+///
+/// The output right after enabling has not accumulated enough noise yet, so
+/// the hardware is told to throw it away before reporting words available.
+const WARM_UP_COUNT: u32 = 0x40000;
+
+/// Hardware random number generator.
+pub struct Rng<'a> {
+    registers: StaticRef<Registers>,
+    client: OptionalCell<&'a dyn entropy::Client32>,
+    requested: Cell<bool>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> Rng<'a> {
+    pub unsafe fn new() -> Rng<'a> {
+        Rng::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Rng<'a> {
+        Rng {
+            registers: StaticRef::new((soc.peripheral_base + 0x104000) as *const Registers),
+            client: OptionalCell::empty(),
+            requested: Cell::new(false),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn init(&self) {
+        if self.registers.ctrl.is_set(CTRL::Enable) {
+            return;
+        }
+        self.registers
+            .status
+            .write(STATUS::WarmUpCount.val(WARM_UP_COUNT));
+        self.registers.int_mask.modify(INT_MASK::Disable::SET);
+        self.registers.ctrl.modify(CTRL::Enable::SET);
+    }
+
+    /// Waits for and returns the next random word.
+    pub fn next_u32(&self) -> u32 {
+        while self.registers.status.read(STATUS::WordsAvailable) == 0 {}
+        self.registers.data.get()
+    }
+
+    /// Fills `buf` with hardware random bytes, waiting for the generator as
+    /// needed.
+    pub fn fill(&self, buf: &mut [u8]) {
+        self.init();
+        for chunk in buf.chunks_mut(4) {
+            let word = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+}
+
+/// xorshift64* generator, for when a fast stream that only has to look
+/// random is enough. Not suitable for keys or anything else an attacker may
+/// want to predict: the state is recoverable from a few outputs.
+///
+/// The same seed always gives the same stream.
+pub struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    /// A generator seeded with `seed`. xorshift gets stuck at zero, so a
+    /// zero seed is replaced by a fixed non-zero one.
+    pub const fn new(seed: u64) -> Prng {
+        Prng {
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// A generator seeded from the hardware generator.
+    pub fn from_rng(rng: &Rng) -> Prng {
+        rng.init();
+        Prng::new(((rng.next_u32() as u64) << 32) | rng.next_u32() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// The high half of `next_u64`, the better half of xorshift64*.
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let word = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+}
+
+/// Endless iterator over hardware random words handed to entropy clients.
+struct RngIter<'a, 'b: 'a>(&'a Rng<'b>);
+
+impl Iterator for RngIter<'_, '_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        Some(self.0.next_u32())
+    }
+}
+
+impl<'a> entropy::Entropy32<'a> for Rng<'a> {
+    fn get(&self) -> Result<(), ErrorCode> {
+        self.init();
+        self.requested.set(true);
+        // The generator is fast enough to serve requests synchronously, but
+        // the callback must not be issued from within `get`.
+        self.deferred_call.set();
+        Ok(())
+    }
+
+    fn cancel(&self) -> Result<(), ErrorCode> {
+        self.requested.set(false);
+        Ok(())
+    }
+
+    fn set_client(&'a self, client: &'a dyn entropy::Client32) {
+        self.client.set(client);
+    }
+}
+
+impl DeferredCallClient for Rng<'_> {
+    fn handle_deferred_call(&self) {
+        if !self.requested.replace(false) {
+            return;
+        }
+        self.client.map(|client| {
+            if let entropy::Continue::More = client.entropy_available(&mut RngIter(self), Ok(())) {
+                self.requested.set(true);
+                self.deferred_call.set();
+            }
+        });
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTRL_OFFSET: usize = 0x104000;
+    const STATUS_OFFSET: usize = 0x104004;
+    const INT_MASK_OFFSET: usize = 0x104010;
+
+    #[test]
+    fn init_discards_the_warm_up_words_once() {
+        let soc = Soc::in_memory();
+        let rng = unsafe { Rng::for_soc(&soc) };
+        rng.init();
+        assert_eq!(soc.read(STATUS_OFFSET), WARM_UP_COUNT);
+        assert_eq!(soc.read(INT_MASK_OFFSET), 1);
+        assert_eq!(soc.read(CTRL_OFFSET), 1);
+
+        // Already running: the count the hardware has reached stays.
+        soc.write(STATUS_OFFSET, 0x1234);
+        rng.init();
+        assert_eq!(soc.read(STATUS_OFFSET), 0x1234);
+    }
+
+    #[test]
+    fn prng_is_reproducible_from_its_seed() {
+        let mut a = Prng::new(0x1234_5678_9ABC_DEF0);
+        let mut b = Prng::new(0x1234_5678_9ABC_DEF0);
+        let mut c = Prng::new(0x1234_5678_9ABC_DEF1);
+        let stream: Vec<u64> = (0..16).map(|_| a.next_u64()).collect();
+        assert_eq!(stream, (0..16).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(stream, (0..16).map(|_| c.next_u64()).collect::<Vec<_>>());
+
+        let mut bytes = [0; 12];
+        Prng::new(0x1234_5678_9ABC_DEF0).fill(&mut bytes);
+        assert_eq!(bytes[..8], stream[0].to_le_bytes());
+        assert_eq!(bytes[8..], stream[1].to_le_bytes()[..4]);
+    }
+
+    #[test]
+    fn prng_zero_seed_does_not_get_stuck() {
+        let mut prng = Prng::new(0);
+        assert_ne!(prng.next_u64(), 0);
+        assert_ne!(prng.next_u64(), 0);
+    }
+}
@@ -0,0 +1,42 @@
+//! Read-modify-write of registers that IRQ handlers may touch too.
+//!
+//! A `modify` reads the register, changes some bits and writes it back, so
+//! an IRQ handler writing the same register in between has its update lost.
+//! The registers this matters for are those shared between drivers, or
+//! between a driver and its own interrupt handler:
+//!
+//! - AUX_ENABLES, one enable bit each for the Mini UART, SPI1 and SPI2.
+//! - The Mini UART's AUX_MU_IER_REG, which `handle_interrupt` changes to
+//!   stop transmit interrupts.
+//! - GPFSELn, ten pins per register, and the GPREN/GPFEN/GPHEN/GPLEN event
+//!   enables, 32 pins per register.
+//!
+//! Registers only one driver writes, from thread context, are modified
+//! directly. Masking IRQs costs a couple of instructions, so the guard is
+//! taken whether or not the driver has interrupts enabled.
+
+use tock_registers::fields::FieldValue;
+use tock_registers::interfaces::ReadWriteable;
+
+/// Runs `f` with IRQs masked on this core.
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    // Safety: the previous mask is restored before returning.
+    unsafe {
+        let masked = cortex_a::exceptions::save_and_disable_interrupts();
+        let result = f();
+        cortex_a::exceptions::restore_interrupts(masked);
+        result
+    }
+}
+
+/// Runs `f`. There are no IRQs to mask when not built for the SoC.
+#[cfg(not(target_arch = "aarch64"))]
+pub(crate) fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
+
+/// `register.modify(value)`, with IRQs masked so the update cannot be lost.
+pub(crate) fn modify<R: ReadWriteable>(register: &R, value: FieldValue<R::T, R::R>) {
+    without_interrupts(|| register.modify(value));
+}
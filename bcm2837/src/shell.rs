@@ -0,0 +1,275 @@
+//! Bring-up shell on the Mini UART.
+//!
+//! `run` reads lines with `UART::read_line` and looks the first word up in
+//! `COMMANDS`. Numbers are hex, with or without `0x`, except GPIO pin
+//! numbers, which are decimal.
+//!
+//! `peek`, `dump` and `poke` only take word-aligned addresses below the end
+//! of the peripheral window, i.e. what the MMU maps, and refuse the kernel's
+//! stack guard page, so a typo does not fault or hang the bus.
+
+use core::fmt::Write;
+use core::str;
+
+use cortex_a::stack;
+
+use crate::debug;
+use crate::gpio;
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::mailbox::Mailbox;
+use crate::power::PowerManager;
+use crate::soc::Soc;
+use crate::uart;
+use crate::uart::UART;
+
+pub struct Command {
+    pub name: &'static str,
+    /// Argument syntax, for `help`.
+    pub usage: &'static str,
+    pub run: fn(&mut UART, &[&str]) -> Result<(), &'static str>,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "",
+        run: help,
+    },
+    Command {
+        name: "peek",
+        usage: "<address>",
+        run: peek,
+    },
+    Command {
+        name: "dump",
+        usage: "<address> <words>",
+        run: dump,
+    },
+    Command {
+        name: "poke",
+        usage: "<address> <value>",
+        run: poke,
+    },
+    Command {
+        name: "gpio",
+        usage: "<pin> <0|1>",
+        run: set_gpio,
+    },
+    Command {
+        name: "temp",
+        usage: "",
+        run: temp,
+    },
+    #[cfg(feature = "profiling")]
+    Command {
+        name: "latency",
+        usage: "[clear]",
+        run: latency,
+    },
+    Command {
+        name: "reboot",
+        usage: "",
+        run: reboot,
+    },
+    Command {
+        name: "poweroff",
+        usage: "",
+        run: poweroff,
+    },
+];
+
+/// Most arguments a command takes.
+const MAX_ARGS: usize = 2;
+
+/// Runs the shell on `uart` for good.
+pub fn run(uart: &mut UART) -> ! {
+    let mut line = [0u8; 80];
+    loop {
+        let _ = uart.write_str("> ");
+        let len = uart.read_line(&mut line);
+        // `read_line` only stores printable ASCII.
+        let text = str::from_utf8(&line[..len]).unwrap_or("");
+        let mut words = text.split_ascii_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let mut args = [""; MAX_ARGS];
+        let mut count = 0;
+        for word in words {
+            if count < MAX_ARGS {
+                args[count] = word;
+            }
+            count += 1;
+        }
+
+        let result = match COMMANDS.iter().find(|command| command.name == name) {
+            Some(_) if count > MAX_ARGS => Err("too many arguments"),
+            Some(command) => (command.run)(uart, &args[..count]),
+            None => Err("unknown command, try help"),
+        };
+        if let Err(error) = result {
+            let _ = write!(uart, "error: {}\r\n", error);
+        }
+    }
+}
+
+fn parse_hex(arg: &str) -> Result<u64, &'static str> {
+    let digits = arg
+        .strip_prefix("0x")
+        .or_else(|| arg.strip_prefix("0X"))
+        .unwrap_or(arg);
+    u64::from_str_radix(digits, 16).map_err(|_| "bad hex number")
+}
+
+/// The word-aligned `arg` as an address `peek` and `poke` may use.
+fn checked_address(arg: &str) -> Result<usize, &'static str> {
+    check_address(parse_hex(arg)?)
+}
+
+fn check_address(address: u64) -> Result<usize, &'static str> {
+    let soc = Soc::detect();
+    if address >= (soc.peripheral_base + soc.peripheral_size) as u64 {
+        return Err("address not mapped");
+    }
+    if address % 4 != 0 {
+        return Err("address not word aligned");
+    }
+    if stack::is_guard_fault(address) {
+        return Err("address in the stack guard page");
+    }
+    Ok(address as usize)
+}
+
+fn help(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    if !args.is_empty() {
+        return Err("help takes no arguments");
+    }
+    for command in COMMANDS {
+        let _ = write!(uart, "{} {}\r\n", command.name, command.usage);
+    }
+    Ok(())
+}
+
+fn peek(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    let [address] = args else {
+        return Err("usage: peek <address>");
+    };
+    let address = checked_address(address)?;
+    // Safety: the address is word aligned and mapped.
+    let value = unsafe { core::ptr::read_volatile(address as *const u32) };
+    let _ = write!(uart, "{:#010x}: {:#010x}\r\n", address, value);
+    Ok(())
+}
+
+/// Most words `dump` prints, less than a page so that checking both ends
+/// is enough to keep clear of the guard page.
+const MAX_DUMP_WORDS: usize = 256;
+
+fn dump(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    let [address, count] = args else {
+        return Err("usage: dump <address> <words>");
+    };
+    let address = checked_address(address)?;
+    let count = parse_hex(count)? as usize;
+    if count == 0 || count > MAX_DUMP_WORDS {
+        return Err("word count out of range");
+    }
+    check_address((address + 4 * (count - 1)) as u64)?;
+    // Safety: both ends of the range are mapped and outside the guard
+    // page, and the range is shorter than a page.
+    unsafe { debug::dump_registers(uart, address, count) };
+    Ok(())
+}
+
+fn poke(_uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    let [address, value] = args else {
+        return Err("usage: poke <address> <value>");
+    };
+    let address = checked_address(address)?;
+    let value = u32::try_from(parse_hex(value)?).map_err(|_| "value does not fit 32 bits")?;
+    // Safety: the address is word aligned and mapped. What the write does
+    // is up to whoever typed it.
+    unsafe { core::ptr::write_volatile(address as *mut u32, value) };
+    Ok(())
+}
+
+fn set_gpio(_uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    let [pin, level] = args else {
+        return Err("usage: gpio <pin> <0|1>");
+    };
+    let pin: usize = pin.parse().map_err(|_| "bad pin number")?;
+    if pin >= gpio::NUM_PINS {
+        return Err("no such pin");
+    }
+    if pin == uart::TXD_PIN || pin == uart::RXD_PIN {
+        return Err("pin belongs to the console");
+    }
+    // Safety: the user asked for the pin.
+    let pins = unsafe { Gpio::new() };
+    match *level {
+        "0" => pins.set_low(pin),
+        "1" => pins.set_high(pin),
+        _ => return Err("level must be 0 or 1"),
+    }
+    pins.set_function(pin, Function::Output);
+    Ok(())
+}
+
+fn temp(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    if !args.is_empty() {
+        return Err("temp takes no arguments");
+    }
+    // Safety: only a property query is made.
+    let (degrees, tenths) = unsafe { Mailbox::new() }
+        .temperature_celsius()
+        .map_err(|_| "mailbox call failed")?;
+    let _ = write!(uart, "{}.{} C\r\n", degrees, tenths);
+    Ok(())
+}
+
+/// Prints the System Timer latency samples, or drops them with `clear`.
+#[cfg(feature = "profiling")]
+fn latency(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        [] => {}
+        ["clear"] => {
+            crate::latency::clear();
+            return Ok(());
+        }
+        _ => return Err("usage: latency [clear]"),
+    }
+    let mut count = 0;
+    crate::latency::for_each_sample(|sample| {
+        let _ = write!(
+            uart,
+            "deadline {:#018x} actual {:#018x} {} ns\r\n",
+            sample.deadline,
+            sample.actual,
+            sample.latency_ns()
+        );
+        count += 1;
+    });
+    if count == 0 {
+        let _ = write!(uart, "no samples\r\n");
+    }
+    Ok(())
+}
+
+fn reboot(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    if !args.is_empty() {
+        return Err("reboot takes no arguments");
+    }
+    uart.flush();
+    // Safety: the SoC goes away, so nothing can observe a second instance.
+    unsafe { PowerManager::new() }.reset()
+}
+
+fn poweroff(uart: &mut UART, args: &[&str]) -> Result<(), &'static str> {
+    if !args.is_empty() {
+        return Err("poweroff takes no arguments");
+    }
+    uart.flush();
+    // Safety: as for `reboot`.
+    unsafe { PowerManager::new() }.power_off()
+}
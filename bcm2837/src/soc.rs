@@ -0,0 +1,106 @@
+//! Boot-time detection of the SoC.
+//!
+//! The peripheral window cannot be found through the mailbox, which itself
+//! sits in it, so `Soc::detect` goes by the CPU core in MIDR_EL1: the
+//! BCM2837 (Pi 3B, 3B+ and QEMU's `raspi3b`) has Cortex-A53 cores, the
+//! BCM2711 (Pi 4) Cortex-A72 ones. If the core is neither, detection falls
+//! back to the compile-time `PERIPHERAL_BASE` and `uart::UART_CLOCK`, i.e.
+//! to the SoC the `bcm2711` feature selects.
+//!
+//! Each driver's `new` uses the detected window; `for_soc` takes a `Soc`
+//! for code that already has one.
+
+use crate::uart;
+use crate::BCM2711_PERIPHERAL_BASE;
+use crate::BCM2711_PERIPHERAL_SIZE;
+use crate::BCM2837_PERIPHERAL_BASE;
+use crate::BCM2837_PERIPHERAL_SIZE;
+use crate::PERIPHERAL_BASE;
+use crate::PERIPHERAL_SIZE;
+
+/// MIDR_EL1.PartNum of the Cortex-A53.
+const CORTEX_A53: u64 = 0xD03;
+/// MIDR_EL1.PartNum of the Cortex-A72.
+const CORTEX_A72: u64 = 0xD08;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Bcm2837,
+    Bcm2711,
+    /// Not recognized; the compile-time addresses are used.
+    Unknown,
+}
+
+/// Addresses and clocks that differ between the SoCs.
+#[derive(Clone, Copy, Debug)]
+pub struct Soc {
+    pub model: Model,
+    /// Start of the peripheral window in the ARM physical address space.
+    pub peripheral_base: usize,
+    pub peripheral_size: usize,
+    /// Default Mini UART input clock, the VPU core clock.
+    pub uart_clock: u32,
+}
+
+impl Soc {
+    pub fn detect() -> Soc {
+        match part_number() {
+            Some(CORTEX_A53) => Soc {
+                model: Model::Bcm2837,
+                peripheral_base: BCM2837_PERIPHERAL_BASE,
+                peripheral_size: BCM2837_PERIPHERAL_SIZE,
+                uart_clock: uart::UART_CLOCK,
+            },
+            Some(CORTEX_A72) => Soc {
+                model: Model::Bcm2711,
+                peripheral_base: BCM2711_PERIPHERAL_BASE,
+                peripheral_size: BCM2711_PERIPHERAL_SIZE,
+                uart_clock: 500_000_000,
+            },
+            _ => Soc {
+                model: Model::Unknown,
+                peripheral_base: PERIPHERAL_BASE,
+                peripheral_size: PERIPHERAL_SIZE,
+                uart_clock: uart::UART_CLOCK,
+            },
+        }
+    }
+}
+
+/// MIDR_EL1.PartNum of the running core.
+fn part_number() -> Option<u64> {
+    #[cfg(target_arch = "aarch64")]
+    {
+        let midr: u64;
+        unsafe { core::arch::asm!("mrs {}, midr_el1", out(reg) midr, options(nomem, nostack)) };
+        Some((midr >> 4) & 0xFFF)
+    }
+    #[cfg(not(target_arch = "aarch64"))]
+    None
+}
+
+#[cfg(test)]
+impl Soc {
+    /// A BCM2837 whose peripheral window is zeroed host memory, so drivers
+    /// built with `for_soc` can be run against plain loads and stores.
+    pub(crate) fn in_memory() -> Soc {
+        let window = vec![0u64; BCM2837_PERIPHERAL_SIZE / 8].leak();
+        Soc {
+            model: Model::Bcm2837,
+            peripheral_base: window.as_mut_ptr() as usize,
+            peripheral_size: BCM2837_PERIPHERAL_SIZE,
+            uart_clock: uart::UART_CLOCK,
+        }
+    }
+
+    /// The register at `offset` in the window of an `in_memory` SoC.
+    pub(crate) fn read(&self, offset: usize) -> u32 {
+        assert!(offset < self.peripheral_size);
+        unsafe { core::ptr::read_volatile((self.peripheral_base + offset) as *const u32) }
+    }
+
+    pub(crate) fn write(&self, offset: usize, value: u32) {
+        assert!(offset < self.peripheral_size);
+        unsafe { core::ptr::write_volatile((self.peripheral_base + offset) as *mut u32, value) }
+    }
+}
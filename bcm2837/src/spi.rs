@@ -0,0 +1,455 @@
+use core::cell::Cell;
+
+use crate::dma;
+use crate::dma::ControlBlock;
+use crate::dma::Dma;
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::soc::Soc;
+
+use kernel::deferred_call::DeferredCall;
+use kernel::deferred_call::DeferredCallClient;
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::TakeCell;
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::ReadWriteable;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CS [
+        ChipSelect OFFSET(0) NUMBITS(2) [
+            CS0 = 0,
+            CS1 = 1,
+            CS2 = 2,
+        ],
+        CPHA OFFSET(2) NUMBITS(1) [],
+        CPOL OFFSET(3) NUMBITS(1) [],
+        Clear OFFSET(4) NUMBITS(2) [
+            Tx = 1,
+            Rx = 2,
+            Both = 3,
+        ],
+        CSPOL OFFSET(6) NUMBITS(1) [],
+        TransferActive OFFSET(7) NUMBITS(1) [],
+        DMAEnable OFFSET(8) NUMBITS(1) [],
+        InterruptOnDone OFFSET(9) NUMBITS(1) [],
+        InterruptOnRxr OFFSET(10) NUMBITS(1) [],
+        AutoDeassertCS OFFSET(11) NUMBITS(1) [],
+        Done OFFSET(16) NUMBITS(1) [],
+        RxData OFFSET(17) NUMBITS(1) [],
+        TxSpace OFFSET(18) NUMBITS(1) [],
+        RxNeedsReading OFFSET(19) NUMBITS(1) [],
+        RxFull OFFSET(20) NUMBITS(1) [],
+        CSPOL0 OFFSET(21) NUMBITS(1) [],
+        CSPOL1 OFFSET(22) NUMBITS(1) [],
+        CSPOL2 OFFSET(23) NUMBITS(1) [],
+    ],
+    CLK [
+        Divider OFFSET(0) NUMBITS(16) [],
+    ],
+    DC [
+        TxDreq OFFSET(0) NUMBITS(8) [],
+        TxPanic OFFSET(8) NUMBITS(8) [],
+        RxDreq OFFSET(16) NUMBITS(8) [],
+        RxPanic OFFSET(24) NUMBITS(8) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: control and status (CS)
+    cs: ReadWrite<u32, CS::Register>,
+
+    /// 0x04: TX and RX FIFOs (FIFO)
+    fifo: ReadWrite<u32>,
+
+    /// 0x08: clock divider (CLK)
+    clk: ReadWrite<u32, CLK::Register>,
+
+    /// 0x0C: data length for DMA mode (DLEN)
+    dlen: ReadWrite<u32>,
+
+    /// 0x10: LoSSI output hold delay (LTOH)
+    _ltoh: ReadWrite<u32>,
+
+    /// 0x14: DMA DREQ controls (DC)
+    dc: ReadWrite<u32, DC::Register>,
+}
+
+/// DMA peripheral map numbers of SPI0's DREQ lines.
+const DREQ_TX: u32 = 6;
+const DREQ_RX: u32 = 7;
+
+/// Shortest transfer `SpiDma` hands to the DMA engine. Setting up two
+/// channels costs more than polling a few bytes.
+pub const DMA_MIN_LENGTH: usize = 32;
+
+/// Interrupt controller line of SPI0. `Spi` is polled and `SpiDma` waits
+/// for the DMA channels, so neither enables it.
+pub const SPI_IRQ: u32 = 54;
+
+/// SPI0 signals on the header, all alt0: CE1, CE0, MISO, MOSI and SCLK.
+const PINS: [usize; 5] = [7, 8, 9, 10, 11];
+
+/// Clock polarity and phase, as in the usual SPI mode numbering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// CPOL = 0, CPHA = 0
+    Mode0,
+    /// CPOL = 0, CPHA = 1
+    Mode1,
+    /// CPOL = 1, CPHA = 0
+    Mode2,
+    /// CPOL = 1, CPHA = 1
+    Mode3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChipSelect {
+    Cs0,
+    Cs1,
+}
+
+/// Polled SPI0 master.
+pub struct Spi {
+    registers: StaticRef<Registers>,
+    soc: Soc,
+}
+
+impl Spi {
+    pub unsafe fn new() -> Spi {
+        Spi::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> Spi {
+        Spi {
+            registers: StaticRef::new((soc.peripheral_base + 0x204000) as *const Registers),
+            soc: *soc,
+        }
+    }
+
+    /// Routes SPI0 to GPIO7-11 and resets the controller.
+    pub fn init(&mut self) {
+        // Safety: GPIO7-11 belong to SPI0.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in PINS {
+            gpio.set_function(pin, Function::Alt0);
+        }
+        self.registers.cs.write(CS::Clear::Both);
+    }
+
+    /// Sets the clock mode and the divider of the core clock. The divider
+    /// is rounded down to an even value; 0 means 65536.
+    pub fn configure(&mut self, mode: Mode, clock_divider: u16) {
+        let (cpol, cpha) = match mode {
+            Mode::Mode0 => (CS::CPOL::CLEAR, CS::CPHA::CLEAR),
+            Mode::Mode1 => (CS::CPOL::CLEAR, CS::CPHA::SET),
+            Mode::Mode2 => (CS::CPOL::SET, CS::CPHA::CLEAR),
+            Mode::Mode3 => (CS::CPOL::SET, CS::CPHA::SET),
+        };
+        self.registers.cs.modify(cpol + cpha);
+        self.registers
+            .clk
+            .write(CLK::Divider.val(clock_divider as u32 & !1));
+    }
+
+    pub fn select(&mut self, cs: ChipSelect) {
+        self.registers.cs.modify(match cs {
+            ChipSelect::Cs0 => CS::ChipSelect::CS0,
+            ChipSelect::Cs1 => CS::ChipSelect::CS1,
+        });
+    }
+
+    /// Sets whether `cs` is asserted high rather than low.
+    pub fn set_cs_active_high(&mut self, cs: ChipSelect, active_high: bool) {
+        let field = match cs {
+            ChipSelect::Cs0 => CS::CSPOL0,
+            ChipSelect::Cs1 => CS::CSPOL1,
+        };
+        self.registers.cs.modify(if active_high {
+            field.val(1)
+        } else {
+            field.val(0)
+        });
+    }
+
+    /// Clocks out `buffer` and replaces its contents with the bytes clocked
+    /// in at the same time.
+    pub fn transfer(&mut self, buffer: &mut [u8]) {
+        self.registers
+            .cs
+            .modify(CS::Clear::Both + CS::TransferActive::SET);
+
+        let mut tx = 0;
+        let mut rx = 0;
+        while rx < buffer.len() {
+            while tx < buffer.len() && self.registers.cs.is_set(CS::TxSpace) {
+                self.registers.fifo.set(buffer[tx] as u32);
+                tx += 1;
+            }
+            while rx < tx && self.registers.cs.is_set(CS::RxData) {
+                buffer[rx] = self.registers.fifo.get() as u8;
+                rx += 1;
+            }
+        }
+
+        while !self.registers.cs.is_set(CS::Done) {}
+        self.registers.cs.modify(CS::TransferActive::CLEAR);
+    }
+
+    /// Starts a DMA-paced transfer of `len` bytes. The FIFO is accessed a
+    /// word of four bytes at a time, and DLEN stops the transfer.
+    fn start_dma(&mut self, len: usize) {
+        self.registers.cs.modify(CS::Clear::Both);
+        self.registers.dlen.set(len as u32);
+        // Request TX data while the FIFO holds at most 32 bytes and RX
+        // reads once it holds 32, with the panic levels Linux uses.
+        self.registers.dc.write(
+            DC::TxDreq.val(0x20)
+                + DC::TxPanic.val(0x10)
+                + DC::RxDreq.val(0x20)
+                + DC::RxPanic.val(0x30),
+        );
+        self.registers
+            .cs
+            .modify(CS::DMAEnable::SET + CS::TransferActive::SET);
+    }
+
+    fn stop_dma(&mut self) {
+        self.registers
+            .cs
+            .modify(CS::DMAEnable::CLEAR + CS::TransferActive::CLEAR + CS::Clear::Both);
+    }
+}
+
+pub trait Client {
+    /// The transfer started by `SpiDma::transfer_dma` finished. `rx` holds
+    /// the bytes clocked in, unless `result` is an error.
+    fn transfer_done(
+        &self,
+        tx: &'static mut [u8],
+        rx: &'static mut [u8],
+        len: usize,
+        result: Result<(), ErrorCode>,
+    );
+}
+
+/// SPI0 master moving data with two DMA channels, one feeding the TX FIFO
+/// and one draining the RX FIFO, both paced by the controller's DREQs.
+///
+/// The DMA engine accesses the FIFO a word at a time, so only transfers of
+/// a multiple of four bytes, from and to word-aligned buffers, of at least
+/// `DMA_MIN_LENGTH` bytes go through it. Others, and those for which a
+/// channel is busy, are polled, with the callback still deferred.
+///
+/// Must be the client of the `Dma` it uses, and registered for its deferred
+/// call.
+pub struct SpiDma<'a> {
+    spi: MapCell<Spi>,
+    /// Physical address of the FIFO register, for the control blocks.
+    fifo_address: usize,
+    dma: &'a Dma<'a>,
+    tx_channel: usize,
+    rx_channel: usize,
+    tx_block: MapCell<ControlBlock>,
+    rx_block: MapCell<ControlBlock>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    /// The result of a finished transfer, for the deferred call to report.
+    result: OptionalCell<Result<(), ErrorCode>>,
+    client: OptionalCell<&'a dyn Client>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> SpiDma<'a> {
+    /// `spi` using `tx_channel` and `rx_channel` of `dma`.
+    pub fn new(spi: Spi, dma: &'a Dma<'a>, tx_channel: usize, rx_channel: usize) -> SpiDma<'a> {
+        SpiDma {
+            fifo_address: &spi.registers.fifo as *const ReadWrite<u32> as usize,
+            spi: MapCell::new(spi),
+            dma,
+            tx_channel,
+            rx_channel,
+            tx_block: MapCell::new(ControlBlock::new(0, 0, 0, 0)),
+            rx_block: MapCell::new(ControlBlock::new(0, 0, 0, 0)),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            result: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    /// The polled controller, for configuration. `None` during a transfer.
+    pub fn map_spi<R>(&self, f: impl FnOnce(&mut Spi) -> R) -> Option<R> {
+        if self.tx_buffer.is_some() {
+            return None;
+        }
+        self.spi.map(f)
+    }
+
+    fn can_dma(&self, tx: &[u8], rx: &[u8], len: usize) -> bool {
+        len >= DMA_MIN_LENGTH
+            && len % 4 == 0
+            && tx.as_ptr() as usize % 4 == 0
+            && rx.as_ptr() as usize % 4 == 0
+            && !self.dma.is_busy(self.tx_channel)
+            && !self.dma.is_busy(self.rx_channel)
+    }
+
+    /// Clocks out the first `len` bytes of `tx` while clocking in as many
+    /// into `rx`, and calls the client once done. `len` is clamped to the
+    /// shorter buffer. Fails with `OFF` if a polled transfer finds the
+    /// controller borrowed by `map_spi`.
+    pub fn transfer_dma(
+        &self,
+        tx: &'static mut [u8],
+        rx: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8], &'static mut [u8])> {
+        if self.tx_buffer.is_some() || self.result.is_some() {
+            return Err((ErrorCode::BUSY, tx, rx));
+        }
+        let len = len.min(tx.len()).min(rx.len());
+        self.len.set(len);
+
+        if !self.can_dma(tx, rx, len) {
+            let polled = self.spi.map(|spi| {
+                rx[..len].copy_from_slice(&tx[..len]);
+                spi.transfer(&mut rx[..len]);
+            });
+            if polled.is_none() {
+                return Err((ErrorCode::OFF, tx, rx));
+            }
+            self.tx_buffer.replace(tx);
+            self.rx_buffer.replace(rx);
+            self.result.set(Ok(()));
+            self.deferred_call.set();
+            return Ok(());
+        }
+
+        // The engine does not snoop the caches: push the outgoing bytes to
+        // memory, and make sure no dirty line over `rx` is written back on
+        // top of what the engine stores.
+        #[cfg(target_arch = "aarch64")]
+        {
+            cortex_a::cache::clean_range(tx.as_ptr() as usize, len);
+            cortex_a::cache::clean_invalidate_range(rx.as_ptr() as usize, len);
+        }
+
+        // Safety: the blocks live in `self` and the buffers are held until
+        // `finish`, which runs once both channels have stopped.
+        let started = self.rx_block.map(|block| {
+            *block = ControlBlock::peripheral_to_memory(self.fifo_address, DREQ_RX, &mut rx[..len]);
+            unsafe { self.dma.start_transfer(self.rx_channel, block) }
+        });
+        if let Some(Err(error)) = started {
+            return Err((error, tx, rx));
+        }
+        let started = self.tx_block.map(|block| {
+            *block = ControlBlock::memory_to_peripheral(&tx[..len], self.fifo_address, DREQ_TX);
+            unsafe { self.dma.start_transfer(self.tx_channel, block) }
+        });
+        if let Some(Err(error)) = started {
+            self.dma.abort(self.rx_channel);
+            return Err((error, tx, rx));
+        }
+
+        self.tx_buffer.replace(tx);
+        self.rx_buffer.replace(rx);
+        self.spi.map(|spi| spi.start_dma(len));
+        Ok(())
+    }
+
+    /// Stops the controller and both channels and hands the buffers back.
+    fn finish(&self, result: Result<(), ErrorCode>) {
+        self.dma.abort(self.tx_channel);
+        self.dma.abort(self.rx_channel);
+        self.spi.map(|spi| spi.stop_dma());
+        #[cfg(target_arch = "aarch64")]
+        self.rx_buffer.map(|rx| {
+            // Safety: the engine is done with `rx`, and any line the CPU
+            // pulled in meanwhile is stale.
+            unsafe { cortex_a::cache::invalidate_range(rx.as_ptr() as usize, self.len.get()) }
+        });
+        self.result.set(result);
+        self.deferred_call.set();
+    }
+}
+
+impl dma::Client for SpiDma<'_> {
+    fn transfer_done(&self, channel: usize, result: Result<(), ErrorCode>) {
+        if self.tx_buffer.is_none() || self.result.is_some() {
+            return;
+        }
+        // A finished TX channel just means the last words are in the FIFO;
+        // the transfer is done once they have been clocked and read back.
+        if channel == self.rx_channel || (channel == self.tx_channel && result.is_err()) {
+            self.finish(result);
+        }
+    }
+}
+
+impl DeferredCallClient for SpiDma<'_> {
+    fn handle_deferred_call(&self) {
+        let Some(result) = self.result.take() else {
+            return;
+        };
+        let (Some(tx), Some(rx)) = (self.tx_buffer.take(), self.rx_buffer.take()) else {
+            return;
+        };
+        self.client
+            .map(|client| client.transfer_done(tx, rx, self.len.get(), result));
+    }
+
+    fn register(&'static self) {
+        self.deferred_call.register(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CS_OFFSET: usize = 0x204000;
+    const CLK_OFFSET: usize = 0x204008;
+
+    #[test]
+    fn configure_sets_mode_and_even_divider() {
+        let soc = Soc::in_memory();
+        let mut spi = unsafe { Spi::for_soc(&soc) };
+
+        spi.configure(Mode::Mode3, 251);
+        assert_eq!(soc.read(CLK_OFFSET), 250);
+        assert_eq!(soc.read(CS_OFFSET), 0b1100);
+
+        spi.configure(Mode::Mode1, 0);
+        assert_eq!(soc.read(CLK_OFFSET), 0);
+        assert_eq!(soc.read(CS_OFFSET), 0b0100);
+    }
+
+    #[test]
+    fn cs_polarity_is_per_chip_select() {
+        let soc = Soc::in_memory();
+        let mut spi = unsafe { Spi::for_soc(&soc) };
+
+        spi.set_cs_active_high(ChipSelect::Cs1, true);
+        assert_eq!(soc.read(CS_OFFSET), 1 << 22);
+        spi.set_cs_active_high(ChipSelect::Cs0, true);
+        assert_eq!(soc.read(CS_OFFSET), 1 << 21 | 1 << 22);
+        spi.set_cs_active_high(ChipSelect::Cs1, false);
+        assert_eq!(soc.read(CS_OFFSET), 1 << 21);
+    }
+}
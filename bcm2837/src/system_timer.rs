@@ -0,0 +1,377 @@
+use core::cell::Cell;
+
+use crate::soc::Soc;
+
+use kernel::hil::time;
+use kernel::hil::time::Ticks;
+use kernel::hil::time::Time;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::Writeable;
+use tock_registers::register_bitfields;
+use tock_registers::registers::ReadOnly;
+use tock_registers::registers::ReadWrite;
+
+register_bitfields![u32,
+    CS [
+        M0 OFFSET(0) NUMBITS(1) [],
+        M1 OFFSET(1) NUMBITS(1) [],
+        M2 OFFSET(2) NUMBITS(1) [],
+        M3 OFFSET(3) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+struct Registers {
+    /// 0x00: control/status (CS), one match bit per compare channel
+    cs: ReadWrite<u32, CS::Register>,
+
+    /// 0x04: counter lower 32 bits (CLO)
+    clo: ReadOnly<u32>,
+
+    /// 0x08: counter higher 32 bits (CHI)
+    chi: ReadOnly<u32>,
+
+    /// 0x0C-0x18: compare (C0-C3)
+    compare: [ReadWrite<u32>; 4],
+}
+
+/// System timer compare channel. C0 and C2 are used by the VideoCore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Channel {
+    C0 = 0,
+    C1 = 1,
+    C2 = 2,
+    C3 = 3,
+}
+
+/// Interrupt controller line of compare channel C1.
+pub const C1_IRQ: u32 = 1;
+
+/// Interrupt controller line of compare channel C3.
+pub const C3_IRQ: u32 = 3;
+
+pub trait Client {
+    /// The compare on `channel` matched.
+    fn compare_fired(&self, channel: Channel);
+}
+
+/// The free-running 1 MHz System Timer.
+pub struct SystemTimer<'a> {
+    registers: StaticRef<Registers>,
+    client: OptionalCell<&'a dyn Client>,
+}
+
+impl<'a> SystemTimer<'a> {
+    pub unsafe fn new() -> SystemTimer<'a> {
+        SystemTimer::for_soc(&Soc::detect())
+    }
+
+    /// Like `new`, for the peripheral window of `soc`.
+    pub unsafe fn for_soc(soc: &Soc) -> SystemTimer<'a> {
+        SystemTimer {
+            registers: StaticRef::new((soc.peripheral_base + 0x3000) as *const Registers),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn Client) {
+        self.client.set(client);
+    }
+
+    /// Microseconds since the counter started.
+    pub fn now_us(&self) -> u64 {
+        read_split_counter(|| self.registers.chi.get(), || self.registers.clo.get())
+    }
+
+    /// The low 32 bits of `now_us`, from a single read of CLO.
+    ///
+    /// This wraps every 71.6 minutes, so it only suits intervals known to be
+    /// shorter, compared with `wrapping_sub`. In exchange it is one bus
+    /// access instead of three or four, and cannot tear.
+    pub fn now_us_lossy(&self) -> u32 {
+        self.registers.clo.get()
+    }
+
+    pub fn compare(&self, channel: Channel) -> u32 {
+        self.registers.compare[channel as usize].get()
+    }
+
+    /// Sets the raw compare value; the channel matches when CLO equals it.
+    pub fn set_compare_raw(&self, channel: Channel, value: u32) {
+        #[cfg(all(feature = "profiling", target_arch = "aarch64"))]
+        crate::latency::set_deadline(channel as usize, value.wrapping_sub(self.now_us_lossy()));
+        self.registers.compare[channel as usize].set(value);
+    }
+
+    /// Schedules a one-shot match on `channel` at `deadline_us`.
+    ///
+    /// Only C1 and C3 are available to the ARM. The comparator only sees
+    /// the low 32 bits of the counter, so deadlines that are already past or
+    /// more than `u32::MAX` microseconds (~71 minutes) away are rejected
+    /// with `INVAL`.
+    pub fn set_compare(&self, channel: Channel, deadline_us: u64) -> Result<(), ErrorCode> {
+        if channel != Channel::C1 && channel != Channel::C3 {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let now = self.now_us();
+        if deadline_us <= now || deadline_us - now > u32::MAX as u64 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.clear_match(channel);
+        self.set_compare_raw(channel, deadline_us as u32);
+        Ok(())
+    }
+
+    pub fn is_matched(&self, channel: Channel) -> bool {
+        self.registers.cs.get() & match_bit(channel) != 0
+    }
+
+    pub fn clear_match(&self, channel: Channel) {
+        // CS is write-1-to-clear, so only the given channel is affected.
+        self.registers.cs.set(match_bit(channel));
+    }
+
+    /// Clears and reports matches on C1 and C3. Call this when `C1_IRQ` or
+    /// `C3_IRQ` fires.
+    pub fn handle_interrupt(&self) {
+        for channel in [Channel::C1, Channel::C3] {
+            if self.is_matched(channel) {
+                #[cfg(all(feature = "profiling", target_arch = "aarch64"))]
+                crate::latency::matched(channel as usize);
+                self.clear_match(channel);
+                self.client.map(|client| client.compare_fired(channel));
+            }
+        }
+    }
+}
+
+/// Reads a 64-bit counter exposed as two 32-bit halves, e.g. CHI and CLO,
+/// without tearing.
+///
+/// The low half may wrap between reading the two. If the high half changed
+/// around the read of the low one, the wrap happened in between, and the
+/// low half read after it belongs with the new high half. This relies on
+/// the low half not wrapping twice during the reads.
+pub fn read_split_counter(
+    mut read_hi: impl FnMut() -> u32,
+    mut read_lo: impl FnMut() -> u32,
+) -> u64 {
+    let hi = read_hi();
+    let lo = read_lo();
+    let hi2 = read_hi();
+    if hi == hi2 {
+        (hi as u64) << 32 | lo as u64
+    } else {
+        (hi2 as u64) << 32 | read_lo() as u64
+    }
+}
+
+fn match_bit(channel: Channel) -> u32 {
+    match channel {
+        Channel::C0 => CS::M0::SET.value,
+        Channel::C1 => CS::M1::SET.value,
+        Channel::C2 => CS::M2::SET.value,
+        Channel::C3 => CS::M3::SET.value,
+    }
+}
+
+/// Minimum distance between now and an alarm, so that the compare is never
+/// set to a value CLO has already passed.
+const ALARM_MIN_DT: u32 = 10;
+
+/// Tock `Alarm` on one System Timer compare channel.
+///
+/// Register it as the `SystemTimer` client to receive compare matches.
+pub struct SystemTimerAlarm<'a> {
+    timer: &'a SystemTimer<'a>,
+    channel: Channel,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+}
+
+impl<'a> SystemTimerAlarm<'a> {
+    pub fn new(timer: &'a SystemTimer<'a>, channel: Channel) -> SystemTimerAlarm<'a> {
+        SystemTimerAlarm {
+            timer,
+            channel,
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl Client for SystemTimerAlarm<'_> {
+    fn compare_fired(&self, channel: Channel) {
+        if channel == self.channel && self.armed.get() {
+            self.armed.set(false);
+            self.client.map(|client| client.alarm());
+        }
+    }
+}
+
+impl time::Time for SystemTimerAlarm<'_> {
+    type Frequency = time::Freq1MHz;
+    type Ticks = time::Ticks32;
+
+    fn now(&self) -> Self::Ticks {
+        Self::Ticks::from(self.timer.registers.clo.get())
+    }
+}
+
+impl<'a> time::Counter<'a> for SystemTimerAlarm<'a> {
+    /// The counter never stops, and overflows are not reported.
+    fn set_overflow_client(&self, _client: &'a dyn time::OverflowClient) {}
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn is_running(&self) -> bool {
+        true
+    }
+}
+
+impl<'a> time::Alarm<'a> for SystemTimerAlarm<'a> {
+    fn set_alarm_client(&self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        let now = self.now();
+        let mut expire = reference.wrapping_add(dt);
+        // A deadline that has already passed fires as soon as possible.
+        if !now.within_range(reference, expire) {
+            expire = now;
+        }
+        let min = self.minimum_dt();
+        if expire.wrapping_sub(now) < min {
+            expire = now.wrapping_add(min);
+        }
+
+        self.timer.clear_match(self.channel);
+        self.armed.set(true);
+        self.timer.set_compare_raw(self.channel, expire.into_u32());
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        Self::Ticks::from(self.timer.compare(self.channel))
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        // The comparator cannot be turned off, so a disarmed alarm just
+        // ignores its next match.
+        self.armed.set(false);
+        self.timer.clear_match(self.channel);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        Self::Ticks::from(ALARM_MIN_DT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 64-bit counter behind CHI/CLO that advances by `step` on every
+    /// read of either half.
+    struct MockCounter {
+        now: Cell<u64>,
+        step: u64,
+    }
+
+    impl MockCounter {
+        fn read_hi(&self) -> u32 {
+            let now = self.now.get();
+            self.now.set(now + self.step);
+            (now >> 32) as u32
+        }
+
+        fn read_lo(&self) -> u32 {
+            let now = self.now.get();
+            self.now.set(now + self.step);
+            now as u32
+        }
+
+        fn read(&self) -> u64 {
+            read_split_counter(|| self.read_hi(), || self.read_lo())
+        }
+    }
+
+    #[test]
+    fn wrap_between_chi_and_clo_reads() {
+        // CHI reads 0, then CLO wraps and reads 0x3, then CHI reads 1 and
+        // the second CLO read 0xD.
+        let counter = MockCounter {
+            now: Cell::new(0xFFFF_FFFE),
+            step: 5,
+        };
+        // The naive combination of the first two reads would be 0x3, more
+        // than four billion ticks in the past.
+        assert_eq!(counter.read(), 0x1_0000_000D);
+    }
+
+    #[test]
+    fn never_goes_backwards_across_wraps() {
+        for step in [1, 2, 3, 7, 1000] {
+            let counter = MockCounter {
+                now: Cell::new(0xFFFF_FFFF - 4 * step),
+                step,
+            };
+            let mut last = 0;
+            for _ in 0..16 {
+                let before = counter.now.get();
+                let value = counter.read();
+                assert!(value >= last, "went back from {:#x} to {:#x}", last, value);
+                assert!(
+                    (before..counter.now.get()).contains(&value),
+                    "{:#x} is not a value the counter had during the read",
+                    value
+                );
+                last = value;
+            }
+        }
+    }
+
+    #[test]
+    fn match_bits_are_one_per_channel() {
+        let channels = [Channel::C0, Channel::C1, Channel::C2, Channel::C3];
+        for (index, channel) in channels.into_iter().enumerate() {
+            assert_eq!(match_bit(channel), 1 << index);
+        }
+    }
+
+    #[test]
+    fn clear_match_writes_only_its_bit() {
+        const CS_OFFSET: usize = 0x3000;
+        let soc = Soc::in_memory();
+        let timer = unsafe { SystemTimer::for_soc(&soc) };
+
+        // All four matches pending. A read-modify-write would write back
+        // the other channels' bits and clear them too.
+        soc.write(CS_OFFSET, 0b1111);
+        assert!(timer.is_matched(Channel::C1));
+        timer.clear_match(Channel::C1);
+        assert_eq!(soc.read(CS_OFFSET), 0b0010);
+
+        timer.clear_match(Channel::C3);
+        assert_eq!(soc.read(CS_OFFSET), 0b1000);
+    }
+}
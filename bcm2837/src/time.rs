@@ -0,0 +1,73 @@
+//! Tick rates of the timebases, as `hil::time::Frequency` types, and
+//! conversions between their ticks and microseconds.
+//!
+//! - The System Timer counts at 1 MHz, Tock's `Freq1MHz`. `SystemTimerAlarm`
+//!   uses it, so it also gets `hil::time::ConvertTicks`.
+//! - The ARM generic timer counts at whatever the firmware put in
+//!   CNTFRQ_EL0: 19.2 MHz on the BCM2837, 54 MHz on the BCM2711. Its
+//!   `GenericTimerFrequency` reads the register on every call.
+//!
+//! Conversions truncate, as `ConvertTicks` does, and are those of
+//! `cortex_a::ticks`, so they do not overflow for any 64-bit count.
+
+use kernel::hil::time::Frequency;
+
+/// Rate of the System Timer.
+pub type SystemTimerFrequency = kernel::hil::time::Freq1MHz;
+
+/// Rate of the ARM generic timer, from CNTFRQ_EL0.
+#[cfg(target_arch = "aarch64")]
+#[derive(Debug)]
+pub enum GenericTimerFrequency {}
+
+#[cfg(target_arch = "aarch64")]
+impl Frequency for GenericTimerFrequency {
+    fn frequency() -> u32 {
+        cortex_a::generic_timer::frequency()
+    }
+}
+
+/// Microseconds in `ticks` ticks at `F`, rounded down.
+pub fn ticks_to_us<F: Frequency>(ticks: u64) -> u64 {
+    cortex_a::ticks::ticks_to_us(ticks, F::frequency() as u64)
+}
+
+/// Ticks at `F` in `us` microseconds, rounded down. Saturates at
+/// `u64::MAX` for rates above 1 MHz.
+pub fn us_to_ticks<F: Frequency>(us: u64) -> u64 {
+    cortex_a::ticks::us_to_ticks(us, F::frequency() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The generic timer rate of the BCM2711.
+    enum Freq54MHz {}
+
+    impl Frequency for Freq54MHz {
+        fn frequency() -> u32 {
+            54_000_000
+        }
+    }
+
+    #[test]
+    fn conversions_at_1mhz_are_identity() {
+        for value in [0, 1, 999_999, u64::MAX] {
+            assert_eq!(ticks_to_us::<SystemTimerFrequency>(value), value);
+            assert_eq!(us_to_ticks::<SystemTimerFrequency>(value), value);
+        }
+    }
+
+    #[test]
+    fn conversions_at_54mhz() {
+        assert_eq!(ticks_to_us::<Freq54MHz>(53), 0);
+        assert_eq!(ticks_to_us::<Freq54MHz>(54), 1);
+        assert_eq!(ticks_to_us::<Freq54MHz>(54_000_000), 1_000_000);
+        assert_eq!(us_to_ticks::<Freq54MHz>(1), 54);
+        assert_eq!(us_to_ticks::<Freq54MHz>(1_000_000), 54_000_000);
+        // The product overflows 64 bits but not the 128-bit intermediate.
+        assert_eq!(ticks_to_us::<Freq54MHz>(u64::MAX), u64::MAX / 54);
+        assert_eq!(us_to_ticks::<Freq54MHz>(u64::MAX), u64::MAX);
+    }
+}
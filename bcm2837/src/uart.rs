@@ -1,7 +1,28 @@
+use core::cell::Cell;
 use core::fmt::Write;
 use core::str;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
 
+use crate::ansi;
+use crate::delay;
+use crate::gpio::Function;
+use crate::gpio::Gpio;
+use crate::gpio::Pull;
+use crate::mailbox::Mailbox;
+use crate::shared;
+use crate::soc::Soc;
+use crate::system_timer::SystemTimer;
+
+use kernel::hil;
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::cells::TakeCell;
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
+use tock_registers::fields::FieldValue;
 use tock_registers::interfaces::ReadWriteable;
 use tock_registers::interfaces::Readable;
 use tock_registers::interfaces::Writeable;
@@ -9,9 +30,10 @@ use tock_registers::register_bitfields;
 use tock_registers::registers::Aliased;
 use tock_registers::registers::ReadOnly;
 use tock_registers::registers::ReadWrite;
+use tock_registers::LocalRegisterCopy;
 
 register_bitfields![u32,
-    Mux [
+    pub(crate) Mux [
     UART OFFSET(0) NUMBITS(1),
     SPI1 OFFSET(1) NUMBITS(1),
     SPI2 OFFSET(2) NUMBITS(1),
@@ -52,18 +74,9 @@ register_bitfields![u32,
         ClearReceiveFIFO OFFSET(1) NUMBITS(1) [],
     ],
     LCR [
+        DivisorLatchAccess OFFSET(7) NUMBITS(1) [],
         BreakSignal OFFSET(6) NUMBITS(1) [],
-        ParityMode OFFSET(4) NUMBITS(2) [
-            Odd = 0,
-            Even = 1,
-            High = 2,
-            Low = 3,
-        ],
-        Parity OFFSET(3) NUMBITS(1) [],
-        StopBits OFFSET(2) NUMBITS(1) [
-            One = 0,
-            OneHalfTwo = 1,
-        ],
+        // Bits 2-5, parity and stop bits on a 16550, are reserved.
         DataWordLength OFFSET(0) NUMBITS(2) [
             Bits7 = 0,
             Bits8 = 3,
@@ -76,9 +89,7 @@ register_bitfields![u32,
         ErronousDataInFIFO OFFSET(7) NUMBITS(1) [],
         THREmptyLineIdle OFFSET(6) NUMBITS(1) [],
         THREmpty OFFSET(5) NUMBITS(1) [],
-        BreakSignalReceived OFFSET(4) NUMBITS(1) [],
-        FramingError OFFSET(3) NUMBITS(1) [],
-        ParityError OFFSET(2) NUMBITS(1) [],
+        // Bits 2-4, parity, framing and break on a 16550, are reserved.
         OverrunError OFFSET(1) NUMBITS(1) [],
         DataAvailable OFFSET(0) NUMBITS(1) [],
     ],
@@ -97,11 +108,38 @@ register_bitfields![u32,
     TransmitterEnable OFFSET(1) NUMBITS (1) [],
     RTSEnable OFFSET(2) NUMBITS (1) [],
     CTSEnable OFFSET(3) NUMBITS (1) [],
-    RTSAutoFlowLevel OFFSET(4) NUMBITS (2) [],
+    RTSAutoFlowLevel OFFSET(4) NUMBITS (2) [
+        ThreeSpaces = 0,
+        TwoSpaces = 1,
+        OneSpace = 2,
+        FourSpaces = 3,
+    ],
     RTSAssertLevel OFFSET(6) NUMBITS (1) [],
-    CTSAssertLevel OFFSET(6) NUMBITS (1) [],
+    CTSAssertLevel OFFSET(7) NUMBITS (1) [],
+    ],
+    STAT [
+        SymbolAvailable OFFSET(0) NUMBITS(1) [],
+        SpaceAvailable OFFSET(1) NUMBITS(1) [],
+        ReceiverIdle OFFSET(2) NUMBITS(1) [],
+        TransmitterIdle OFFSET(3) NUMBITS(1) [],
+        ReceiverOverrun OFFSET(4) NUMBITS(1) [],
+        TransmitFIFOFull OFFSET(5) NUMBITS(1) [],
+        RTSStatus OFFSET(6) NUMBITS(1) [],
+        CTSLine OFFSET(7) NUMBITS(1) [],
+        TransmitFIFOEmpty OFFSET(8) NUMBITS(1) [],
+        TransmitterDone OFFSET(9) NUMBITS(1) [],
+        ReceiveFIFOLevel OFFSET(16) NUMBITS(4) [],
+        TransmitFIFOLevel OFFSET(24) NUMBITS(4) [],
     ],
 ];
+
+// The flow control assert levels are adjacent single bits; make sure they
+// can never alias each other again.
+const _: () = assert!(
+    (CNTL::RTSAssertLevel.mask << CNTL::RTSAssertLevel.shift)
+        & (CNTL::CTSAssertLevel.mask << CNTL::CTSAssertLevel.shift)
+        == 0
+);
 register_bitfields![u16,
     DLR [
         Divisor OFFSET(0) NUMBITS(16) [],
@@ -147,55 +185,760 @@ struct Registers {
     /// 0x58: modem status (MSR)
     msr: ReadOnly<u32, MSR::Register>,
 
-    /// 0x5C: scratch
-    scratch: ReadOnly<u32, ()>,
+    /// 0x5C: scratch, one byte of storage
+    scratch: ReadWrite<u32, ()>,
 
     /// 0x60: extra constrol (CNTL)
     control: ReadWrite<u32, CNTL::Register>,
 
     /// 0x64: extra status (STAT)
-    status: ReadOnly<u32, ()>,
+    status: ReadOnly<u32, STAT::Register>,
 
     /// 0x68: baudrate (BAUD)
     baud: ReadWrite<u32, BAUD::Register>,
 }
 
-pub struct UART(StaticRef<Registers>);
+/// The divisor latch view of the Mini UART's first two registers, for
+/// `UART::with_dlab`.
+pub struct DivisorLatch<'a>(&'a Registers);
+
+impl DivisorLatch<'_> {
+    /// The divisor, from DLL (0x40) and DLM (0x44).
+    pub fn divisor(&self) -> u16 {
+        let low = self.0.rbr_thr.get() & 0xFF;
+        let high = self.0.ier.get() & 0xFF;
+        ((high << 8) | low) as u16
+    }
+
+    pub fn set_divisor(&self, divisor: u16) {
+        self.0.rbr_thr.set(divisor as u32 & 0xFF);
+        self.0.ier.set(divisor as u32 >> 8);
+    }
+}
+
+/// Mini UART input clock, the VPU core clock (250 MHz unless `core_freq`
+/// is changed in `config.txt`). Only assumed until `reclock` asks the
+/// firmware for the actual rate.
+#[cfg(not(feature = "qemu"))]
+pub const UART_CLOCK: u32 = 250_000_000;
+/// Mini UART input clock, the core clock QEMU's firmware emulation reports.
+/// QEMU ignores the baud rate divisor, but this keeps the computed rates
+/// consistent with what the mailbox says.
+#[cfg(feature = "qemu")]
+pub const UART_CLOCK: u32 = 350_000_000;
+
+/// Baud rate configured by `init()`.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// BAUD register value for `baud` with a Mini UART clocked at `clock`, per
+/// the datasheet formula `baud = clock / (8 * (divisor + 1))`.
+///
+/// Returns `None` if the rate is not achievable, i.e. the divisor would
+/// underflow or not fit in the 16-bit BAUD register.
+pub const fn baud_divisor(clock: u32, baud: u32) -> Option<u32> {
+    if baud == 0 || baud > clock / 8 {
+        return None;
+    }
+    let divisor = clock / (8 * baud) - 1;
+    if divisor > u16::MAX as u32 {
+        None
+    } else {
+        Some(divisor)
+    }
+}
+
+/// The Mini UART only supports `None`; `UART::configure` rejects the rest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+/// The Mini UART only supports `One`; `UART::configure` rejects `Two`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StopBits {
+    #[default]
+    One,
+    Two,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WordLength {
+    Seven,
+    #[default]
+    Eight,
+}
+
+/// Line configuration. The default is 8N1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UartConfig {
+    pub word_length: WordLength,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl UartConfig {
+    /// LCR fields selecting this configuration, or `NOSUPPORT` if it asks
+    /// for parity or two stop bits, which the Mini UART lacks.
+    pub fn lcr(&self) -> Result<FieldValue<u32, LCR::Register>, ErrorCode> {
+        if self.parity != Parity::None || self.stop_bits != StopBits::One {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        Ok(match self.word_length {
+            WordLength::Seven => LCR::DataWordLength::Bits7,
+            WordLength::Eight => LCR::DataWordLength::Bits8,
+        })
+    }
+}
+
+/// Line errors reported for a received byte.
+///
+/// The Mini UART LSR only reports overruns, so `UART` never produces the
+/// other variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartError {
+    Overrun,
+    Parity,
+    Framing,
+    Break,
+}
+
+fn line_error(lsr: LocalRegisterCopy<u32, LSR::Register>) -> Option<UartError> {
+    if lsr.is_set(LSR::OverrunError) {
+        Some(UartError::Overrun)
+    } else {
+        None
+    }
+}
+
+/// Writes `s` with `write` in bursts of as many bytes as `space` says the
+/// transmit FIFO has room for, asking again once each burst is written.
+fn write_in_bursts(s: &[u8], mut space: impl FnMut() -> usize, mut write: impl FnMut(u8)) {
+    let mut remaining = s;
+    while !remaining.is_empty() {
+        let (burst, rest) = remaining.split_at(space().min(remaining.len()));
+        for byte in burst.iter() {
+            write(*byte);
+        }
+        remaining = rest;
+    }
+}
+
+/// GPIO carrying the Mini UART transmit line (alt5).
+pub const TXD_PIN: usize = 14;
+
+/// GPIO carrying the Mini UART receive line (alt5).
+pub const RXD_PIN: usize = 15;
+
+/// GPIOs carrying the Mini UART flow control lines (alt5).
+pub const CTS_PIN: usize = 16;
+pub const RTS_PIN: usize = 17;
+
+/// Depth of the Mini UART transmit and receive FIFOs.
+pub const FIFO_DEPTH: usize = 8;
+
+/// Polls of the receiver after which a `Deadline` takes a System Timer
+/// that has not moved to be stopped.
+const TIMER_STALL_POLLS: u64 = 1000;
+
+/// Rough number of receiver polls per microsecond, for timing out without
+/// the System Timer.
+const POLLS_PER_US: u64 = 4;
+
+/// The deadline of the `*_timeout` reads, measured with the System Timer.
+/// If its counter does not move, e.g. in an emulator that lacks it, polls
+/// are counted instead, which is only roughly accurate.
+struct Deadline<'a> {
+    timer: SystemTimer<'a>,
+    start: u64,
+    timeout_us: u64,
+    polls: u64,
+}
+
+impl Deadline<'_> {
+    fn new(soc: &Soc, timeout_us: u64) -> Self {
+        // Safety: only the counter is read.
+        let timer = unsafe { SystemTimer::for_soc(soc) };
+        Deadline {
+            start: timer.now_us(),
+            timer,
+            timeout_us,
+            polls: 0,
+        }
+    }
+
+    /// Counts a poll of the receiver and returns whether the deadline has
+    /// passed.
+    fn poll(&mut self) -> bool {
+        self.polls += 1;
+        let now = self.timer.now_us();
+        let elapsed = if now == self.start && self.polls >= TIMER_STALL_POLLS {
+            self.polls / POLLS_PER_US
+        } else {
+            now.wrapping_sub(self.start)
+        };
+        elapsed >= self.timeout_us
+    }
+}
+
+/// Interrupt line of the AUX block (Mini UART, SPI1, SPI2) on the BCM2837
+/// interrupt controller. This is bit 29 of the IRQ1 bank.
+pub const AUX_IRQ: u32 = 29;
+
+/// Callbacks for the interrupt-driven mode, delivered from
+/// `UART::handle_interrupt`.
+pub trait Client {
+    /// The transmitter can accept more data. The transmit interrupt has
+    /// been disabled and must be re-enabled to get another callback.
+    fn tx_done(&self);
+
+    /// A byte was received.
+    fn rx_ready(&self, byte: u8);
+}
+
+/// Whether a `UART` handed out by `uart1` is alive.
+///
+/// Taking it uses an exclusive access, which only works once the MMU maps
+/// RAM as normal memory.
+static UART1_TAKEN: AtomicBool = AtomicBool::new(false);
+
+pub struct UART {
+    registers: StaticRef<Registers>,
+    /// The SoC the registers were found on, for the GPIO block `init` uses.
+    soc: Soc,
+    clock: u32,
+    /// Baud rate last set, restored by `reclock`.
+    baud: u32,
+    client: Option<&'static dyn Client>,
+    /// The client again, if it is an `RxRing` the read methods take bytes
+    /// from.
+    rx_ring: Option<&'static dyn RxBuffer>,
+    cts_flow_control: bool,
+    /// Whether this instance holds `UART1_TAKEN`.
+    guarded: bool,
+}
 
 impl UART {
-    pub unsafe fn uart1() -> UART {
-        UART(StaticRef::new(0x3F215000 as *const Registers))
+    /// The Mini UART, or `None` if another instance from `uart1` is alive.
+    /// Dropping the instance makes it available again.
+    pub fn uart1() -> Option<UART> {
+        if UART1_TAKEN.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        // Safety: the flag guarantees this is the only guarded instance.
+        let mut uart = unsafe { UART::uart1_unchecked() };
+        uart.guarded = true;
+        Some(uart)
+    }
+
+    /// The Mini UART, regardless of other instances.
+    ///
+    /// # Safety
+    ///
+    /// Other instances may be using the registers concurrently. Meant for
+    /// the panic and fault handlers, which take over the UART for good.
+    ///
+    /// The registers and the clock are those of the SoC `Soc::detect`
+    /// finds.
+    pub unsafe fn uart1_unchecked() -> UART {
+        UART::uart1_for_soc(&Soc::detect())
+    }
+
+    /// Like `uart1_unchecked`, for the peripheral window and clock of `soc`.
+    pub unsafe fn uart1_for_soc(soc: &Soc) -> UART {
+        UART {
+            registers: StaticRef::new((soc.peripheral_base + 0x215000) as *const Registers),
+            soc: *soc,
+            clock: soc.uart_clock,
+            baud: DEFAULT_BAUD_RATE,
+            client: None,
+            rx_ring: None,
+            cts_flow_control: false,
+            guarded: false,
+        }
+    }
+
+    pub fn set_client(&mut self, client: &'static dyn Client) {
+        self.client = Some(client);
+        self.rx_ring = None;
+    }
+
+    /// Makes `ring` the client, and has `try_read_byte` and the reads built
+    /// on it take bytes from the ring rather than the receive register the
+    /// interrupt handler drains.
+    pub fn set_rx_ring<const N: usize>(&mut self, ring: &'static RxRing<N>) {
+        self.client = Some(ring);
+        self.rx_ring = Some(ring);
+    }
+
+    pub fn enable_rx_interrupt(&mut self) {
+        shared::modify(&self.registers.ier, IER::ReceivedDataAvailable::SET);
+    }
+
+    pub fn disable_rx_interrupt(&mut self) {
+        shared::modify(&self.registers.ier, IER::ReceivedDataAvailable::CLEAR);
+    }
+
+    pub fn enable_tx_interrupt(&mut self) {
+        shared::modify(
+            &self.registers.ier,
+            IER::TransmitterHoldingRegisterEmpty::SET,
+        );
+    }
+
+    pub fn disable_tx_interrupt(&mut self) {
+        shared::modify(
+            &self.registers.ier,
+            IER::TransmitterHoldingRegisterEmpty::CLEAR,
+        );
+    }
+
+    /// Services all pending Mini UART interrupts. Call this when `AUX_IRQ`
+    /// fires.
+    pub fn handle_interrupt(&mut self) {
+        loop {
+            let iir = self.registers.iir_fcr.extract();
+            if iir.matches_all(IIR::Pending::NotPending) {
+                break;
+            }
+            match iir.read_as_enum(IIR::Identification) {
+                Some(IIR::Identification::Value::ReceiveDataAvailable) => {
+                    while let Some(byte) = self.read_receive_register() {
+                        if let Some(client) = self.client {
+                            client.rx_ready(byte);
+                        }
+                    }
+                }
+                Some(IIR::Identification::Value::TransmitterHoldingRegisterEmpty) => {
+                    // THRE stays asserted while the FIFO is empty, so mask it
+                    // until there is more data to send.
+                    self.disable_tx_interrupt();
+                    if let Some(client) = self.client {
+                        client.tx_done();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Sets the input clock (in Hz) used for subsequent baud rate
+    /// calculations.
+    pub fn set_clock(&mut self, clock: u32) {
+        self.clock = clock;
+    }
+
+    /// Runs `f` with LCR.DLAB set, so that 0x40 and 0x44 are the divisor
+    /// latch, and clears it again before returning. IRQs are masked
+    /// meanwhile, as a handler reading the receive buffer would get the
+    /// divisor instead.
+    ///
+    /// The PL011 has no DLAB; its divisors are registers of their own. On
+    /// the Mini UART the latch is the same 16-bit divisor as the BAUD
+    /// register, which `set_baud_rate` writes directly, so this is mostly
+    /// useful for code written against a 16550.
+    pub fn with_dlab<R>(&mut self, f: impl FnOnce(&DivisorLatch) -> R) -> R {
+        let registers = &*self.registers;
+        shared::without_interrupts(|| {
+            registers.lcr.modify(LCR::DivisorLatchAccess::SET);
+            let result = f(&DivisorLatch(registers));
+            registers.lcr.modify(LCR::DivisorLatchAccess::CLEAR);
+            result
+        })
+    }
+
+    pub fn set_baud_rate(&mut self, baud: u32) -> Result<(), ErrorCode> {
+        let divisor = baud_divisor(self.clock, baud).ok_or(ErrorCode::INVAL)?;
+        self.registers.baud.set(divisor);
+        self.baud = baud;
+        Ok(())
+    }
+
+    /// Asks the firmware for the core clock it measures, which the Mini
+    /// UART runs from, and recomputes the divisor for the current baud rate.
+    ///
+    /// The mailbox call has no timeout, so this is left out of `init`, which
+    /// the panic handler goes through too.
+    ///
+    /// The core clock follows `core_freq` and, unless `enable_uart=1` or
+    /// `core_freq_min` pin it, changes under the firmware's frequency
+    /// scaling, which garbles the line until this is called again. On
+    /// failure the clock and divisor are left alone.
+    pub fn reclock(&mut self) -> Result<(), ErrorCode> {
+        // Safety: only a property query is made.
+        let clock = unsafe { Mailbox::new() }
+            .mini_uart_clock()
+            .map_err(|_| ErrorCode::FAIL)?;
+        if clock == 0 {
+            return Err(ErrorCode::FAIL);
+        }
+        let previous = self.clock;
+        self.clock = clock;
+        let result = self.set_baud_rate(self.baud);
+        if result.is_err() {
+            self.clock = previous;
+        }
+        result
     }
 
+    /// Enables hardware flow control. With `rts`, RTS is de-asserted once
+    /// the receive FIFO has only three free spaces left; with `cts`, the
+    /// transmitter pauses while CTS is de-asserted.
+    pub fn enable_flow_control(&mut self, rts: bool, cts: bool) {
+        let rts_enable = if rts {
+            CNTL::RTSEnable::SET
+        } else {
+            CNTL::RTSEnable::CLEAR
+        };
+        let cts_enable = if cts {
+            CNTL::CTSEnable::SET
+        } else {
+            CNTL::CTSEnable::CLEAR
+        };
+        self.registers
+            .control
+            .modify(CNTL::RTSAutoFlowLevel::ThreeSpaces + rts_enable + cts_enable);
+        self.cts_flow_control = cts;
+    }
+
+    fn clear_to_send(&self) -> bool {
+        !self.cts_flow_control || self.registers.msr.is_set(MSR::ClearToSend)
+    }
+
+    /// Sets the framing. Only the word length can be chosen, so parity and
+    /// two stop bits are rejected with `NOSUPPORT` and LCR is left alone.
+    pub fn configure(&mut self, config: UartConfig) -> Result<(), ErrorCode> {
+        self.registers.lcr.modify(config.lcr()?);
+        Ok(())
+    }
+
+    /// Routes the Mini UART to the header pins and configures it for 8N1 at
+    /// `DEFAULT_BAUD_RATE`.
+    ///
+    /// This selects alt5 (TXD1/RXD1) on GPIO14/15 through the `gpio` module
+    /// and disables their pulls, so it does not depend on the firmware
+    /// having muxed the pins. The clock assumed so far is kept; call
+    /// `reclock` afterwards to take it from the firmware.
     pub fn init(&mut self) {
-        self.0.enables.modify(Mux::UART::SET);
-        self.0.control.modify(CNTL::ReceiverEnable::SET);
-        self.0.lcr.modify(LCR::DataWordLength::Bits8);
-        self.0.mcr.modify(MCR::RequestToSend::CLEAR);
-        self.0.ier.set(0);
-        self.0
-            .iir_fcr
-            .write(FCR::ClearReceiveFIFO::CLEAR + FCR::ClearTransmitFIFO::CLEAR);
-        self.0.baud.set(25000000 / 8 / 115200);
-        self.0
+        // Safety: GPIO14 and GPIO15 belong to the Mini UART.
+        let gpio = unsafe { Gpio::for_soc(&self.soc) };
+        for pin in [TXD_PIN, RXD_PIN] {
+            gpio.set_function(pin, Function::Alt5);
+            gpio.set_pull(pin, Pull::None);
+        }
+
+        shared::modify(&self.registers.enables, Mux::UART::SET);
+        self.baud = DEFAULT_BAUD_RATE;
+        self.reset();
+    }
+
+    /// Puts the Mini UART back into the state `init` leaves it in, whatever
+    /// the firmware or earlier code did: interrupts and flow control off,
+    /// both FIFOs empty, RTS and break cleared, and 8N1 at
+    /// `DEFAULT_BAUD_RATE`. The AUX enable and the pins are left alone.
+    pub fn reset(&mut self) {
+        self.registers.control.set(0);
+        self.cts_flow_control = false;
+        self.registers.ier.set(0);
+        self.clear_fifos();
+        self.registers.mcr.modify(MCR::RequestToSend::CLEAR);
+        self.registers.lcr.write(LCR::DataWordLength::Bits8);
+        let _ = self.set_baud_rate(DEFAULT_BAUD_RATE);
+        self.registers
             .control
             .modify(CNTL::ReceiverEnable::SET + CNTL::TransmitterEnable::SET);
     }
 
+    /// Queues `byte` for transmission if the transmitter can accept it.
+    /// Returns `false` without writing if the holding register is full.
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        if self.clear_to_send() && self.registers.lsr.is_set(LSR::THREmpty) {
+            self.registers.rbr_thr.set(byte as u32);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
-        while self.0.lsr.read(LSR::THREmpty) == 0 {}
-        self.0.rbr_thr.set(byte as u32);
+        while !self.try_write_byte(byte) {}
     }
 
+    /// Writes `s`, filling the transmit FIFO in bursts and only waiting when
+    /// it is full.
     pub fn write_bytes(&mut self, s: &[u8]) {
-        for byte in s.iter() {
-            self.write_byte(*byte);
+        let registers = &*self.registers;
+        write_in_bursts(
+            s,
+            || {
+                if self.clear_to_send() {
+                    FIFO_DEPTH.saturating_sub(self.tx_fifo_level())
+                } else {
+                    0
+                }
+            },
+            |byte| registers.rbr_thr.set(byte as u32),
+        );
+    }
+
+    /// Waits until every queued byte, including the one in the shift
+    /// register, has left the wire.
+    pub fn flush(&mut self) {
+        while !self.registers.lsr.is_set(LSR::THREmptyLineIdle) {}
+    }
+
+    /// Like `flush`, but gives up after `cycles` polls. Returns whether the
+    /// transmitter drained.
+    pub fn flush_timeout(&mut self, cycles: usize) -> bool {
+        for _ in 0..cycles {
+            if self.registers.lsr.is_set(LSR::THREmptyLineIdle) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Holds the transmit line low for `duration_us` microseconds, after
+    /// the queued bytes have gone out so none of them is cut short.
+    ///
+    /// The Mini UART can only send breaks; it has no break detection.
+    pub fn send_break(&mut self, duration_us: u64) {
+        self.flush();
+        self.registers.lcr.modify(LCR::BreakSignal::SET);
+        delay::delay_us(duration_us);
+        self.registers.lcr.modify(LCR::BreakSignal::CLEAR);
+    }
+
+    /// Checks that the registers behave as the Mini UART's should, as a
+    /// quick test of the register mapping on a new board.
+    ///
+    /// The Mini UART has no internal loopback (its MCR only drives RTS), so
+    /// nothing goes over the wire: patterns are written to BAUD and read
+    /// back, and bytes queued with the transmitter stopped must show up in
+    /// the transmit FIFO level. The queued bytes are discarded and BAUD is
+    /// restored afterwards. Fails with `FAIL` if any check does not hold.
+    pub fn self_test(&mut self) -> Result<(), ErrorCode> {
+        let baud = self.registers.baud.get();
+        let baud_ok = [0x5A5A, 0xA5A5].iter().all(|&pattern| {
+            self.registers.baud.set(pattern);
+            self.registers.baud.get() == pattern
+        });
+        self.registers.baud.set(baud);
+
+        self.flush();
+        self.registers
+            .control
+            .modify(CNTL::TransmitterEnable::CLEAR);
+        self.registers.iir_fcr.write(FCR::ClearTransmitFIFO::SET);
+        let mut fifo_ok = self.tx_fifo_level() == 0;
+        for (queued, byte) in [0x55, 0xAA, 0x00, 0xFF].iter().enumerate() {
+            self.registers.rbr_thr.set(*byte);
+            fifo_ok &= self.tx_fifo_level() == queued + 1;
+        }
+        self.registers.iir_fcr.write(FCR::ClearTransmitFIFO::SET);
+        fifo_ok &= self.tx_fifo_level() == 0;
+        self.registers.control.modify(CNTL::TransmitterEnable::SET);
+
+        if baud_ok && fifo_ok {
+            Ok(())
+        } else {
+            Err(ErrorCode::FAIL)
+        }
+    }
+
+    /// The byte in the scratch register. `init` leaves it alone, so it
+    /// keeps its value as long as the AUX block is neither reset nor powered
+    /// down, e.g. to pass a reboot reason on.
+    pub fn scratch_read(&self) -> u8 {
+        self.registers.scratch.get() as u8
+    }
+
+    pub fn scratch_write(&mut self, value: u8) {
+        self.registers.scratch.set(value as u32);
+    }
+
+    /// Discards everything in both FIFOs, including bytes not sent yet.
+    pub fn clear_fifos(&mut self) {
+        self.registers
+            .iir_fcr
+            .write(FCR::ClearReceiveFIFO::SET + FCR::ClearTransmitFIFO::SET);
+    }
+
+    /// Enables or disables the FIFOs. Unlike a 16550 or the PL011, the Mini
+    /// UART's FIFOs are always on (IIR reports them as enabled), so only
+    /// `true` is accepted; `false` fails with `NOSUPPORT`.
+    pub fn set_fifo(&mut self, enabled: bool) -> Result<(), ErrorCode> {
+        if enabled {
+            Ok(())
+        } else {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+
+    /// Sets how many received bytes raise the receive interrupt. The Mini
+    /// UART has no trigger levels and interrupts as soon as one byte is in
+    /// the FIFO, so only 1 is accepted; anything else fails with
+    /// `NOSUPPORT`. The PL011 can trigger at 1/8 to 7/8 of its FIFO.
+    pub fn set_rx_trigger(&mut self, bytes: usize) -> Result<(), ErrorCode> {
+        if bytes == 1 {
+            Ok(())
+        } else {
+            Err(ErrorCode::NOSUPPORT)
+        }
+    }
+
+    /// Number of bytes waiting in the receive FIFO.
+    pub fn rx_fifo_level(&self) -> usize {
+        self.registers.status.read(STAT::ReceiveFIFOLevel) as usize
+    }
+
+    /// Number of bytes queued in the transmit FIFO.
+    pub fn tx_fifo_level(&self) -> usize {
+        #[cfg(test)]
+        tests::STAT_READS.with(|reads| reads.set(reads.get() + 1));
+        self.registers.status.read(STAT::TransmitFIFOLevel) as usize
+    }
+
+    /// Returns the next received byte, or `None` if none is available.
+    ///
+    /// With an `RxRing` set by `set_rx_ring`, the byte comes from the ring.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        match self.rx_ring {
+            Some(ring) => ring.try_read_byte(),
+            None => self.read_receive_register(),
+        }
+    }
+
+    fn read_receive_register(&mut self) -> Option<u8> {
+        if self.registers.lsr.is_set(LSR::DataAvailable) {
+            Some(self.registers.rbr_thr.read(RBR::Data) as u8)
+        } else {
+            None
         }
     }
 
     pub fn read_byte(&mut self) -> u8 {
-        while self.0.lsr.read(LSR::DataAvailable) == 0 {}
-        self.0.rbr_thr.read(RBR::Data) as u8
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return byte;
+            }
+        }
+    }
+
+    /// Like `read_byte`, but gives up after `timeout_us` microseconds.
+    ///
+    /// The deadline is measured with the System Timer. If its counter does
+    /// not move, e.g. in an emulator that lacks it, polls are counted
+    /// instead, which is only roughly accurate.
+    pub fn read_byte_timeout(&mut self, timeout_us: u64) -> Option<u8> {
+        let mut deadline = Deadline::new(&self.soc, timeout_us);
+        self.read_byte_before(&mut deadline)
+    }
+
+    fn read_byte_before(&mut self, deadline: &mut Deadline) -> Option<u8> {
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return Some(byte);
+            }
+            if deadline.poll() {
+                return None;
+            }
+        }
+    }
+
+    /// Fills `buf` with received bytes, waiting for as long as it takes.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Like `read_bytes`, but gives up once `timeout_us` microseconds have
+    /// passed in total, timed as in `read_byte_timeout`. Returns the number
+    /// of bytes read.
+    pub fn read_bytes_timeout(&mut self, buf: &mut [u8], timeout_us: u64) -> usize {
+        // One deadline for all bytes, so counted polls add up too.
+        let mut deadline = Deadline::new(&self.soc, timeout_us);
+        for (count, byte) in buf.iter_mut().enumerate() {
+            match self.read_byte_before(&mut deadline) {
+                Some(received) => *byte = received,
+                None => return count,
+            }
+        }
+        buf.len()
+    }
+
+    /// Like `try_read_byte`, but reports whether the receive FIFO overran
+    /// before the byte, i.e. bytes were lost. The byte is consumed either
+    /// way. The Mini UART has no parity, framing or break detection, so a
+    /// corrupted byte is returned as `Ok`. Bytes from an `RxRing` carry no
+    /// line status and are always `Ok`.
+    pub fn try_read_byte_checked(&mut self) -> Option<Result<u8, UartError>> {
+        if let Some(ring) = self.rx_ring {
+            return ring.try_read_byte().map(Ok);
+        }
+        // Reading LSR clears its error bits, so sample it only once per
+        // received byte.
+        let lsr = self.registers.lsr.extract();
+        if !lsr.is_set(LSR::DataAvailable) {
+            return None;
+        }
+        let byte = self.registers.rbr_thr.read(RBR::Data) as u8;
+        Some(match line_error(lsr) {
+            Some(error) => Err(error),
+            None => Ok(byte),
+        })
+    }
+
+    /// Like `read_byte`, but reports an overrun as `try_read_byte_checked`
+    /// does. The byte is consumed either way.
+    pub fn read_byte_checked(&mut self) -> Result<u8, UartError> {
+        loop {
+            if let Some(result) = self.try_read_byte_checked() {
+                return result;
+            }
+        }
+    }
+
+    /// Reads a line into `buf`, echoing it back, and returns its length.
+    ///
+    /// The line ends at CR or LF, which is not stored, or once `buf` is
+    /// full. Backspace and DEL erase the last character, on the terminal as
+    /// well. Other control characters are dropped.
+    pub fn read_line(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.read_byte() {
+                b'\r' | b'\n' => {
+                    self.write_bytes(b"\r\n");
+                    break;
+                }
+                0x08 | 0x7F => {
+                    if len > 0 {
+                        len -= 1;
+                        let _ = ansi::cursor_back(self, 1);
+                        let _ = ansi::clear_line(self);
+                    }
+                }
+                byte if byte == b' ' || byte.is_ascii_graphic() => {
+                    buf[len] = byte;
+                    len += 1;
+                    self.write_byte(byte);
+                }
+                _ => {}
+            }
+        }
+        len
+    }
+}
+
+impl Drop for UART {
+    fn drop(&mut self) {
+        if self.guarded {
+            UART1_TAKEN.store(false, Ordering::Release);
+        }
     }
 }
 
@@ -205,3 +948,771 @@ impl Write for UART {
         Ok(())
     }
 }
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::Error for UartError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            UartError::Parity | UartError::Framing => embedded_io::ErrorKind::InvalidData,
+            UartError::Break | UartError::Overrun => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::ErrorType for UART {
+    type Error = UartError;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::Read for UART {
+    /// Blocks until one byte arrives, then takes whatever else is already
+    /// in the FIFO. A line error on a later byte ends the read early and
+    /// drops that byte, as the bytes before it cannot be returned with it.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, UartError> {
+        let Some((first, rest)) = buf.split_first_mut() else {
+            return Ok(0);
+        };
+        *first = self.read_byte_checked()?;
+        let mut count = 1;
+        for byte in rest.iter_mut() {
+            match self.try_read_byte_checked() {
+                Some(Ok(received)) => *byte = received,
+                Some(Err(_)) | None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::ReadReady for UART {
+    fn read_ready(&mut self) -> Result<bool, UartError> {
+        Ok(self.registers.lsr.is_set(LSR::DataAvailable))
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::Write for UART {
+    /// Blocks until one byte is queued, then queues as many more as fit in
+    /// the FIFO. Returns the number of bytes queued.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, UartError> {
+        let Some((first, rest)) = buf.split_first() else {
+            return Ok(0);
+        };
+        self.write_byte(*first);
+        let queued = rest
+            .iter()
+            .take_while(|byte| self.try_write_byte(**byte))
+            .count();
+        Ok(1 + queued)
+    }
+
+    fn flush(&mut self) -> Result<(), UartError> {
+        UART::flush(self);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::WriteReady for UART {
+    fn write_ready(&mut self) -> Result<bool, UartError> {
+        Ok(self.clear_to_send() && self.registers.lsr.is_set(LSR::THREmpty))
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_nb::serial::Error for UartError {
+    fn kind(&self) -> embedded_hal_nb::serial::ErrorKind {
+        match self {
+            UartError::Overrun => embedded_hal_nb::serial::ErrorKind::Overrun,
+            UartError::Parity => embedded_hal_nb::serial::ErrorKind::Parity,
+            UartError::Framing => embedded_hal_nb::serial::ErrorKind::FrameFormat,
+            UartError::Break => embedded_hal_nb::serial::ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_nb::serial::ErrorType for UART {
+    type Error = UartError;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_nb::serial::Read<u8> for UART {
+    fn read(&mut self) -> embedded_hal_nb::nb::Result<u8, UartError> {
+        match self.try_read_byte_checked() {
+            Some(result) => result.map_err(embedded_hal_nb::nb::Error::Other),
+            None => Err(embedded_hal_nb::nb::Error::WouldBlock),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_nb::serial::Write<u8> for UART {
+    fn write(&mut self, word: u8) -> embedded_hal_nb::nb::Result<(), UartError> {
+        if self.try_write_byte(word) {
+            Ok(())
+        } else {
+            Err(embedded_hal_nb::nb::Error::WouldBlock)
+        }
+    }
+
+    fn flush(&mut self) -> embedded_hal_nb::nb::Result<(), UartError> {
+        if self.registers.lsr.is_set(LSR::THREmptyLineIdle) {
+            Ok(())
+        } else {
+            Err(embedded_hal_nb::nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// `fmt::Write` adapter that collects output in an `N` byte buffer and
+/// hands it to the UART in bursts, once the buffer is full, at each newline,
+/// on `flush` and when dropped.
+pub struct BufferedUart<const N: usize> {
+    uart: UART,
+    buffer: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> BufferedUart<N> {
+    pub fn new(uart: UART) -> BufferedUart<N> {
+        const { assert!(N > 0, "BufferedUart needs a buffer") };
+        BufferedUart {
+            uart,
+            buffer: [0; N],
+            len: 0,
+        }
+    }
+
+    /// The wrapped UART. Flush first if the order of output matters.
+    pub fn uart(&mut self) -> &mut UART {
+        &mut self.uart
+    }
+
+    /// Queues the buffered bytes in the transmit FIFO. Unlike
+    /// `UART::flush`, this does not wait for them to leave the wire.
+    pub fn flush(&mut self) {
+        self.uart.write_bytes(&self.buffer[..self.len]);
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Write for BufferedUart<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == N {
+                self.flush();
+            }
+            self.buffer[self.len] = byte;
+            self.len += 1;
+            if byte == b'\n' {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Drop for BufferedUart<N> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Receive ring buffer of `N` bytes, filled from the receive interrupt.
+///
+/// Register it with `UART::set_rx_ring` and enable the receive interrupt;
+/// reads from either the ring or the UART then take bytes from the ring,
+/// so they don't race the interrupt handler. Bytes arriving while the
+/// ring is full are dropped and reported by `take_overrun`. `handle_interrupt`
+/// is the only producer; reads are meant to come from a single context too,
+/// otherwise bytes may be returned twice.
+pub struct RxRing<const N: usize> {
+    ring: [AtomicU8; N],
+    /// Total bytes written, only advanced by `rx_ready`.
+    head: AtomicUsize,
+    /// Total bytes read, only advanced by the readers.
+    tail: AtomicUsize,
+    overrun: AtomicBool,
+}
+
+impl<const N: usize> RxRing<N> {
+    pub const fn new() -> RxRing<N> {
+        // A repeated const item is the only way to build the array in a
+        // const fn; each element is still a separate atomic.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicU8 = AtomicU8::new(0);
+        assert!(N > 0, "RxRing needs a non-zero capacity");
+        RxRing {
+            ring: [EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overrun: AtomicBool::new(false),
+        }
+    }
+
+    /// Number of buffered bytes.
+    pub fn len(&self) -> usize {
+        self.head
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the oldest buffered byte, or `None` if the ring is empty.
+    pub fn try_read_byte(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if self.head.load(Ordering::Acquire) == tail {
+            return None;
+        }
+        let byte = self.ring[tail % N].load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+
+    /// Waits for and returns the oldest buffered byte.
+    pub fn read_byte(&self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Fills `buf` from the ring, waiting for bytes as needed.
+    pub fn read_bytes(&self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Whether bytes were dropped because the ring was full since the last
+    /// call.
+    pub fn take_overrun(&self) -> bool {
+        self.overrun.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl<const N: usize> Default for RxRing<N> {
+    fn default() -> RxRing<N> {
+        RxRing::new()
+    }
+}
+
+/// A client that buffers received bytes for the `UART` read methods.
+trait RxBuffer {
+    fn try_read_byte(&self) -> Option<u8>;
+}
+
+impl<const N: usize> RxBuffer for RxRing<N> {
+    fn try_read_byte(&self) -> Option<u8> {
+        RxRing::try_read_byte(self)
+    }
+}
+
+impl<const N: usize> Client for RxRing<N> {
+    fn tx_done(&self) {}
+
+    fn rx_ready(&self, byte: u8) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == N {
+            self.overrun.store(true, Ordering::Relaxed);
+            return;
+        }
+        self.ring[head % N].store(byte, Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+    }
+}
+
+/// Interrupt-driven Tock UART on top of `UART`.
+///
+/// Call `handle_interrupt` when `AUX_IRQ` fires. Aborts are completed from
+/// the next interrupt, which is forced by enabling the transmit interrupt.
+pub struct MiniUart<'a> {
+    uart: MapCell<UART>,
+    tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_index: Cell<usize>,
+    tx_abort: Cell<bool>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    rx_abort: Cell<bool>,
+    rx_error: Cell<hil::uart::Error>,
+}
+
+impl<'a> MiniUart<'a> {
+    pub fn new(uart: UART) -> MiniUart<'a> {
+        MiniUart {
+            uart: MapCell::new(uart),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_index: Cell::new(0),
+            tx_abort: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_abort: Cell::new(false),
+            rx_error: Cell::new(hil::uart::Error::None),
+        }
+    }
+
+    /// Sets up the Mini UART, see `UART::init`, and takes its clock from
+    /// the firmware with `UART::reclock`.
+    pub fn init(&self) {
+        self.uart.map(|uart| {
+            uart.init();
+            let _ = uart.reclock();
+        });
+    }
+
+    /// Moves as much of the pending transmission into the FIFO as fits.
+    fn fill_tx_fifo(&self, uart: &mut UART) {
+        self.tx_buffer.map(|buffer| {
+            while self.tx_index.get() < self.tx_len.get() {
+                if !uart.try_write_byte(buffer[self.tx_index.get()]) {
+                    break;
+                }
+                self.tx_index.set(self.tx_index.get() + 1);
+            }
+        });
+    }
+
+    /// Moves received bytes into the pending receive buffer.
+    fn drain_rx_fifo(&self, uart: &mut UART) {
+        self.rx_buffer.map(|buffer| {
+            while self.rx_index.get() < self.rx_len.get() {
+                match uart.try_read_byte_checked() {
+                    Some(Ok(byte)) => {
+                        buffer[self.rx_index.get()] = byte;
+                        self.rx_index.set(self.rx_index.get() + 1);
+                    }
+                    Some(Err(error)) => {
+                        self.rx_error.set(match error {
+                            UartError::Overrun => hil::uart::Error::OverrunError,
+                            UartError::Parity => hil::uart::Error::ParityError,
+                            UartError::Framing => hil::uart::Error::FramingError,
+                            UartError::Break => hil::uart::Error::BreakError,
+                        });
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    pub fn handle_interrupt(&self) {
+        let mut tx_done = None;
+        let mut rx_done = None;
+
+        self.uart.map(|uart| {
+            self.drain_rx_fifo(uart);
+            if self.rx_buffer.is_some()
+                && (self.rx_index.get() == self.rx_len.get()
+                    || self.rx_abort.get()
+                    || self.rx_error.get() != hil::uart::Error::None)
+            {
+                uart.disable_rx_interrupt();
+                rx_done = self.rx_buffer.take();
+            }
+
+            self.fill_tx_fifo(uart);
+            if self.tx_buffer.is_some()
+                && (self.tx_index.get() == self.tx_len.get() || self.tx_abort.get())
+            {
+                tx_done = self.tx_buffer.take();
+            }
+            if self.tx_buffer.is_none() {
+                uart.disable_tx_interrupt();
+            }
+        });
+
+        if let Some(buffer) = rx_done {
+            let (rval, error) = if self.rx_abort.replace(false) {
+                (Err(ErrorCode::CANCEL), hil::uart::Error::Aborted)
+            } else {
+                match self.rx_error.replace(hil::uart::Error::None) {
+                    hil::uart::Error::None => (Ok(()), hil::uart::Error::None),
+                    error => (Err(ErrorCode::FAIL), error),
+                }
+            };
+            self.rx_client.map(|client| {
+                client.received_buffer(buffer, self.rx_index.get(), rval, error);
+            });
+        }
+
+        if let Some(buffer) = tx_done {
+            let rval = if self.tx_abort.replace(false) {
+                Err(ErrorCode::CANCEL)
+            } else {
+                Ok(())
+            };
+            self.tx_client.map(|client| {
+                client.transmitted_buffer(buffer, self.tx_index.get(), rval);
+            });
+        }
+    }
+}
+
+impl hil::uart::Configure for MiniUart<'_> {
+    /// Sets the baud rate, word length and flow control. The Mini UART has
+    /// no parity and a single stop bit, so any other combination is
+    /// rejected with `NOSUPPORT`. A baud rate the divisor cannot produce
+    /// from the current clock is rejected with `INVAL`. Nothing is changed
+    /// if the parameters are rejected.
+    ///
+    /// Hardware flow control routes CTS and RTS to GPIO16/17 and turns on
+    /// both directions, as `UART::enable_flow_control(true, true)`.
+    fn configure(&self, params: hil::uart::Parameters) -> Result<(), ErrorCode> {
+        let word_length = match params.width {
+            hil::uart::Width::Seven => WordLength::Seven,
+            hil::uart::Width::Eight => WordLength::Eight,
+            hil::uart::Width::Six => return Err(ErrorCode::NOSUPPORT),
+        };
+        if params.parity != hil::uart::Parity::None || params.stop_bits != hil::uart::StopBits::One
+        {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        let config = UartConfig {
+            word_length,
+            ..UartConfig::default()
+        };
+        self.uart
+            .map(|uart| {
+                uart.set_baud_rate(params.baud_rate)?;
+                uart.configure(config)?;
+                if params.hw_flow_control {
+                    // Safety: GPIO16/17 belong to the Mini UART once flow
+                    // control is asked for.
+                    let gpio = unsafe { Gpio::for_soc(&uart.soc) };
+                    for pin in [CTS_PIN, RTS_PIN] {
+                        gpio.set_function(pin, Function::Alt5);
+                    }
+                }
+                uart.enable_flow_control(params.hw_flow_control, params.hw_flow_control);
+                Ok(())
+            })
+            .unwrap_or(Err(ErrorCode::BUSY))
+    }
+}
+
+impl<'a> hil::uart::Transmit<'a> for MiniUart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn hil::uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.tx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, tx_buffer));
+        }
+        if tx_len == 0 || tx_len > tx_buffer.len() {
+            return Err((ErrorCode::SIZE, tx_buffer));
+        }
+        self.tx_buffer.replace(tx_buffer);
+        self.tx_len.set(tx_len);
+        self.tx_index.set(0);
+        self.uart.map(|uart| {
+            self.fill_tx_fifo(uart);
+            // Completion, even if everything fit, is reported from the
+            // interrupt handler.
+            uart.enable_tx_interrupt();
+        });
+        Ok(())
+    }
+
+    fn transmit_word(&self, _word: u32) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Returns `BUSY` if a transmission was in flight; its buffer then
+    /// comes back through `transmitted_buffer` with `CANCEL`.
+    fn transmit_abort(&self) -> Result<(), ErrorCode> {
+        if self.tx_buffer.is_none() {
+            return Ok(());
+        }
+        self.tx_abort.set(true);
+        self.uart.map(|uart| uart.enable_tx_interrupt());
+        Err(ErrorCode::BUSY)
+    }
+}
+
+impl<'a> hil::uart::Receive<'a> for MiniUart<'a> {
+    fn set_receive_client(&self, client: &'a dyn hil::uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.rx_buffer.is_some() {
+            return Err((ErrorCode::BUSY, rx_buffer));
+        }
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            return Err((ErrorCode::SIZE, rx_buffer));
+        }
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+        self.uart.map(|uart| uart.enable_rx_interrupt());
+        Ok(())
+    }
+
+    fn receive_word(&self) -> Result<(), ErrorCode> {
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    /// Returns `BUSY` if a reception was in flight; its buffer then comes
+    /// back through `received_buffer` with `CANCEL`.
+    fn receive_abort(&self) -> Result<(), ErrorCode> {
+        if self.rx_buffer.is_none() {
+            return Ok(());
+        }
+        self.rx_abort.set(true);
+        // The receive interrupt only fires on data, so use the transmit
+        // interrupt to get into the handler.
+        self.uart.map(|uart| uart.enable_tx_interrupt());
+        Err(ErrorCode::BUSY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    std::thread_local! {
+        /// Reads of the transmit FIFO level made on this thread.
+        pub(super) static STAT_READS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// The VPU core clock the datasheet formula is usually quoted for.
+    const CORE_CLOCK: u32 = 250_000_000;
+
+    #[test]
+    fn baud_divisor_matches_datasheet() {
+        for (baud, expected) in [(9600, 3254), (115_200, 270), (921_600, 32)] {
+            assert_eq!(baud_divisor(CORE_CLOCK, baud), Some(expected));
+            assert_eq!(expected, CORE_CLOCK / (8 * baud) - 1);
+        }
+    }
+
+    #[test]
+    fn baud_divisor_rejects_unreachable_rates() {
+        assert_eq!(baud_divisor(CORE_CLOCK, 0), None);
+        assert_eq!(baud_divisor(CORE_CLOCK, CORE_CLOCK / 8 + 1), None);
+        // A divisor above 0xFFFF does not fit BAUD.
+        assert_eq!(baud_divisor(CORE_CLOCK, 300), None);
+    }
+
+    #[test]
+    fn lcr_round_trips_the_word_length() {
+        for (word_length, bits) in [(WordLength::Seven, 0b00), (WordLength::Eight, 0b11)] {
+            let config = UartConfig {
+                word_length,
+                ..UartConfig::default()
+            };
+            // Start with every bit set, DLAB, break and the reserved bits
+            // included, as `configure` modifies whatever LCR holds.
+            let mut lcr = LocalRegisterCopy::<u32, LCR::Register>::new(0xFF);
+            lcr.modify(config.lcr().unwrap());
+            assert_eq!(lcr.get(), 0xFC | bits);
+        }
+    }
+
+    #[test]
+    fn configure_rejects_parity_and_two_stop_bits() {
+        const LCR_OFFSET: usize = 0x21504C;
+        let soc = Soc::in_memory();
+        let mut uart = unsafe { UART::uart1_for_soc(&soc) };
+        soc.write(LCR_OFFSET, 0b11);
+        for config in [
+            UartConfig {
+                parity: Parity::Even,
+                ..UartConfig::default()
+            },
+            UartConfig {
+                parity: Parity::Odd,
+                ..UartConfig::default()
+            },
+            UartConfig {
+                word_length: WordLength::Seven,
+                stop_bits: StopBits::Two,
+                ..UartConfig::default()
+            },
+        ] {
+            assert_eq!(uart.configure(config), Err(ErrorCode::NOSUPPORT));
+            assert_eq!(soc.read(LCR_OFFSET), 0b11);
+        }
+    }
+
+    #[test]
+    fn write_bytes_polls_the_fifo_once_per_burst() {
+        const AUX_MU_IO: usize = 0x215040;
+        const AUX_MU_STAT: usize = 0x215064;
+        let soc = Soc::in_memory();
+        let mut uart = unsafe { UART::uart1_for_soc(&soc) };
+        // Five bytes stay queued, so each poll finds room for three.
+        soc.write(AUX_MU_STAT, 5 << 24);
+        STAT_READS.with(|reads| reads.set(0));
+
+        let message: Vec<u8> = (1..=20).collect();
+        uart.write_bytes(&message);
+
+        assert_eq!(STAT_READS.with(Cell::get), 7);
+        assert_eq!(soc.read(AUX_MU_IO), 20);
+    }
+
+    #[test]
+    fn init_muxes_gpio14_and_15_to_alt5() {
+        const GPFSEL1: usize = 0x200004;
+        const AUX_ENABLES: usize = 0x215004;
+        const AUX_MU_BAUD: usize = 0x215068;
+        let soc = Soc::in_memory();
+        // Every pin of the register on alt3, so stray writes show.
+        soc.write(GPFSEL1, 0x3FFF_FFFF);
+
+        let mut uart = unsafe { UART::uart1_for_soc(&soc) };
+        uart.init();
+
+        let alt5 = Function::Alt5 as u32;
+        assert_eq!(
+            soc.read(GPFSEL1),
+            0x3FFF_FFFF & !(0o77 << 12) | alt5 << 12 | alt5 << 15
+        );
+        assert_eq!(soc.read(AUX_ENABLES), 1);
+        assert_eq!(
+            Some(soc.read(AUX_MU_BAUD)),
+            baud_divisor(UART_CLOCK, DEFAULT_BAUD_RATE)
+        );
+    }
+
+    #[test]
+    fn default_lcr_is_8n1() {
+        let mut lcr = LocalRegisterCopy::<u32, LCR::Register>::new(0);
+        lcr.modify(UartConfig::default().lcr().unwrap());
+        assert_eq!(lcr.get(), 0b0000_0011);
+    }
+
+    /// A UART on an in-memory peripheral window fed from a ring holding
+    /// `bytes`. The System Timer there never ticks, so timeouts are counted
+    /// in polls.
+    fn uart_fed_with(bytes: &[u8]) -> (UART, &'static RxRing<8>) {
+        let ring: &'static RxRing<8> = Box::leak(Box::new(RxRing::new()));
+        for &byte in bytes {
+            ring.rx_ready(byte);
+        }
+        let mut uart = unsafe { UART::uart1_for_soc(&Soc::in_memory()) };
+        uart.set_rx_ring(ring);
+        (uart, ring)
+    }
+
+    #[test]
+    fn read_bytes_timeout_returns_what_arrived_in_time() {
+        let (mut uart, _) = uart_fed_with(b"abc");
+        let mut buf = [0; 5];
+        assert_eq!(uart.read_bytes_timeout(&mut buf, 10), 3);
+        assert_eq!(buf, *b"abc\0\0");
+    }
+
+    #[test]
+    fn read_bytes_timeout_stops_when_buf_is_full() {
+        let (mut uart, ring) = uart_fed_with(b"abcde");
+        let mut buf = [0; 3];
+        assert_eq!(uart.read_bytes_timeout(&mut buf, 10), 3);
+        assert_eq!(buf, *b"abc");
+        assert_eq!(ring.len(), 2);
+    }
+
+    #[test]
+    fn only_overrun_is_a_line_error() {
+        let reserved = LocalRegisterCopy::<u32, LSR::Register>::new(0b1_1101);
+        assert_eq!(line_error(reserved), None);
+        let overrun = LocalRegisterCopy::<u32, LSR::Register>::new(0b10);
+        assert_eq!(line_error(overrun), Some(UartError::Overrun));
+    }
+
+    /// Records the results of transmit and receive callbacks.
+    #[derive(Default)]
+    struct Results {
+        transmitted: Cell<Option<Result<(), ErrorCode>>>,
+        received: Cell<Option<Result<(), ErrorCode>>>,
+    }
+
+    impl hil::uart::TransmitClient for Results {
+        fn transmitted_buffer(
+            &self,
+            _buffer: &'static mut [u8],
+            _len: usize,
+            rval: Result<(), ErrorCode>,
+        ) {
+            self.transmitted.set(Some(rval));
+        }
+    }
+
+    impl hil::uart::ReceiveClient for Results {
+        fn received_buffer(
+            &self,
+            _buffer: &'static mut [u8],
+            _len: usize,
+            rval: Result<(), ErrorCode>,
+            _error: hil::uart::Error,
+        ) {
+            self.received.set(Some(rval));
+        }
+    }
+
+    #[test]
+    fn abort_in_flight_is_busy_then_cancel() {
+        use hil::uart::Receive;
+        use hil::uart::Transmit;
+
+        // The mock window never drains the FIFO nor receives anything, so
+        // both transfers stay in flight.
+        let uart = MiniUart::new(unsafe { UART::uart1_for_soc(&Soc::in_memory()) });
+        let results: &'static Results = Box::leak(Box::default());
+        uart.set_transmit_client(results);
+        uart.set_receive_client(results);
+        let tx = vec![0u8; 4].leak();
+        let rx = vec![0u8; 4].leak();
+        assert!(uart.transmit_buffer(tx, 4).is_ok());
+        assert!(uart.receive_buffer(rx, 4).is_ok());
+
+        assert_eq!(uart.transmit_abort(), Err(ErrorCode::BUSY));
+        assert_eq!(uart.receive_abort(), Err(ErrorCode::BUSY));
+        assert_eq!(results.transmitted.get(), None);
+        uart.handle_interrupt();
+        assert_eq!(results.transmitted.get(), Some(Err(ErrorCode::CANCEL)));
+        assert_eq!(results.received.get(), Some(Err(ErrorCode::CANCEL)));
+
+        assert_eq!(uart.transmit_abort(), Ok(()));
+        assert_eq!(uart.receive_abort(), Ok(()));
+    }
+
+    #[test]
+    fn stalled_deadline_is_shared_between_bytes() {
+        let (mut uart, _) = uart_fed_with(b"");
+        let mut deadline = Deadline::new(&Soc::in_memory(), 10);
+        assert_eq!(uart.read_byte_before(&mut deadline), None);
+        assert_eq!(deadline.polls, TIMER_STALL_POLLS);
+        // The next byte only gets the poll that finds the deadline passed.
+        assert_eq!(uart.read_byte_before(&mut deadline), None);
+        assert_eq!(deadline.polls, TIMER_STALL_POLLS + 1);
+    }
+}
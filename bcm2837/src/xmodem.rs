@@ -0,0 +1,145 @@
+//! XMODEM-CRC receiver, to load data over the Mini UART.
+//!
+//! The receiver asks for CRC mode by sending `C` until the sender starts,
+//! then takes 128-byte (SOH) or 1 KiB (STX) blocks. Each block is checked
+//! for its sequence number and CRC-16/XMODEM and answered with ACK, or NAK
+//! for a retransmission. A repeated block, sent again because our ACK was
+//! lost, is acknowledged but not stored. Any other sequence number ends the
+//! transfer. EOT completes it.
+//!
+//! XMODEM has no length field: the last block is padded, usually with
+//! SUB (0x1A), and the padding is received like data. Padding that does not
+//! fit in the destination is dropped.
+
+use crate::uart::UART;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Requests CRC mode instead of checksums.
+const CRC_MODE: u8 = b'C';
+/// Padding of the last block.
+const SUB: u8 = 0x1A;
+
+/// How long to wait for the sender to start, per `C`.
+const START_TIMEOUT_US: u64 = 3_000_000;
+/// Number of `C`s to send before giving up.
+const START_TRIES: usize = 10;
+/// How long to wait for the next block to start.
+const BLOCK_TIMEOUT_US: u64 = 10_000_000;
+/// How long to wait for each byte within a block.
+const BYTE_TIMEOUT_US: u64 = 1_000_000;
+/// Quiet time after which the line counts as purged.
+const PURGE_TIMEOUT_US: u64 = 100_000;
+/// Bad blocks in a row after which the transfer is abandoned.
+const MAX_ERRORS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XmodemError {
+    /// The sender did not start, or stopped sending.
+    Timeout,
+    /// The sender cancelled the transfer.
+    Cancelled,
+    /// A block arrived out of order.
+    Sequence,
+    /// `MAX_ERRORS` blocks in a row were corrupted.
+    TooManyErrors,
+    /// The data does not fit in the destination.
+    BufferFull,
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, initial value 0.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Receives a file into `dest` and returns the number of bytes stored.
+pub fn receive(uart: &mut UART, dest: &mut [u8]) -> Result<usize, XmodemError> {
+    let mut block = [0u8; 1024];
+    let mut expected: u8 = 1;
+    let mut received = 0;
+    let mut errors = 0;
+
+    let mut header = None;
+    for _ in 0..START_TRIES {
+        uart.write_byte(CRC_MODE);
+        header = uart.read_byte_timeout(START_TIMEOUT_US);
+        if header.is_some() {
+            break;
+        }
+    }
+
+    loop {
+        let len = match header {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) => {
+                uart.write_byte(ACK);
+                return Ok(received);
+            }
+            Some(CAN) => return Err(XmodemError::Cancelled),
+            Some(_) => 0,
+            None => return Err(cancel(uart, XmodemError::Timeout)),
+        };
+
+        let data = &mut block[..len];
+        let mut trailer = [0u8; 4];
+        let valid = len > 0
+            && uart.read_bytes_timeout(&mut trailer[..2], BYTE_TIMEOUT_US) == 2
+            && uart.read_bytes_timeout(data, BYTE_TIMEOUT_US) == len
+            && uart.read_bytes_timeout(&mut trailer[2..], BYTE_TIMEOUT_US) == 2
+            && trailer[0] == !trailer[1]
+            && crc16(data) == u16::from_be_bytes([trailer[2], trailer[3]]);
+
+        if !valid {
+            errors += 1;
+            if errors == MAX_ERRORS {
+                return Err(cancel(uart, XmodemError::TooManyErrors));
+            }
+            purge(uart);
+            // Before the first block, keep asking for CRC mode.
+            uart.write_byte(if expected == 1 { CRC_MODE } else { NAK });
+        } else if trailer[0] == expected {
+            errors = 0;
+            let stored = data.len().min(dest.len() - received);
+            dest[received..received + stored].copy_from_slice(&data[..stored]);
+            received += stored;
+            if data[stored..].iter().any(|&byte| byte != SUB) {
+                return Err(cancel(uart, XmodemError::BufferFull));
+            }
+            expected = expected.wrapping_add(1);
+            uart.write_byte(ACK);
+        } else if trailer[0] == expected.wrapping_sub(1) {
+            uart.write_byte(ACK);
+        } else {
+            return Err(cancel(uart, XmodemError::Sequence));
+        }
+
+        header = uart.read_byte_timeout(BLOCK_TIMEOUT_US);
+    }
+}
+
+/// Drops whatever is still arriving of a bad block.
+fn purge(uart: &mut UART) {
+    while uart.read_byte_timeout(PURGE_TIMEOUT_US).is_some() {}
+}
+
+/// Tells the sender to stop, and returns `error` for convenience.
+fn cancel(uart: &mut UART, error: XmodemError) -> XmodemError {
+    uart.write_bytes(&[CAN, CAN]);
+    error
+}
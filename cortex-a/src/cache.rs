@@ -0,0 +1,77 @@
+//! Data cache maintenance by virtual address, and barriers.
+//!
+//! Memory shared with a bus master that does not snoop the ARM caches, like
+//! the DMA engine, the VideoCore or a core with its caches still off, needs
+//! these: clean before the other side reads, invalidate before the CPU reads
+//! what the other side wrote.
+//!
+//! The range operations work on whole lines of the smallest data cache line
+//! size in CTR_EL0.DminLine, and finish with a `dsb sy` so the maintenance
+//! has completed when they return.
+
+use core::arch::asm;
+
+/// Smallest data cache line size in bytes, from CTR_EL0.DminLine.
+pub fn dcache_line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack)) };
+    // DminLine is log2 of the number of 4-byte words.
+    4 << ((ctr >> 16) & 0xF)
+}
+
+/// Runs `op` on the address of every data cache line overlapping the `len`
+/// bytes from `address`, then waits for the operations to complete.
+fn for_each_line(address: usize, len: usize, op: impl Fn(usize)) {
+    if len == 0 {
+        return;
+    }
+    let line = dcache_line_size();
+    for line_address in (address & !(line - 1)..address + len).step_by(line) {
+        op(line_address);
+    }
+    dsb();
+}
+
+/// Writes dirty lines covering the range back to the point of coherency.
+pub fn clean_range(address: usize, len: usize) {
+    for_each_line(address, len, |line| unsafe {
+        asm!("dc cvac, {}", in(reg) line, options(nostack));
+    });
+}
+
+/// Discards the lines covering the range, so the next reads come from
+/// memory.
+///
+/// # Safety
+///
+/// Dirty data in the lines is lost, including bytes outside the range that
+/// share its first or last line. The range should be line aligned, or the
+/// bytes around it must not have been written through the cache.
+pub unsafe fn invalidate_range(address: usize, len: usize) {
+    for_each_line(address, len, |line| {
+        asm!("dc ivac, {}", in(reg) line, options(nostack));
+    });
+}
+
+/// Writes dirty lines covering the range back to the point of coherency and
+/// discards them.
+pub fn clean_invalidate_range(address: usize, len: usize) {
+    for_each_line(address, len, |line| unsafe {
+        asm!("dc civac, {}", in(reg) line, options(nostack));
+    });
+}
+
+/// Full system data synchronization barrier.
+pub fn dsb() {
+    unsafe { asm!("dsb sy", options(nostack)) };
+}
+
+/// Full system data memory barrier.
+pub fn dmb() {
+    unsafe { asm!("dmb sy", options(nostack)) };
+}
+
+/// Instruction synchronization barrier.
+pub fn isb() {
+    unsafe { asm!("isb", options(nostack)) };
+}
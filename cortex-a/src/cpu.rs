@@ -0,0 +1,31 @@
+//! Which core is running, and at which exception level.
+
+use core::arch::asm;
+
+/// Affinity level 0 of MPIDR_EL1, the core's number within the cluster. The
+/// Cortex-A53 and A72 clusters of the BCM2837 and BCM2711 have
+/// `smp::NUM_CORES` cores, so only the low two bits are used.
+#[inline]
+pub fn core_id() -> usize {
+    let mpidr: u64;
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack));
+    }
+    (mpidr & 0b11) as usize
+}
+
+/// Whether this is core 0, the one `_start` runs the kernel on.
+#[inline]
+pub fn is_boot_core() -> bool {
+    core_id() == 0
+}
+
+/// The exception level the core runs at, 0-3 (CurrentEL.EL).
+#[inline]
+pub fn current_el() -> u8 {
+    let current_el: u64;
+    unsafe {
+        asm!("mrs {}, CurrentEL", out(reg) current_el, options(nomem, nostack));
+    }
+    ((current_el >> 2) & 0b11) as u8
+}
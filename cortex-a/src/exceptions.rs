@@ -0,0 +1,240 @@
+//! EL1 exception vector table.
+//!
+//! `_start` installs `_vectors` in VBAR_EL1. IRQs taken from EL1 save the
+//! caller-saved registers and call
+//!
+//! ```ignore
+//! #[no_mangle]
+//! extern "C" fn handle_irq();
+//! ```
+//!
+//! which the chip crate provides. Every other exception calls
+//!
+//! ```ignore
+//! #[no_mangle]
+//! extern "C" fn handle_exception(kind: u64, esr: u64, far: u64, elr: u64) -> !;
+//! ```
+//!
+//! with `kind` the index of the vector entry (see `exception_name`) and the
+//! ESR_EL1, FAR_EL1 and ELR_EL1 values. Exceptions from AArch64 EL0 return
+//! to the kernel through `syscall::SysCall::switch_to_process` instead.
+//!
+//! An IRQ uses a 192 byte frame on the interrupted stack, 720 bytes with the
+//! `save-fp` feature, on top of whatever `handle_irq` needs.
+
+use core::arch::asm;
+use core::arch::global_asm;
+
+/// Size of the stack `handle_exception` runs on.
+const FAULT_STACK_SIZE: usize = 4096;
+
+#[repr(C, align(16))]
+struct FaultStack([u8; FAULT_STACK_SIZE]);
+
+/// Shared by every core; a second core faulting while the first reports
+/// would clobber it, but both halt anyway.
+#[no_mangle]
+static mut FAULT_STACK: FaultStack = FaultStack([0; FAULT_STACK_SIZE]);
+
+global_asm!(
+    "
+.macro FAULT kind
+.balign 0x80
+    mov     x0, #\\kind
+    b       fault_trampoline
+.endm
+
+.macro IRQ
+.balign 0x80
+    b       irq_trampoline
+.endm
+
+// Exceptions from a process go back to the kernel through `syscall`.
+.macro USER kind
+.balign 0x80
+    stp     x0, x1, [sp, #112]
+    mov     x0, #\\kind
+    b       user_trampoline
+.endm
+
+.section .text.vectors, \"ax\"
+.balign 0x800
+.global _vectors
+_vectors:
+    // Current EL with SP_EL0
+    FAULT 0
+    FAULT 1
+    FAULT 2
+    FAULT 3
+    // Current EL with SP_ELx
+    FAULT 4
+    IRQ
+    FAULT 6
+    FAULT 7
+    // Lower EL, AArch64
+    USER 8
+    USER 9
+    USER 10
+    USER 11
+    // Lower EL, AArch32
+    FAULT 12
+    FAULT 13
+    FAULT 14
+    FAULT 15
+
+// `handle_exception` never returns, so it gets a stack of its own. The
+// faulting one may have overflowed into its guard page.
+fault_trampoline:
+    adrp    x4, FAULT_STACK
+    add     x4, x4, :lo12:FAULT_STACK
+    add     x4, x4, #{fault_stack_size}
+    mov     sp, x4
+    mrs     x1, esr_el1
+    mrs     x2, far_el1
+    mrs     x3, elr_el1
+    b       handle_exception
+
+// Saves the registers the AAPCS64 lets `handle_irq` clobber, plus the
+// exception return state, in a 192 byte frame. With FP saving on, q0-q31,
+// FPSR and FPCR follow in another 528 bytes.
+irq_trampoline:
+    sub     sp, sp, #192
+    stp     x0, x1, [sp, #0]
+    stp     x2, x3, [sp, #16]
+    stp     x4, x5, [sp, #32]
+    stp     x6, x7, [sp, #48]
+    stp     x8, x9, [sp, #64]
+    stp     x10, x11, [sp, #80]
+    stp     x12, x13, [sp, #96]
+    stp     x14, x15, [sp, #112]
+    stp     x16, x17, [sp, #128]
+    stp     x18, x29, [sp, #144]
+    mrs     x0, elr_el1
+    stp     x30, x0, [sp, #160]
+    mrs     x0, spsr_el1
+    str     x0, [sp, #176]
+.if {save_fp}
+    sub     sp, sp, #528
+    stp     q0, q1, [sp, #0]
+    stp     q2, q3, [sp, #32]
+    stp     q4, q5, [sp, #64]
+    stp     q6, q7, [sp, #96]
+    stp     q8, q9, [sp, #128]
+    stp     q10, q11, [sp, #160]
+    stp     q12, q13, [sp, #192]
+    stp     q14, q15, [sp, #224]
+    stp     q16, q17, [sp, #256]
+    stp     q18, q19, [sp, #288]
+    stp     q20, q21, [sp, #320]
+    stp     q22, q23, [sp, #352]
+    stp     q24, q25, [sp, #384]
+    stp     q26, q27, [sp, #416]
+    stp     q28, q29, [sp, #448]
+    stp     q30, q31, [sp, #480]
+    mrs     x0, fpsr
+    mrs     x1, fpcr
+    str     x0, [sp, #512]
+    str     x1, [sp, #520]
+.endif
+
+    bl      handle_irq
+
+.if {save_fp}
+    ldr     x0, [sp, #512]
+    ldr     x1, [sp, #520]
+    msr     fpsr, x0
+    msr     fpcr, x1
+    ldp     q0, q1, [sp, #0]
+    ldp     q2, q3, [sp, #32]
+    ldp     q4, q5, [sp, #64]
+    ldp     q6, q7, [sp, #96]
+    ldp     q8, q9, [sp, #128]
+    ldp     q10, q11, [sp, #160]
+    ldp     q12, q13, [sp, #192]
+    ldp     q14, q15, [sp, #224]
+    ldp     q16, q17, [sp, #256]
+    ldp     q18, q19, [sp, #288]
+    ldp     q20, q21, [sp, #320]
+    ldp     q22, q23, [sp, #352]
+    ldp     q24, q25, [sp, #384]
+    ldp     q26, q27, [sp, #416]
+    ldp     q28, q29, [sp, #448]
+    ldp     q30, q31, [sp, #480]
+    add     sp, sp, #528
+.endif
+
+    ldr     x0, [sp, #176]
+    msr     spsr_el1, x0
+    ldp     x30, x0, [sp, #160]
+    msr     elr_el1, x0
+    ldp     x18, x29, [sp, #144]
+    ldp     x16, x17, [sp, #128]
+    ldp     x14, x15, [sp, #112]
+    ldp     x12, x13, [sp, #96]
+    ldp     x10, x11, [sp, #80]
+    ldp     x8, x9, [sp, #64]
+    ldp     x6, x7, [sp, #48]
+    ldp     x4, x5, [sp, #32]
+    ldp     x2, x3, [sp, #16]
+    ldp     x0, x1, [sp, #0]
+    add     sp, sp, #192
+    eret
+",
+    fault_stack_size = const FAULT_STACK_SIZE,
+    save_fp = const cfg!(feature = "save-fp") as u8,
+);
+
+/// Human-readable name of a vector table entry passed to `handle_exception`.
+pub fn exception_name(kind: u64) -> &'static str {
+    match kind {
+        0 => "synchronous (EL1, SP_EL0)",
+        1 => "IRQ (EL1, SP_EL0)",
+        2 => "FIQ (EL1, SP_EL0)",
+        3 => "SError (EL1, SP_EL0)",
+        4 => "synchronous (EL1)",
+        5 => "IRQ (EL1)",
+        6 => "FIQ (EL1)",
+        7 => "SError (EL1)",
+        8 => "synchronous (EL0, AArch64)",
+        9 => "IRQ (EL0, AArch64)",
+        10 => "FIQ (EL0, AArch64)",
+        11 => "SError (EL0, AArch64)",
+        12 => "synchronous (EL0, AArch32)",
+        13 => "IRQ (EL0, AArch32)",
+        14 => "FIQ (EL0, AArch32)",
+        15 => "SError (EL0, AArch32)",
+        _ => "unknown",
+    }
+}
+
+/// Unmasks IRQs (PSTATE.I).
+#[inline]
+pub unsafe fn enable_interrupts() {
+    asm!("msr daifclr, #2", options(nostack));
+}
+
+/// Masks IRQs (PSTATE.I).
+#[inline]
+pub unsafe fn disable_interrupts() {
+    asm!("msr daifset, #2", options(nostack));
+}
+
+/// DAIF.I, set while IRQs are masked.
+const DAIF_I: u64 = 1 << 7;
+
+/// Masks IRQs and returns whether they were already masked, for
+/// `restore_interrupts`.
+#[inline]
+pub unsafe fn save_and_disable_interrupts() -> bool {
+    let daif: u64;
+    asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif, options(nostack));
+    daif & DAIF_I != 0
+}
+
+/// Unmasks IRQs unless `save_and_disable_interrupts` found them masked.
+#[inline]
+pub unsafe fn restore_interrupts(masked: bool) {
+    if !masked {
+        enable_interrupts();
+    }
+}
@@ -0,0 +1,95 @@
+//! ARM generic timer, EL1 physical timer (CNTP).
+//!
+//! The counter is per-core and runs at `frequency()` Hz regardless of the CPU
+//! clock. On the BCM2837 the CNTP interrupt is delivered through the
+//! per-core local interrupt controller at 0x40000040, not the peripheral
+//! interrupt controller.
+
+use core::arch::asm;
+
+/// CNTP_CTL_EL0.ENABLE
+const CTL_ENABLE: u64 = 1 << 0;
+/// CNTP_CTL_EL0.IMASK
+const CTL_IMASK: u64 = 1 << 1;
+/// CNTP_CTL_EL0.ISTATUS
+const CTL_ISTATUS: u64 = 1 << 2;
+
+/// Current value of the physical counter (CNTPCT_EL0).
+#[inline]
+pub fn counter() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mrs {}, cntpct_el0", out(reg) value, options(nomem, nostack));
+    }
+    value
+}
+
+/// Counter frequency in Hz (CNTFRQ_EL0), as programmed by the firmware.
+#[inline]
+pub fn frequency() -> u32 {
+    let value: u64;
+    unsafe {
+        asm!("mrs {}, cntfrq_el0", out(reg) value, options(nomem, nostack));
+    }
+    value as u32
+}
+
+/// Current value of the virtual counter (CNTVCT_EL0), for timing code.
+///
+/// The `isb` keeps the read from being executed ahead of the code being
+/// measured. The virtual counter equals the physical one unless EL2 set an
+/// offset.
+#[inline]
+pub fn ticks() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("isb", "mrs {}, cntvct_el0", out(reg) value, options(nomem, nostack));
+    }
+    value
+}
+
+/// Rate of `ticks()` in Hz. CNTFRQ_EL0 is set by the firmware, so this is
+/// read at run time rather than being a constant.
+#[inline]
+pub fn ticks_per_second() -> u64 {
+    frequency() as u64
+}
+
+/// Microseconds since `start`, a value returned by `ticks()`.
+pub fn elapsed_us(start: u64) -> u64 {
+    crate::ticks::ticks_to_us(ticks().wrapping_sub(start), ticks_per_second())
+}
+
+/// Fires the timer `ticks` counter ticks from now (CNTP_TVAL_EL0).
+#[inline]
+pub fn set_timeout(ticks: u32) {
+    unsafe {
+        asm!("msr cntp_tval_el0, {}", in(reg) ticks as u64, options(nomem, nostack));
+    }
+}
+
+/// Enables the timer with its interrupt unmasked.
+#[inline]
+pub fn enable() {
+    unsafe {
+        asm!("msr cntp_ctl_el0, {}", in(reg) CTL_ENABLE, options(nomem, nostack));
+    }
+}
+
+/// Disables the timer. This also deasserts its interrupt.
+#[inline]
+pub fn disable() {
+    unsafe {
+        asm!("msr cntp_ctl_el0, {}", in(reg) CTL_IMASK, options(nomem, nostack));
+    }
+}
+
+/// Whether the timer condition has been met (CNTP_CTL_EL0.ISTATUS).
+#[inline]
+pub fn is_pending() -> bool {
+    let value: u64;
+    unsafe {
+        asm!("mrs {}, cntp_ctl_el0", out(reg) value, options(nomem, nostack));
+    }
+    value & CTL_ISTATUS != 0
+}
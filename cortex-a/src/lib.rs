@@ -1,20 +1,120 @@
 #![no_std]
 
+#[cfg(target_arch = "aarch64")]
 use core::arch::global_asm;
 
+#[cfg(target_arch = "aarch64")]
+pub mod cache;
+#[cfg(target_arch = "aarch64")]
+pub mod cpu;
+#[cfg(target_arch = "aarch64")]
+pub mod exceptions;
+#[cfg(target_arch = "aarch64")]
+pub mod generic_timer;
+#[cfg(target_arch = "aarch64")]
+pub mod mmu;
+#[cfg(target_arch = "aarch64")]
+pub mod mpu;
+#[cfg(all(feature = "semihosting", target_arch = "aarch64"))]
+pub mod semihosting;
+#[cfg(target_arch = "aarch64")]
+pub mod smp;
+#[cfg(target_arch = "aarch64")]
+pub mod stack;
+#[cfg(target_arch = "aarch64")]
+pub mod syscall;
+pub mod ticks;
+
+/// Reserves `size` bytes for the boot core's stack, and a guard page below
+/// it.
+///
+/// The stack goes into the `.stack_buffer` input section and the guard page
+/// into `.stack_guard`. The board linker script must `KEEP` both in its
+/// `.stack` output section, the guard first and page aligned, and define
+/// `_sstack_guard`/`_estack_guard` around the guard page and
+/// `_sstack`/`_estack` around the stack. `_start` sets the stack pointer to
+/// `_estack` and the stack grows down through the buffer; see `stack` for
+/// how overflows are caught. `size` must be a multiple of 16, the AArch64
+/// stack alignment, and the macro must be used exactly once.
+///
+/// ```ignore
+/// cortex_a::reserve_stack!(0x4000);
+/// ```
+#[macro_export]
+macro_rules! reserve_stack {
+    ($size:expr) => {
+        const _: () = assert!($size % 16 == 0, "the stack size must be a multiple of 16");
+
+        /// The boot core's stack, see `cortex_a::reserve_stack`.
+        #[no_mangle]
+        #[link_section = ".stack_buffer"]
+        static mut STACK_MEMORY: [u8; $size] = [0; $size];
+
+        /// Unmapped once the MMU is on, see `cortex_a::stack`.
+        #[no_mangle]
+        #[link_section = ".stack_guard"]
+        static mut STACK_GUARD: [u8; $crate::stack::GUARD_SIZE] = [0; $crate::stack::GUARD_SIZE];
+    };
+}
+
+/// Reserves `count` stacks of `size` bytes for secondary cores, for
+/// `smp::start_core`.
+///
+/// The stacks go into the `.secondary_stacks` input section, which the board
+/// linker script must `KEEP` between `_ssecondary_stacks` and
+/// `_esecondary_stacks`, 16-byte aligned. Core `id` (1 to `count`) gets the
+/// `size` bytes ending at `_ssecondary_stacks + id * size`, which is where
+/// `_secondary_start` points its stack pointer. The macro also defines the
+/// absolute symbol `_secondary_stack_size`; the linker script should
+/// `PROVIDE` it as 0 for boards that do not use the macro.
+///
+/// `size` must be a multiple of 16, as the stack pointer must stay 16-byte
+/// aligned, and the macro may be used at most once.
+///
+/// ```ignore
+/// cortex_a::reserve_stacks!(3, 0x4000);
+/// ```
+#[macro_export]
+macro_rules! reserve_stacks {
+    ($count:expr, $size:expr) => {
+        const _: () = assert!($size % 16 == 0, "the stack size must be a multiple of 16");
+        const _: () = assert!(
+            $count > 0 && $count < $crate::smp::NUM_CORES,
+            "there are only three secondary cores"
+        );
+
+        /// The secondary cores' stacks, see `cortex_a::reserve_stacks`.
+        #[no_mangle]
+        #[link_section = ".secondary_stacks"]
+        static mut SECONDARY_STACK_MEMORY: [[u8; $size]; $count] = [[0; $size]; $count];
+
+        core::arch::global_asm!(
+            ".global _secondary_stack_size",
+            ".set _secondary_stack_size, {size}",
+            size = const $size,
+        );
+    };
+}
+
 #[cfg(target_arch = "aarch64")]
 global_asm!(
     "
 .section .start, \"ax\"
 .global _start
 _start:
+	bl      _el2_to_el1
 	// read cpu id, stop slave cores
 	mrs     x1, mpidr_el1
 	and     x1, x1, #3
 	cbz     x1, 2f
-	// cpu id > 0, stop
-1: wfi
-	b       1b
+	// cpu id > 0, wait on the spin table until released (see smp)
+	mov     x2, #0xd8
+	add     x2, x2, x1, lsl #3
+1:	wfe
+	ldr     x3, [x2]
+	cbz     x3, 1b
+	mov     x0, x1
+	br      x3
 2:  // cpu id == 0
 
 	/* Enable NEON/SIMD instructions */
@@ -23,12 +123,82 @@ _start:
 	isb
 	/* -- */
 
+	/* Install the exception vectors */
+	ldr     x30, =_vectors
+	msr     vbar_el1, x30
+	isb
+
   ldr     x30, =_estack
 	mov     sp, x30
+
+	/* Copy .data from its load address, unless it is linked in place.
+	 * Words are copied if all of the addresses allow it, bytes otherwise. */
+	ldr     x0, =__data_start
+	ldr     x1, =__data_end
+	ldr     x2, =__data_load_start
+	cmp     x0, x2
+	b.eq    7f
+	orr     x3, x0, x1
+	orr     x3, x3, x2
+	tst     x3, #7
+	b.ne    6f
+5:	cmp     x0, x1
+	b.hs    7f
+	ldr     x3, [x2], #8
+	str     x3, [x0], #8
+	b       5b
+6:	cmp     x0, x1
+	b.hs    7f
+	ldrb    w3, [x2], #1
+	strb    w3, [x0], #1
+	b       6b
+7:
+	/* Zero the BSS. Both ends are 8-byte aligned by the linker script. */
+	ldr     x0, =__bss_start
+	ldr     x1, =__bss_end
+3:	cmp     x0, x1
+	b.hs    4f
+	str     xzr, [x0], #8
+	b       3b
+4:
+	/* Mark the bottom of the stack, see stack::check_stack_canary */
+	ldr     x0, =_sstack
+	ldr     x1, ={canary}
+	str     x1, [x0]
+
   bl      kernel_main
 halt:
 	wfe
 	b halt
+",
+    canary = const stack::CANARY,
+);
+
+// _el2_to_el1: returns to the caller at EL1h, with DAIF masked and SP_EL1
+// still to be set up. The firmware and QEMU enter the kernel at EL2; there
+// EL1 is set up to run AArch64 with the timers accessible and the MMU off,
+// and the return goes through `eret`. Clobbers x0.
+#[cfg(target_arch = "aarch64")]
+global_asm!(
+    "
+.section .text
+.global _el2_to_el1
+_el2_to_el1:
+	mrs     x0, CurrentEL
+	cmp     x0, #(2 << 2)
+	b.eq    1f
+	ret
+1:	mov     x0, #(1 << 31)
+	msr     hcr_el2, x0
+	mov     x0, #3
+	msr     cnthctl_el2, x0
+	msr     cntvoff_el2, xzr
+	ldr     x0, =0x30d00800
+	msr     sctlr_el1, x0
+	mov     x0, #0x3c5
+	msr     spsr_el2, x0
+	msr     elr_el2, x30
+	eret
 "
 );
 
@@ -0,0 +1,217 @@
+//! EL1 stage 1 translation with an identity map.
+//!
+//! The lowest 4 GiB are identity mapped through TTBR0_EL1 with a 4 KiB
+//! granule, starting at level 1. Everything below the peripheral base is
+//! normal memory, write-back cacheable; everything from it up is
+//! device-nGnRnE, which also covers the core-local peripherals above the SoC
+//! ones. The lowest 1 GiB and the 1 GiB holding the peripheral base are
+//! split into 2 MiB blocks by level 2 tables, so the base must be 2 MiB
+//! aligned.
+//!
+//! The lowest 2 MiB, which hold the kernel and process memory, are further
+//! split into 4 KiB pages by a level 3 table. Those pages are kernel-only
+//! until `set_user_access` opens them to EL0; this is what the MPU
+//! implementation uses to isolate processes.
+//!
+//! With the data cache on, memory shared with the VideoCore or the DMA
+//! engine (mailbox messages, the framebuffer, DMA control blocks) has to be
+//! cleaned and invalidated around each hand-over.
+
+use core::arch::asm;
+use core::ptr::addr_of;
+use core::ptr::addr_of_mut;
+
+const L1_BLOCK_SIZE: u64 = 1 << 30;
+const L2_BLOCK_SIZE: u64 = 1 << 21;
+
+/// Size of the pages EL0 access is granted in.
+pub const PAGE_SIZE: usize = 4096;
+/// End of the range mapped with pages, from address 0.
+pub const USER_WINDOW_END: usize = L2_BLOCK_SIZE as usize;
+
+/// MAIR_EL1 attribute index of normal write-back memory.
+const ATTR_NORMAL: u64 = 0;
+/// MAIR_EL1 attribute index of device-nGnRnE memory.
+const ATTR_DEVICE: u64 = 1;
+/// Attr0 = 0xFF (normal, inner/outer write-back RW-allocate), attr1 = 0x00
+/// (device-nGnRnE).
+const MAIR: u64 = 0x00FF;
+
+/// Set in every valid descriptor; an entry without it faults.
+const DESC_VALID: u64 = 0b1;
+const DESC_BLOCK: u64 = 0b01;
+const DESC_TABLE: u64 = 0b11;
+const DESC_PAGE: u64 = 0b11;
+/// AP[1]: accessible from EL0.
+const DESC_AP_EL0: u64 = 1 << 6;
+/// AP[2]: read-only.
+const DESC_AP_RO: u64 = 1 << 7;
+/// Inner shareable.
+const DESC_SH_INNER: u64 = 0b11 << 8;
+/// Access flag. Without it the first access faults.
+const DESC_AF: u64 = 1 << 10;
+/// Unprivileged execute-never.
+const DESC_UXN: u64 = 1 << 54;
+/// Privileged and unprivileged execute-never.
+const DESC_XN: u64 = 0b11 << 53;
+
+/// AttrIndx field of a block descriptor.
+const fn desc_attr(index: u64) -> u64 {
+    index << 2
+}
+
+/// Without UXN, EL0 could execute kernel memory it cannot read.
+const NORMAL: u64 = desc_attr(ATTR_NORMAL) | DESC_SH_INNER | DESC_AF | DESC_UXN;
+const NORMAL_BLOCK: u64 = DESC_BLOCK | NORMAL;
+const NORMAL_PAGE: u64 = DESC_PAGE | NORMAL;
+const DEVICE_BLOCK: u64 = DESC_BLOCK | desc_attr(ATTR_DEVICE) | DESC_AF | DESC_XN;
+
+/// T0SZ = 32 (4 GiB), inner/outer write-back walks, inner shareable, 4 KiB
+/// granule, TTBR1 walks disabled (EPD1), 32-bit physical addresses.
+const TCR: u64 = 32 | 0b01 << 8 | 0b01 << 10 | 0b11 << 12 | 1 << 23;
+
+/// SCTLR_EL1.M, C and I.
+const SCTLR_M: u64 = 1 << 0;
+const SCTLR_C: u64 = 1 << 2;
+const SCTLR_I: u64 = 1 << 12;
+
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+static mut LEVEL1: Table = Table([0; 512]);
+/// 2 MiB blocks of the lowest 1 GiB.
+static mut LEVEL2_LOW: Table = Table([0; 512]);
+/// 2 MiB blocks of the 1 GiB holding the peripheral base, if that is not
+/// the lowest one.
+static mut LEVEL2_PERIPHERAL: Table = Table([0; 512]);
+/// 4 KiB pages of the lowest 2 MiB.
+static mut LEVEL3_USER: Table = Table([0; 512]);
+
+/// Number of 1 GiB level 1 entries covering the 4 GiB input range.
+const L1_ENTRIES: usize = 4;
+
+/// Block descriptor for `address`, device memory from `peripheral_base` up.
+const fn block(address: u64, peripheral_base: u64) -> u64 {
+    if address < peripheral_base {
+        address | NORMAL_BLOCK
+    } else {
+        address | DEVICE_BLOCK
+    }
+}
+
+/// Builds the identity map with device memory from `peripheral_base` up and
+/// turns on the MMU and caches.
+///
+/// # Safety
+///
+/// Must be called once, on the boot core, before anything relies on the
+/// caches being off.
+pub unsafe fn enable(peripheral_base: usize) {
+    let peripheral_base = peripheral_base as u64;
+    let level1 = &mut *addr_of_mut!(LEVEL1);
+
+    for (index, entry) in level1.0[..L1_ENTRIES].iter_mut().enumerate() {
+        let start = index as u64 * L1_BLOCK_SIZE;
+        let level2 = if index == 0 {
+            addr_of_mut!(LEVEL2_LOW)
+        } else if peripheral_base > start && peripheral_base < start + L1_BLOCK_SIZE {
+            addr_of_mut!(LEVEL2_PERIPHERAL)
+        } else {
+            *entry = block(start, peripheral_base);
+            continue;
+        };
+        for (index, entry) in (*level2).0.iter_mut().enumerate() {
+            *entry = block(start + index as u64 * L2_BLOCK_SIZE, peripheral_base);
+        }
+        *entry = level2 as u64 | DESC_TABLE;
+    }
+
+    let level3 = &mut *addr_of_mut!(LEVEL3_USER);
+    for (index, entry) in level3.0.iter_mut().enumerate() {
+        *entry = (index * PAGE_SIZE) as u64 | NORMAL_PAGE;
+    }
+    (*addr_of_mut!(LEVEL2_LOW)).0[0] = addr_of!(LEVEL3_USER) as u64 | DESC_TABLE;
+
+    asm!(
+        "msr mair_el1, {mair}",
+        "msr tcr_el1, {tcr}",
+        "msr ttbr0_el1, {ttbr0}",
+        "dsb ish",
+        "tlbi vmalle1",
+        "dsb ish",
+        "isb",
+        "mrs {sctlr}, sctlr_el1",
+        "orr {sctlr}, {sctlr}, {enable}",
+        "msr sctlr_el1, {sctlr}",
+        "isb",
+        mair = in(reg) MAIR,
+        tcr = in(reg) TCR,
+        ttbr0 = in(reg) addr_of!(LEVEL1) as u64,
+        enable = in(reg) SCTLR_M | SCTLR_C | SCTLR_I,
+        sctlr = out(reg) _,
+        options(nostack),
+    );
+}
+
+/// What EL0 may do with a page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserAccess {
+    None,
+    ReadOnly,
+    ReadWrite,
+    ReadExecute,
+    ReadWriteExecute,
+}
+
+/// Sets EL0 access to the pages overlapping `start..end`, which must be
+/// below `USER_WINDOW_END`. The kernel keeps read/write access to all of
+/// them, and pages removed with `unmap_page` stay unmapped. Takes effect
+/// after `flush_tlb`.
+///
+/// # Safety
+///
+/// `enable` must have run, and no page of the kernel's own code may be made
+/// writable from EL0, as that also makes it execute-never at EL1.
+pub unsafe fn set_user_access(start: usize, end: usize, access: UserAccess) {
+    let level3 = &mut *addr_of_mut!(LEVEL3_USER);
+    let first = start / PAGE_SIZE;
+    let last = end.min(USER_WINDOW_END).div_ceil(PAGE_SIZE);
+    let bits = match access {
+        UserAccess::None => NORMAL_PAGE,
+        UserAccess::ReadOnly => NORMAL_PAGE | DESC_AP_EL0 | DESC_AP_RO,
+        UserAccess::ReadWrite => NORMAL_PAGE | DESC_AP_EL0,
+        UserAccess::ReadExecute => (NORMAL_PAGE & !DESC_UXN) | DESC_AP_EL0 | DESC_AP_RO,
+        UserAccess::ReadWriteExecute => (NORMAL_PAGE & !DESC_UXN) | DESC_AP_EL0,
+    };
+    for page in first..last {
+        if level3.0[page] & DESC_VALID != 0 {
+            level3.0[page] = (page * PAGE_SIZE) as u64 | bits;
+        }
+    }
+}
+
+/// Removes the page holding `address`, which must be below
+/// `USER_WINDOW_END`, so that any access to it faults. Takes effect after
+/// `flush_tlb`.
+///
+/// # Safety
+///
+/// `enable` must have run, and nothing may still rely on the page.
+pub unsafe fn unmap_page(address: usize) {
+    let level3 = &mut *addr_of_mut!(LEVEL3_USER);
+    level3.0[address / PAGE_SIZE] = 0;
+}
+
+/// Makes table updates visible to the table walker and drops stale TLB
+/// entries.
+pub fn flush_tlb() {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vmalle1",
+            "dsb ish",
+            "isb",
+            options(nostack)
+        );
+    }
+}
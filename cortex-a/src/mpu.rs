@@ -0,0 +1,252 @@
+//! Tock `MPU` on top of the translation tables.
+//!
+//! The Cortex-A53 has no PMSA MPU, so process memory is protected with the
+//! level 3 page table `mmu` keeps for the lowest 2 MiB. There is a single
+//! address space: every page is always mapped for the kernel, and
+//! `configure_mpu` only changes which pages EL0 may touch. Regions are
+//! therefore page granular and must lie below `mmu::USER_WINDOW_END`.
+//!
+//! Execute-only has no EL0 encoding and is granted as read/execute.
+
+use core::cell::Cell;
+use core::fmt;
+
+use crate::mmu;
+use crate::mmu::UserAccess;
+use crate::mmu::PAGE_SIZE;
+
+use kernel::platform::mpu;
+use kernel::platform::mpu::Permissions;
+use kernel::platform::mpu::Region;
+
+/// Number of regions besides process memory a process may have.
+const NUM_REGIONS: usize = 8;
+
+const fn page_align_up(address: usize) -> usize {
+    (address + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+const fn page_align_down(address: usize) -> usize {
+    address & !(PAGE_SIZE - 1)
+}
+
+fn user_access(permissions: Permissions) -> UserAccess {
+    match permissions {
+        Permissions::ReadWriteExecute => UserAccess::ReadWriteExecute,
+        Permissions::ReadWriteOnly => UserAccess::ReadWrite,
+        Permissions::ReadExecuteOnly | Permissions::ExecuteOnly => UserAccess::ReadExecute,
+        Permissions::ReadOnly => UserAccess::ReadOnly,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct PageRange {
+    start: usize,
+    size: usize,
+    access: UserAccess,
+}
+
+/// Process memory, of which EL0 may access `start..app_break`.
+#[derive(Clone, Copy)]
+struct AppMemory {
+    start: usize,
+    size: usize,
+    app_break: usize,
+    access: UserAccess,
+}
+
+/// EL0 access of one process.
+#[derive(Default)]
+pub struct MpuConfig {
+    regions: [Option<PageRange>; NUM_REGIONS],
+    app_memory: Option<AppMemory>,
+}
+
+impl fmt::Display for MpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\r\n Page table regions:\r\n")?;
+        if let Some(app) = self.app_memory {
+            write!(
+                f,
+                "  App memory: {:#010x}-{:#010x}, break {:#010x}, {:?}\r\n",
+                app.start,
+                app.start + app.size,
+                app.app_break,
+                app.access
+            )?;
+        }
+        for (index, region) in self.regions.iter().enumerate() {
+            match region {
+                Some(region) => write!(
+                    f,
+                    "  <{}>: {:#010x}-{:#010x}, {:?}\r\n",
+                    index,
+                    region.start,
+                    region.start + region.size,
+                    region.access
+                )?,
+                None => write!(f, "  <{}>: unused\r\n", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Mpu {
+    /// Whether the window was opened to EL0 since it was last closed.
+    configured: Cell<bool>,
+}
+
+impl Mpu {
+    /// # Safety
+    ///
+    /// `mmu::enable` must have run before the first process is configured.
+    pub const unsafe fn new() -> Mpu {
+        Mpu {
+            configured: Cell::new(false),
+        }
+    }
+
+    fn close_window(&self) {
+        unsafe { mmu::set_user_access(0, mmu::USER_WINDOW_END, UserAccess::None) };
+        self.configured.set(false);
+    }
+}
+
+impl mpu::MPU for Mpu {
+    type MpuConfig = MpuConfig;
+
+    /// The translation tables are always in use, so there is nothing to turn
+    /// on.
+    fn enable_app_mpu(&self) {}
+
+    /// EL0 access never restricts the kernel, so the process pages are left
+    /// open until the next `configure_mpu`.
+    fn disable_app_mpu(&self) {}
+
+    fn number_total_regions(&self) -> usize {
+        NUM_REGIONS
+    }
+
+    fn new_config(&self) -> Option<MpuConfig> {
+        Some(MpuConfig::default())
+    }
+
+    fn reset_config(&self, config: &mut MpuConfig) {
+        *config = MpuConfig::default();
+    }
+
+    fn allocate_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_region_size: usize,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Option<Region> {
+        let slot = config.regions.iter_mut().find(|region| region.is_none())?;
+
+        let unallocated_start = unallocated_memory_start as usize;
+        let start = page_align_up(unallocated_start);
+        let size = page_align_up(min_region_size);
+        let end = start.checked_add(size)?;
+        if end > unallocated_start + unallocated_memory_size || end > mmu::USER_WINDOW_END {
+            return None;
+        }
+
+        *slot = Some(PageRange {
+            start,
+            size,
+            access: user_access(permissions),
+        });
+        Some(Region::new(start as *const u8, size))
+    }
+
+    fn remove_memory_region(&self, region: Region, config: &mut MpuConfig) -> Result<(), ()> {
+        let slot = config
+            .regions
+            .iter_mut()
+            .find(|slot| {
+                slot.is_some_and(|slot| {
+                    slot.start == region.start_address() as usize && slot.size == region.size()
+                })
+            })
+            .ok_or(())?;
+        *slot = None;
+        Ok(())
+    }
+
+    fn allocate_app_memory_region(
+        &self,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Option<(*const u8, usize)> {
+        if config.app_memory.is_some() {
+            return None;
+        }
+
+        let unallocated_start = unallocated_memory_start as usize;
+        let start = page_align_up(unallocated_start);
+        // The app and kernel parts must not share a page, or the process
+        // could reach the grant memory.
+        let initial_size =
+            page_align_up(initial_app_memory_size) + page_align_up(initial_kernel_memory_size);
+        let size = page_align_up(min_memory_size.max(initial_size));
+        let end = start.checked_add(size)?;
+        if end > unallocated_start + unallocated_memory_size || end > mmu::USER_WINDOW_END {
+            return None;
+        }
+
+        config.app_memory = Some(AppMemory {
+            start,
+            size,
+            app_break: start + initial_app_memory_size,
+            access: user_access(permissions),
+        });
+        Some((start as *const u8, size))
+    }
+
+    fn update_app_memory_region(
+        &self,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        permissions: Permissions,
+        config: &mut MpuConfig,
+    ) -> Result<(), ()> {
+        let app = config.app_memory.as_mut().ok_or(())?;
+        let app_break = app_memory_break as usize;
+        let kernel_break = kernel_memory_break as usize;
+
+        if app_break < app.start
+            || kernel_break > app.start + app.size
+            || page_align_up(app_break) > page_align_down(kernel_break)
+        {
+            return Err(());
+        }
+
+        app.app_break = app_break;
+        app.access = user_access(permissions);
+        Ok(())
+    }
+
+    fn configure_mpu(&self, config: &MpuConfig) {
+        if self.configured.get() {
+            self.close_window();
+        }
+        unsafe {
+            for region in config.regions.iter().flatten() {
+                mmu::set_user_access(region.start, region.start + region.size, region.access);
+            }
+            if let Some(app) = config.app_memory {
+                mmu::set_user_access(app.start, app.app_break, app.access);
+            }
+        }
+        self.configured.set(true);
+        mmu::flush_tlb();
+    }
+}
@@ -0,0 +1,67 @@
+//! ARM semihosting, for output and exit codes under QEMU.
+//!
+//! Operations trap to the debugger or emulator through `hlt #0xF000`, with
+//! the operation number in w0 and its parameter in x1. QEMU only handles
+//! them when started with `-semihosting`; on real hardware without a
+//! debugger attached they raise an exception instead.
+
+use core::arch::asm;
+use core::fmt;
+
+/// Writes a NUL-terminated string to the host console.
+const SYS_WRITE0: u64 = 0x04;
+/// Reports an exit to the host.
+const SYS_EXIT: u64 = 0x18;
+
+/// SYS_EXIT reason for a normal application exit.
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Bytes written per SYS_WRITE0 call, excluding the terminator.
+const CHUNK: usize = 64;
+
+/// Issues semihosting operation `op` with parameter `param`.
+unsafe fn call(op: u64, param: usize) -> u64 {
+    let result;
+    asm!(
+        "hlt #0xF000",
+        inout("x0") op => result,
+        in("x1") param,
+        options(nostack)
+    );
+    result
+}
+
+/// Writes `s` to the host console.
+///
+/// NUL bytes in `s` end the chunk they appear in, as SYS_WRITE0 stops at
+/// them.
+pub fn write_str(s: &str) {
+    let mut buffer = [0u8; CHUNK + 1];
+    for chunk in s.as_bytes().chunks(CHUNK) {
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        buffer[chunk.len()] = 0;
+        // Safety: the buffer is NUL-terminated and outlives the call.
+        unsafe { call(SYS_WRITE0, buffer.as_ptr() as usize) };
+    }
+}
+
+/// Ends the emulation with exit status `code`.
+pub fn exit(code: u32) -> ! {
+    let block = [ADP_STOPPED_APPLICATION_EXIT, code as u64];
+    // Safety: the parameter block outlives the call.
+    unsafe { call(SYS_EXIT, block.as_ptr() as usize) };
+    // Without a host to stop us, there is nothing left to do.
+    loop {
+        unsafe { asm!("wfe", options(nomem, nostack)) };
+    }
+}
+
+/// `fmt::Write` adapter for `write_str`.
+pub struct HostConsole;
+
+impl fmt::Write for HostConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_str(s);
+        Ok(())
+    }
+}
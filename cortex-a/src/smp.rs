@@ -0,0 +1,123 @@
+//! Starting the secondary cores.
+//!
+//! `_start` parks cores 1-3 on the spin table the firmware uses: each core
+//! waits in `wfe` until the 64-bit release address for it (0xE0, 0xE8 and
+//! 0xF0 for cores 1, 2 and 3) is non-zero, then jumps there with its core ID
+//! in x0. The firmware's own armstub behaves the same way, so this works
+//! whether or not `_start` runs on the secondaries.
+//!
+//! Each secondary runs on a stack of its own from `reserve_stacks!`:
+//! `_secondary_start` sets `sp = _ssecondary_stacks + id * size`, the top of
+//! the slot of core `id`. There is no guard page between the slots, so a
+//! secondary overflowing its stack runs into the one of the core below it.
+//!
+//! The secondaries poll with their MMU and caches off, and so read RAM
+//! directly. Anything the primary writes for them has to be cleaned from its
+//! data cache to the point of coherency (`dc civac`) before the `sev`, or
+//! they may see stale values once the primary has caches enabled.
+
+use core::arch::asm;
+use core::arch::global_asm;
+use core::mem::size_of;
+use core::ptr::addr_of;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use crate::cache;
+
+/// Number of cores on the BCM2837.
+pub const NUM_CORES: usize = 4;
+
+/// Spin-table release address of core `id`.
+pub const fn release_address(id: usize) -> usize {
+    0xD8 + 8 * id
+}
+
+/// Rust entry point of a secondary core, called with the core ID.
+pub type Entry = extern "C" fn(usize) -> !;
+
+/// Entry point of each core, read by `_secondary_start`.
+#[no_mangle]
+static SECONDARY_ENTRIES: [AtomicUsize; NUM_CORES] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+global_asm!(
+    "
+.section .text
+.global _secondary_start
+_secondary_start:
+    bl      _el2_to_el1
+    mrs     x0, mpidr_el1
+    and     x0, x0, #3
+
+    /* Same per-core setup as the boot core gets in _start */
+    mov     x30, #(0x3 << 20)
+    msr     cpacr_el1, x30
+    isb
+    ldr     x30, =_vectors
+    msr     vbar_el1, x30
+    isb
+
+    /* sp = _ssecondary_stacks + id * size, see reserve_stacks! */
+    ldr     x1, =_ssecondary_stacks
+    ldr     x2, =_secondary_stack_size
+    madd    x1, x0, x2, x1
+    mov     sp, x1
+    ldr     x1, =SECONDARY_ENTRIES
+    ldr     x2, [x1, x0, lsl #3]
+    br      x2
+"
+);
+
+extern "C" {
+    fn _secondary_start() -> !;
+    static _ssecondary_stacks: u8;
+    static _esecondary_stacks: u8;
+    /// An absolute symbol whose address is the stack size, 0 without
+    /// `reserve_stacks!`.
+    static _secondary_stack_size: u8;
+}
+
+/// Number of secondary cores `reserve_stacks!` reserved a stack for.
+pub fn reserved_stacks() -> usize {
+    // Safety: only the addresses of the linker symbols are taken.
+    let (start, end, size) = unsafe {
+        (
+            addr_of!(_ssecondary_stacks) as usize,
+            addr_of!(_esecondary_stacks) as usize,
+            addr_of!(_secondary_stack_size) as usize,
+        )
+    };
+    if size == 0 {
+        0
+    } else {
+        (end - start) / size
+    }
+}
+
+/// Releases core `id` (1-3) from the spin table to run `entry` on its stack
+/// from `reserve_stacks!`.
+///
+/// # Safety
+///
+/// Core `id` must still be parked.
+pub unsafe fn start_core(id: usize, entry: Entry) {
+    assert!(id > 0 && id < NUM_CORES, "invalid secondary core {}", id);
+    assert!(id <= reserved_stacks(), "no stack reserved for core {}", id);
+
+    SECONDARY_ENTRIES[id].store(entry as usize, Ordering::SeqCst);
+    let release = release_address(id) as *mut u64;
+    core::ptr::write_volatile(release, _secondary_start as usize as u64);
+
+    for address in [
+        &SECONDARY_ENTRIES[id] as *const AtomicUsize as usize,
+        release as usize,
+    ] {
+        cache::clean_invalidate_range(address, size_of::<u64>());
+    }
+    asm!("sev", options(nostack));
+}
@@ -0,0 +1,53 @@
+//! Boot stack overflow detection.
+//!
+//! Below the stack reserved with `reserve_stack!` sits a guard page. Once the
+//! MMU is on, `enable_guard` unmaps it, so an overflow takes a translation
+//! fault. `handle_exception` runs on a stack of its own and can recognize the
+//! fault with `is_guard_fault`.
+//!
+//! Before that, or if the stack pointer jumps past the guard page, the
+//! canary `_start` writes to the lowest word of the stack is the only
+//! indication: `check_stack_canary` fails once it has been overwritten.
+
+use core::ptr::addr_of;
+
+use crate::mmu;
+
+/// Size of the guard page.
+pub const GUARD_SIZE: usize = mmu::PAGE_SIZE;
+
+/// Value `_start` writes to the lowest word of the stack.
+pub const CANARY: u64 = 0x5354_4143_4B5F_4F4B;
+
+extern "C" {
+    static _sstack: u64;
+    static _sstack_guard: u8;
+    static _estack_guard: u8;
+}
+
+/// Whether the canary at the bottom of the stack is intact.
+pub fn check_stack_canary() -> bool {
+    // Safety: `_sstack` is the 8-byte aligned start of the stack buffer.
+    unsafe { core::ptr::read_volatile(addr_of!(_sstack)) == CANARY }
+}
+
+fn guard_range() -> core::ops::Range<usize> {
+    unsafe { addr_of!(_sstack_guard) as usize..addr_of!(_estack_guard) as usize }
+}
+
+/// Whether a fault at `far` hit the guard page, i.e. the stack overflowed.
+pub fn is_guard_fault(far: u64) -> bool {
+    guard_range().contains(&(far as usize))
+}
+
+/// Unmaps the guard page.
+///
+/// # Safety
+///
+/// `mmu::enable` must have run.
+pub unsafe fn enable_guard() {
+    for page in guard_range().step_by(mmu::PAGE_SIZE) {
+        mmu::unmap_page(page);
+    }
+    mmu::flush_tlb();
+}
@@ -0,0 +1,359 @@
+//! Tock userspace/kernel boundary for AArch64.
+//!
+//! Processes run at EL0 on SP_EL0 with IRQs unmasked. `switch_to_process`
+//! saves the kernel's callee-saved registers on its own stack, loads the
+//! process registers and `eret`s. Any exception taken from EL0 lands in
+//! `user_trampoline` (see the lower-EL entries of `_vectors`), which saves
+//! the process registers back and returns to the kernel as if
+//! `_switch_to_user` had returned.
+//!
+//! The FP/SIMD registers of a process (q0-q31, FPSR and FPCR) are switched
+//! with it, and the kernel's callee-saved d8-d15 are preserved across
+//! `_switch_to_user` as the AAPCS64 requires.
+//!
+//! Syscall ABI: a process issues `svc #class`, with the Tock syscall class
+//! as the immediate. The arguments are in x0-x3 and the return value is
+//! passed back in x0-x3, as 32-bit values zero-extended to 64 bits. All
+//! other registers are preserved. Upcalls are entered with their four
+//! arguments in x0-x3 and x30 pointing back to where the process was, so
+//! returning from the upcall resumes it.
+
+use core::arch::global_asm;
+use core::fmt::Write;
+
+use kernel::process::FunctionCall;
+use kernel::syscall::ContextSwitchReason;
+use kernel::syscall::Syscall;
+use kernel::syscall::SyscallReturn;
+use kernel::syscall::UserspaceKernelBoundary;
+use kernel::ErrorCode;
+
+/// Register state of a process saved while the kernel runs.
+///
+/// The layout is relied on by `_switch_to_user` and `user_trampoline`.
+#[derive(Default)]
+#[repr(C, align(16))]
+pub struct Aarch64StoredState {
+    /// x0-x30
+    regs: [u64; 31],
+    /// SP_EL0
+    sp: u64,
+    /// ELR_EL1, where the process resumes.
+    pc: u64,
+    /// SPSR_EL1
+    spsr: u64,
+    /// ESR_EL1 of the last exception taken from the process.
+    esr: u64,
+    /// FAR_EL1 of the last exception taken from the process.
+    far: u64,
+    /// q0-q31
+    fp_regs: [u128; 32],
+    fpsr: u64,
+    fpcr: u64,
+}
+
+/// Number of words `store_context` writes. The FP/SIMD registers are not
+/// included.
+const STORED_STATE_WORDS: usize = 36;
+
+/// Index of the link register in `Aarch64StoredState::regs`.
+const LR: usize = 30;
+
+/// SPSR_EL1 for a process: EL0 with SP_EL0 (EL0t) and nothing masked.
+const SPSR_EL0T: u64 = 0;
+
+/// ESR_EL1.EC of an `svc` executed in AArch64 state.
+const EC_SVC64: u64 = 0x15;
+
+/// Vector table entries of exceptions taken from EL0, as passed back by
+/// `_switch_to_user`.
+const KIND_LOWER_SYNC: u64 = 8;
+const KIND_LOWER_IRQ: u64 = 9;
+const KIND_LOWER_FIQ: u64 = 10;
+
+global_asm!(
+    "
+.section .text
+// _switch_to_user(state: *mut Aarch64StoredState) -> u64
+//
+// Frame on the kernel stack, 192 bytes:
+//   0-95    x19-x30
+//   96      state
+//   104     DAIF of the kernel
+//   112-127 scratch for x0 and x1 of the process
+//   128-191 d8-d15
+//
+// The FP/SIMD state of the process starts at offset 288 of the state: q0-q31
+// followed by FPSR and FPCR.
+.global _switch_to_user
+_switch_to_user:
+    sub     sp, sp, #192
+    stp     x19, x20, [sp, #0]
+    stp     x21, x22, [sp, #16]
+    stp     x23, x24, [sp, #32]
+    stp     x25, x26, [sp, #48]
+    stp     x27, x28, [sp, #64]
+    stp     x29, x30, [sp, #80]
+    mrs     x1, daif
+    stp     x0, x1, [sp, #96]
+    stp     d8, d9, [sp, #128]
+    stp     d10, d11, [sp, #144]
+    stp     d12, d13, [sp, #160]
+    stp     d14, d15, [sp, #176]
+
+    add     x1, x0, #288
+    ldp     q0, q1, [x1, #0]
+    ldp     q2, q3, [x1, #32]
+    ldp     q4, q5, [x1, #64]
+    ldp     q6, q7, [x1, #96]
+    ldp     q8, q9, [x1, #128]
+    ldp     q10, q11, [x1, #160]
+    ldp     q12, q13, [x1, #192]
+    ldp     q14, q15, [x1, #224]
+    ldp     q16, q17, [x1, #256]
+    ldp     q18, q19, [x1, #288]
+    ldp     q20, q21, [x1, #320]
+    ldp     q22, q23, [x1, #352]
+    ldp     q24, q25, [x1, #384]
+    ldp     q26, q27, [x1, #416]
+    ldp     q28, q29, [x1, #448]
+    ldp     q30, q31, [x1, #480]
+    ldr     x2, [x1, #512]
+    ldr     x3, [x1, #520]
+    msr     fpsr, x2
+    msr     fpcr, x3
+
+    ldr     x1, [x0, #248]
+    msr     sp_el0, x1
+    ldr     x1, [x0, #256]
+    msr     elr_el1, x1
+    ldr     x1, [x0, #264]
+    msr     spsr_el1, x1
+
+    ldp     x2, x3, [x0, #16]
+    ldp     x4, x5, [x0, #32]
+    ldp     x6, x7, [x0, #48]
+    ldp     x8, x9, [x0, #64]
+    ldp     x10, x11, [x0, #80]
+    ldp     x12, x13, [x0, #96]
+    ldp     x14, x15, [x0, #112]
+    ldp     x16, x17, [x0, #128]
+    ldp     x18, x19, [x0, #144]
+    ldp     x20, x21, [x0, #160]
+    ldp     x22, x23, [x0, #176]
+    ldp     x24, x25, [x0, #192]
+    ldp     x26, x27, [x0, #208]
+    ldp     x28, x29, [x0, #224]
+    ldr     x30, [x0, #240]
+    ldp     x0, x1, [x0, #0]
+    eret
+
+// Entered from the lower-EL vectors with the process's x0 and x1 in the
+// scratch slots and the vector index in x0. SP_EL1 is where
+// _switch_to_user left it.
+.global user_trampoline
+user_trampoline:
+    ldr     x1, [sp, #96]
+    stp     x2, x3, [x1, #16]
+    stp     x4, x5, [x1, #32]
+    stp     x6, x7, [x1, #48]
+    stp     x8, x9, [x1, #64]
+    stp     x10, x11, [x1, #80]
+    stp     x12, x13, [x1, #96]
+    stp     x14, x15, [x1, #112]
+    stp     x16, x17, [x1, #128]
+    stp     x18, x19, [x1, #144]
+    stp     x20, x21, [x1, #160]
+    stp     x22, x23, [x1, #176]
+    stp     x24, x25, [x1, #192]
+    stp     x26, x27, [x1, #208]
+    stp     x28, x29, [x1, #224]
+    str     x30, [x1, #240]
+    ldp     x2, x3, [sp, #112]
+    stp     x2, x3, [x1, #0]
+
+    mrs     x2, sp_el0
+    str     x2, [x1, #248]
+    mrs     x2, elr_el1
+    str     x2, [x1, #256]
+    mrs     x2, spsr_el1
+    str     x2, [x1, #264]
+    mrs     x2, esr_el1
+    str     x2, [x1, #272]
+    mrs     x2, far_el1
+    str     x2, [x1, #280]
+
+    add     x2, x1, #288
+    stp     q0, q1, [x2, #0]
+    stp     q2, q3, [x2, #32]
+    stp     q4, q5, [x2, #64]
+    stp     q6, q7, [x2, #96]
+    stp     q8, q9, [x2, #128]
+    stp     q10, q11, [x2, #160]
+    stp     q12, q13, [x2, #192]
+    stp     q14, q15, [x2, #224]
+    stp     q16, q17, [x2, #256]
+    stp     q18, q19, [x2, #288]
+    stp     q20, q21, [x2, #320]
+    stp     q22, q23, [x2, #352]
+    stp     q24, q25, [x2, #384]
+    stp     q26, q27, [x2, #416]
+    stp     q28, q29, [x2, #448]
+    stp     q30, q31, [x2, #480]
+    mrs     x3, fpsr
+    mrs     x4, fpcr
+    str     x3, [x2, #512]
+    str     x4, [x2, #520]
+
+    // If the kernel had IRQs unmasked, a pending IRQ is taken as soon as
+    // DAIF is restored.
+    ldr     x2, [sp, #104]
+    ldp     x19, x20, [sp, #0]
+    ldp     x21, x22, [sp, #16]
+    ldp     x23, x24, [sp, #32]
+    ldp     x25, x26, [sp, #48]
+    ldp     x27, x28, [sp, #64]
+    ldp     x29, x30, [sp, #80]
+    ldp     d8, d9, [sp, #128]
+    ldp     d10, d11, [sp, #144]
+    ldp     d12, d13, [sp, #160]
+    ldp     d14, d15, [sp, #176]
+    add     sp, sp, #192
+    msr     daif, x2
+    ret
+"
+);
+
+extern "C" {
+    fn _switch_to_user(state: *mut Aarch64StoredState) -> u64;
+}
+
+pub struct SysCall(());
+
+impl SysCall {
+    pub const unsafe fn new() -> SysCall {
+        SysCall(())
+    }
+}
+
+impl UserspaceKernelBoundary for SysCall {
+    type StoredState = Aarch64StoredState;
+
+    fn initial_process_app_brk_size(&self) -> usize {
+        // Process state lives in the kernel, not on the process stack.
+        0
+    }
+
+    unsafe fn initialize_process(
+        &self,
+        accessible_memory_start: *const u8,
+        app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> Result<(), ()> {
+        if app_brk < accessible_memory_start {
+            return Err(());
+        }
+        *state = Aarch64StoredState::default();
+        // The stack grows down from the initial break, 16-byte aligned as the
+        // AAPCS64 requires.
+        state.sp = app_brk as u64 & !0xF;
+        state.spsr = SPSR_EL0T;
+        Ok(())
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        return_value: SyscallReturn,
+    ) -> Result<(), ()> {
+        let (mut r0, mut r1, mut r2, mut r3) = (0, 0, 0, 0);
+        return_value.encode_syscall_return(&mut r0, &mut r1, &mut r2, &mut r3);
+        state.regs[0] = r0 as u64;
+        state.regs[1] = r1 as u64;
+        state.regs[2] = r2 as u64;
+        state.regs[3] = r3 as u64;
+        Ok(())
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+        callback: FunctionCall,
+    ) -> Result<(), ()> {
+        state.regs[0] = callback.argument0 as u64;
+        state.regs[1] = callback.argument1 as u64;
+        state.regs[2] = callback.argument2 as u64;
+        state.regs[3] = callback.argument3 as u64;
+        state.regs[LR] = state.pc;
+        state.pc = callback.pc as u64;
+        Ok(())
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &mut Self::StoredState,
+    ) -> (ContextSwitchReason, Option<*const u8>) {
+        let kind = _switch_to_user(state);
+
+        let reason = match kind {
+            KIND_LOWER_SYNC if state.esr >> 26 == EC_SVC64 => {
+                let class = (state.esr & 0xFFFF) as u8;
+                match Syscall::from_register_arguments(
+                    class,
+                    state.regs[0] as usize,
+                    state.regs[1] as usize,
+                    state.regs[2] as usize,
+                    state.regs[3] as usize,
+                ) {
+                    Some(syscall) => ContextSwitchReason::SyscallFired { syscall },
+                    None => ContextSwitchReason::Fault,
+                }
+            }
+            KIND_LOWER_IRQ | KIND_LOWER_FIQ => ContextSwitchReason::Interrupted,
+            _ => ContextSwitchReason::Fault,
+        };
+        (reason, Some(state.sp as *const u8))
+    }
+
+    unsafe fn print_context(
+        &self,
+        _accessible_memory_start: *const u8,
+        _app_brk: *const u8,
+        state: &Self::StoredState,
+        writer: &mut dyn Write,
+    ) {
+        for (index, pair) in state.regs.chunks(2).enumerate() {
+            let _ = write!(writer, " x{:<2}: {:#018x}", index * 2, pair[0]);
+            if let Some(odd) = pair.get(1) {
+                let _ = write!(writer, "   x{:<2}: {:#018x}", index * 2 + 1, odd);
+            }
+            let _ = writer.write_str("\r\n");
+        }
+        let _ = write!(
+            writer,
+            " sp : {:#018x}   pc : {:#018x}\r\n spsr: {:#010x}   esr: {:#010x}   far: {:#018x}\r\n",
+            state.sp, state.pc, state.spsr, state.esr, state.far
+        );
+    }
+
+    fn store_context(&self, state: &Self::StoredState, out: &mut [u8]) -> Result<usize, ErrorCode> {
+        let size = STORED_STATE_WORDS * 8;
+        if out.len() < size {
+            return Err(ErrorCode::SIZE);
+        }
+        let words = state
+            .regs
+            .iter()
+            .chain([state.sp, state.pc, state.spsr, state.esr, state.far].iter());
+        for (chunk, word) in out.chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(size)
+    }
+}
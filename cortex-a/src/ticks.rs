@@ -0,0 +1,22 @@
+//! Conversions between counter ticks and microseconds at a given rate.
+//!
+//! Both truncate and are done in 128 bits, so they do not overflow for any
+//! 64-bit count, and saturate at `u64::MAX` where the result does not fit.
+
+/// Microseconds in `ticks` ticks at `frequency` Hz, rounded down.
+pub const fn ticks_to_us(ticks: u64, frequency: u64) -> u64 {
+    saturate(ticks as u128 * 1_000_000 / frequency as u128)
+}
+
+/// Ticks at `frequency` Hz in `us` microseconds, rounded down.
+pub const fn us_to_ticks(us: u64, frequency: u64) -> u64 {
+    saturate(us as u128 * frequency as u128 / 1_000_000)
+}
+
+const fn saturate(value: u128) -> u64 {
+    if value > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        value as u64
+    }
+}
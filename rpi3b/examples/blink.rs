@@ -0,0 +1,39 @@
+//! Blinks the activity LED twice a second, without the kernel, as a first
+//! test of a board. Build it with `cargo build --example blink`; the image
+//! boots like the kernel's.
+
+#![no_main]
+#![no_std]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+use bcm2837::led::ActivityLed;
+use kernel::hil::led::Led;
+
+cortex_a::reserve_stack!(0x2000);
+
+#[no_mangle]
+pub extern "C" fn kernel_main() {
+    let soc = bcm2837::soc::Soc::detect();
+    // Safety: nothing has touched memory that would be affected by turning
+    // on the caches yet.
+    unsafe { cortex_a::mmu::enable(soc.peripheral_base) };
+
+    // Safety: nothing else runs.
+    let led = unsafe { ActivityLed::detect() };
+    led.init();
+    loop {
+        led.toggle();
+        bcm2837::delay::delay_ms(500);
+    }
+}
+
+#[panic_handler]
+fn panic(_panic_info: &PanicInfo<'_>) -> ! {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
@@ -0,0 +1,20 @@
+#!/bin/sh
+
+# Boots the echo loop in QEMU's raspi3b machine and checks that it prints
+# "Hello world" before exiting through semihosting.
+
+set -e
+
+cd "$(dirname "$0")"
+cargo build --release --features smoke-test
+
+output=$(timeout 30 qemu-system-aarch64 \
+  -M raspi3b \
+  -smp 4 \
+  -semihosting \
+  -serial null \
+  -serial stdio \
+  -display none \
+  -kernel ../target/aarch64-unknown-none/release/raspberry_pi)
+echo "$output"
+echo "$output" | grep -q "Hello world"
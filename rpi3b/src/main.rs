@@ -5,33 +5,214 @@ use bcm2837;
 use core::arch::asm;
 use core::fmt::Write;
 
-/// Dummy buffer that causes the linker to reserve enough space for the stack.
-#[no_mangle]
-#[link_section = ".stack_buffer"]
-pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
+#[cfg(not(feature = "echo"))]
+use kernel::capabilities;
+#[cfg(not(feature = "echo"))]
+use kernel::create_capability;
+#[cfg(not(feature = "echo"))]
+use kernel::debug;
+
+#[cfg(not(feature = "echo"))]
+mod platform;
+
+cortex_a::reserve_stack!(0x2000);
+
+/// Time the board has to get from `kernel_main` to the main loop or the
+/// echo loop before the watchdog resets it.
+const BOOT_TIMEOUT_MS: u32 = 10_000;
 
 #[no_mangle]
 pub extern "C" fn kernel_main() {
-    let mut uart = unsafe { bcm2837::uart::UART::uart1() };
-    let _ = write!(&mut uart, "Hello world\n");
+    // Safety: nothing else uses the watchdog.
+    let watchdog = unsafe { bcm2837::power::BootWatchdog::arm(BOOT_TIMEOUT_MS) };
+    let soc = bcm2837::soc::Soc::detect();
+    // Safety: nothing has touched memory that would be affected by turning
+    // on the caches yet.
+    unsafe { cortex_a::mmu::enable(soc.peripheral_base) };
+    // Safety: the MMU is on and nothing lives in the guard page.
+    unsafe { cortex_a::stack::enable_guard() };
+    print_board_info();
+
+    #[cfg(feature = "echo")]
+    echo(watchdog);
+
+    #[cfg(not(feature = "echo"))]
+    {
+        // Safety: this is the only call, and the MMU is on.
+        let (board_kernel, platform, chip) = unsafe { platform::setup() };
+        let main_loop_capability = create_capability!(capabilities::MainLoopCapability);
+        watchdog.boot_complete();
+        debug!("Initialization complete. Entering main loop");
+        unsafe { cortex_a::exceptions::enable_interrupts() };
+        board_kernel.kernel_loop(
+            platform,
+            chip,
+            None::<&kernel::ipc::IPC<0>>,
+            &main_loop_capability,
+        );
+    }
+}
+
+/// Prints a line with the board model, the ARM memory size and the core
+/// and PL011 clocks as the firmware measures them. Anything the mailbox
+/// fails to report is printed as `?`.
+fn print_board_info() {
+    use bcm2837::mailbox;
+    use bcm2837::mailbox::Clock;
+
+    let Some(mut uart) = bcm2837::uart::UART::uart1() else {
+        return;
+    };
+    uart.init();
+    let _ = uart.reclock();
+    // Safety: only property queries are made.
+    let mailbox = unsafe { mailbox::Mailbox::new() };
+
+    let _ = write!(&mut uart, "\r\nboard: ");
+    match mailbox.get_board_revision() {
+        Ok(revision) => {
+            let model = mailbox::board_model(revision).unwrap_or("unknown board");
+            let _ = write!(&mut uart, "{} (revision {:#x})", model, revision);
+        }
+        Err(_) => {
+            let _ = write!(&mut uart, "?");
+        }
+    }
+    match mailbox.get_arm_memory() {
+        Ok((_, size)) => {
+            let _ = write!(&mut uart, ", {} MiB RAM", size >> 20);
+        }
+        Err(_) => {
+            let _ = write!(&mut uart, ", ? MiB RAM");
+        }
+    }
+    for (name, clock) in [("core", Clock::Core), ("UART", Clock::Uart)] {
+        match mailbox.get_measured_clock_rate(clock) {
+            Ok(rate) => {
+                let _ = write!(&mut uart, ", {} {} MHz", name, rate / 1_000_000);
+            }
+            Err(_) => {
+                let _ = write!(&mut uart, ", {} ? MHz", name);
+            }
+        }
+    }
+    let _ = write!(&mut uart, "\r\n");
+    uart.flush();
+}
+
+/// Echoes the Mini UART back to itself, for bring-up without the kernel.
+///
+/// Received bytes are collected by the receive interrupt, and the core
+/// sleeps in `wfi` while there is nothing to do.
+#[cfg(all(feature = "echo", not(feature = "poll")))]
+fn echo(watchdog: bcm2837::power::BootWatchdog) -> ! {
+    use bcm2837::ansi;
+    use bcm2837::interrupt::Interrupt;
+    use bcm2837::uart::RxRing;
+    use cortex_a::exceptions;
+
+    static RX: RxRing<64> = RxRing::new();
+
+    let mut uart = greet(watchdog);
+    uart.set_rx_ring(&RX);
+    uart.enable_rx_interrupt();
+    // Safety: the AUX line is only serviced here.
+    let interrupt = unsafe { Interrupt::new() };
+    interrupt.enable(bcm2837::uart::AUX_IRQ);
+    unsafe { exceptions::enable_interrupts() };
+
     loop {
-        match uart.read_byte() {
-            b'\r' => uart.write_byte(b'\n'),
-            0x7F => {
-                let _ = uart.write_str("\x1B[1D");
-                let _ = uart.write_str("\x1B[K");
+        // `handle_irq` masks the line it takes; the UART is serviced and the
+        // line unmasked here.
+        if let Some(irq) = interrupt.next_taken() {
+            uart.handle_interrupt();
+            unsafe {
+                exceptions::disable_interrupts();
+                interrupt.complete(irq);
+                exceptions::enable_interrupts();
             }
-            r => {
-                uart.write_byte(r);
+        }
+        while let Some(byte) = RX.try_read_byte() {
+            match byte {
+                b'\r' => uart.write_bytes(b"\r\n"),
+                0x7F => {
+                    let _ = ansi::cursor_back(&mut uart, 1);
+                    let _ = ansi::clear_line(&mut uart);
+                }
+                byte => uart.write_byte(byte),
+            }
+        }
+
+        // Check for work with IRQs masked, so one arriving in between is not
+        // taken just before sleeping. `wfi` still wakes up for it.
+        unsafe {
+            exceptions::disable_interrupts();
+            if interrupt.next_taken().is_none() {
+                asm!("wfi", options(nomem, nostack));
             }
+            exceptions::enable_interrupts();
         }
     }
 }
 
+/// The polled echo loop, spinning on the receiver.
+#[cfg(feature = "poll")]
+fn echo(watchdog: bcm2837::power::BootWatchdog) -> ! {
+    let mut uart = greet(watchdog);
+    let mut line = [0; 80];
+    loop {
+        uart.read_line(&mut line);
+    }
+}
+
+/// Sets up the Mini UART and says hello, which ends the boot.
+#[cfg(feature = "echo")]
+fn greet(watchdog: bcm2837::power::BootWatchdog) -> bcm2837::uart::UART {
+    let mut uart = bcm2837::uart::UART::uart1().unwrap();
+    // Do not rely on config.txt having set up the Mini UART.
+    uart.init();
+    let _ = uart.reclock();
+    watchdog.boot_complete();
+    let _ = write!(&mut uart, "Hello world\n");
+    #[cfg(feature = "smoke-test")]
+    smoke_test_exit(&mut uart);
+    uart
+}
+
+/// Ends a `smoke-test.sh` run once the greeting is out.
+#[cfg(feature = "smoke-test")]
+fn smoke_test_exit(uart: &mut bcm2837::uart::UART) {
+    uart.flush();
+    cortex_a::semihosting::exit(0)
+}
+
 use core::panic::PanicInfo;
 
+/// The UART panics are printed on. The panicking code may have left it in
+/// any state, so it is set up from scratch.
+unsafe fn panic_console() -> bcm2837::console::ConsoleWriter {
+    use bcm2837::console::Console;
+
+    if cfg!(feature = "pl011") {
+        bcm2837::console::select_console(Console::Pl011)
+    } else {
+        bcm2837::console::select_console(Console::Mini)
+    }
+}
+
 #[panic_handler]
-fn panic(_panic_info: &PanicInfo<'_>) -> ! {
+fn panic(panic_info: &PanicInfo<'_>) -> ! {
+    use bcm2837::ansi;
+
+    // Safety: nothing else runs once we panic.
+    let mut uart = unsafe { panic_console() };
+    let _ = ansi::set_color(&mut uart, ansi::Color::Red, ansi::Color::Default);
+    let _ = write!(&mut uart, "\r\n{}\r\n", panic_info);
+    if !cortex_a::stack::check_stack_canary() {
+        let _ = write!(&mut uart, "kernel stack overflow\r\n");
+    }
+    let _ = ansi::reset(&mut uart);
+    uart.flush();
     loop {
         unsafe {
             asm!("wfi");
@@ -0,0 +1,230 @@
+//! Tock board setup: the Mini UART shared by the console, the process
+//! console and debug output, the System Timer virtualized for the alarm
+//! driver and the scheduler, and a few header pins for the GPIO driver.
+
+use core::ptr::addr_of;
+use core::ptr::addr_of_mut;
+
+use bcm2837::chip::Bcm2837;
+use bcm2837::chip::Bcm2837DefaultPeripherals;
+use bcm2837::gpio::GpioPin;
+use bcm2837::system_timer::Channel;
+use bcm2837::system_timer::SystemTimerAlarm;
+
+use capsules_core::virtualizers::virtual_alarm::VirtualMuxAlarm;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::debug;
+use kernel::platform::scheduler_timer::VirtualSchedulerTimer;
+use kernel::platform::KernelResources;
+use kernel::platform::SyscallDriverLookup;
+use kernel::scheduler::round_robin::RoundRobinSched;
+use kernel::static_init;
+
+/// Number of concurrent processes this platform supports.
+const NUM_PROCS: usize = 4;
+
+static mut PROCESSES: [Option<&'static dyn kernel::process::Process>; NUM_PROCS] =
+    [None; NUM_PROCS];
+
+/// What the kernel does when a process faults.
+static FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::PanicFaultPolicy {};
+
+pub type Chip = Bcm2837<'static, Bcm2837DefaultPeripherals<'static>>;
+type Alarm = VirtualMuxAlarm<'static, SystemTimerAlarm<'static>>;
+
+/// Syscall drivers and kernel resources of the board.
+pub struct RaspberryPi {
+    console: &'static capsules_core::console::Console<'static>,
+    alarm: &'static capsules_core::alarm::AlarmDriver<'static, Alarm>,
+    gpio: &'static capsules_core::gpio::GPIO<'static, GpioPin<'static>>,
+    scheduler: &'static RoundRobinSched<'static>,
+    scheduler_timer: &'static VirtualSchedulerTimer<Alarm>,
+}
+
+impl SyscallDriverLookup for RaspberryPi {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::syscall::SyscallDriver>) -> R,
+    {
+        match driver_num {
+            capsules_core::console::DRIVER_NUM => f(Some(self.console)),
+            capsules_core::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules_core::gpio::DRIVER_NUM => f(Some(self.gpio)),
+            _ => f(None),
+        }
+    }
+}
+
+impl KernelResources<Chip> for RaspberryPi {
+    type SyscallDriverLookup = Self;
+    type SyscallFilter = ();
+    type ProcessFault = ();
+    type CredentialsCheckingPolicy = ();
+    type Scheduler = RoundRobinSched<'static>;
+    type SchedulerTimer = VirtualSchedulerTimer<Alarm>;
+    type WatchDog = ();
+    type ContextSwitchCallback = ();
+
+    fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
+        self
+    }
+    fn syscall_filter(&self) -> &Self::SyscallFilter {
+        &()
+    }
+    fn process_fault(&self) -> &Self::ProcessFault {
+        &()
+    }
+    fn credentials_checking_policy(&self) -> &'static Self::CredentialsCheckingPolicy {
+        &()
+    }
+    fn scheduler(&self) -> &Self::Scheduler {
+        self.scheduler
+    }
+    fn scheduler_timer(&self) -> &Self::SchedulerTimer {
+        self.scheduler_timer
+    }
+    fn watchdog(&self) -> &Self::WatchDog {
+        &()
+    }
+    fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
+        &()
+    }
+}
+
+/// Resets the board, for the process console's `reset` command.
+fn reset() -> ! {
+    // Safety: the SoC goes away, so nothing can observe a second instance.
+    unsafe { bcm2837::power::PowerManager::new().reset() }
+}
+
+/// Sets up the peripherals, capsules and processes.
+///
+/// # Safety
+///
+/// Must be called once, with the MMU enabled.
+pub unsafe fn setup() -> (&'static kernel::Kernel, &'static RaspberryPi, &'static Chip) {
+    let peripherals: &'static Bcm2837DefaultPeripherals = static_init!(
+        Bcm2837DefaultPeripherals<'static>,
+        Bcm2837DefaultPeripherals::new()
+    );
+    peripherals.init();
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&*addr_of!(PROCESSES)));
+    let chip = static_init!(Chip, Bcm2837::new(peripherals));
+
+    let process_management_capability =
+        create_capability!(capabilities::ProcessManagementCapability);
+
+    // Compare channel 1 backs every alarm; channel 3 is left free.
+    let system_timer_alarm = static_init!(
+        SystemTimerAlarm<'static>,
+        SystemTimerAlarm::new(&peripherals.system_timer, Channel::C1)
+    );
+    peripherals.system_timer.set_client(system_timer_alarm);
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(system_timer_alarm)
+        .finalize(components::alarm_mux_component_static!(SystemTimerAlarm));
+    let alarm = components::alarm::AlarmDriverComponent::new(
+        board_kernel,
+        capsules_core::alarm::DRIVER_NUM,
+        mux_alarm,
+    )
+    .finalize(components::alarm_component_static!(SystemTimerAlarm));
+
+    let scheduler_timer_alarm = static_init!(Alarm, VirtualMuxAlarm::new(mux_alarm));
+    scheduler_timer_alarm.setup();
+    let scheduler_timer = static_init!(
+        VirtualSchedulerTimer<Alarm>,
+        VirtualSchedulerTimer::new(scheduler_timer_alarm)
+    );
+
+    let uart_mux = components::console::UartMuxComponent::new(
+        &peripherals.mini_uart,
+        bcm2837::uart::DEFAULT_BAUD_RATE,
+    )
+    .finalize(components::uart_mux_component_static!());
+    let console = components::console::ConsoleComponent::new(
+        board_kernel,
+        capsules_core::console::DRIVER_NUM,
+        uart_mux,
+    )
+    .finalize(components::console_component_static!());
+    components::debug_writer::DebugWriterComponent::new(uart_mux)
+        .finalize(components::debug_writer_component_static!());
+
+    // Header pins 29, 31, 33 and 37, which no other peripheral here uses.
+    let gpio = components::gpio::GpioComponent::new(
+        board_kernel,
+        capsules_core::gpio::DRIVER_NUM,
+        components::gpio_component_helper!(
+            GpioPin,
+            0 => &peripherals.gpio[5],
+            1 => &peripherals.gpio[6],
+            2 => &peripherals.gpio[13],
+            3 => &peripherals.gpio[26]
+        ),
+    )
+    .finalize(components::gpio_component_static!(GpioPin));
+
+    let process_printer = components::process_printer::ProcessPrinterTextComponent::new()
+        .finalize(components::process_printer_text_component_static!());
+    let process_console = components::process_console::ProcessConsoleComponent::new(
+        board_kernel,
+        uart_mux,
+        mux_alarm,
+        process_printer,
+        Some(reset),
+    )
+    .finalize(components::process_console_component_static!(
+        SystemTimerAlarm
+    ));
+    let _ = process_console.start();
+
+    let scheduler = components::sched::round_robin::RoundRobinComponent::new(&*addr_of!(PROCESSES))
+        .finalize(components::round_robin_component_static!(NUM_PROCS));
+
+    let platform = static_init!(
+        RaspberryPi,
+        RaspberryPi {
+            console,
+            alarm,
+            gpio,
+            scheduler,
+            scheduler_timer,
+        }
+    );
+
+    extern "C" {
+        /// Beginning of the ROM region containing app images.
+        static _sapps: u8;
+        /// End of the ROM region containing app images.
+        static _eapps: u8;
+        /// Beginning of the RAM region for app memory.
+        static mut _sappmem: u8;
+        /// End of the RAM region for app memory.
+        static _eappmem: u8;
+    }
+
+    kernel::process::load_processes(
+        board_kernel,
+        chip,
+        core::slice::from_raw_parts(
+            addr_of!(_sapps),
+            addr_of!(_eapps) as usize - addr_of!(_sapps) as usize,
+        ),
+        core::slice::from_raw_parts_mut(
+            addr_of_mut!(_sappmem),
+            addr_of!(_eappmem) as usize - addr_of!(_sappmem) as usize,
+        ),
+        &mut *addr_of_mut!(PROCESSES),
+        &FAULT_RESPONSE,
+        &process_management_capability,
+    )
+    .unwrap_or_else(|err| {
+        debug!("Error loading processes!");
+        debug!("{:?}", err);
+    });
+
+    (board_kernel, platform, chip)
+}